@@ -57,6 +57,12 @@ if errorlevel 1 (
   popd
   exit /b 1
 )
+copy /y "can_networks.toml" "%BUNDLE_DIR%\can_networks.toml" >nul
+if errorlevel 1 (
+  echo ERROR: Failed to copy can_networks.toml into can-test bundle.
+  popd
+  exit /b 1
+)
 
 if not exist "%BUNDLE_DIR%\cross_domain_logger_windows.exe" (
   echo ERROR: Can-test bundle exe is missing after copy.
@@ -116,21 +122,11 @@ if errorlevel 1 (
   echo   User display channels: 1 to 11
   echo   Internal app channels: 0 to 10
   echo.
-  echo Channel mapping ^(user channel to app channel to VN to Network^):
-  echo   1  to 0  to vn 1670 1 to FD_CANW
-  echo   2  to 1  to vn 1670 1 to FD_CAN5
-  echo   3  to 2  to vn 1670 2 to FD_CAN9
-  echo   4  to 3  to vn 1670 2 to FD_CAN13
-  echo   5  to 4  to vn 1670 2 to FD_CAN14
-  echo   6  to 5  to vn 1670 1 to FD_CAN15
-  echo   7  to 6  to vn 1670 1 to FD_CAN17
-  echo   8  to 7  to vn 1670 1 to FD_CAN18
-  echo   9  to 8  to vn 1670 1 to FD_CAN20
-  echo   10 to 9  to vn 1670 1 to FD_CAN21
-  echo   11 to 10 to vn 1670 1 to HS_CAN1
+  echo Channel mapping ^(can_networks.toml shipped in the can-test bundle^):
+  type "can_networks.toml"
   echo.
   echo Output naming:
-  echo   ASC files are written using network names ^(for example FD_CANW.asc^).
+  echo   ASC files are written using the network names from can_networks.toml ^(for example FD_CANW.asc^).
 ) > "%SUMMARY_TXT%"
 
 if not exist "%SUMMARY_TXT%" (
@@ -40,26 +40,15 @@ echo Starting parallel CAN capture for channels: %CHANNELS%
 echo Duration: %CAPTURE_MS% ms
 echo App: %APP_NAME%, Interface Version: %IFACE_VER%
 echo Output folder: %OUT_DIR%
+echo File names come from can_networks.toml next to the exe.
 echo.
 
 if not exist "%OUT_DIR%" mkdir "%OUT_DIR%"
 
 for %%C in (%CHANNELS%) do (
   set /a APP_CH=%%C-1
-  set "NET_NAME=channel%%C"
-  if %%C==1 set "NET_NAME=FD_CANW"
-  if %%C==2 set "NET_NAME=FD_CAN5"
-  if %%C==3 set "NET_NAME=FD_CAN9"
-  if %%C==4 set "NET_NAME=FD_CAN13"
-  if %%C==5 set "NET_NAME=FD_CAN14"
-  if %%C==6 set "NET_NAME=FD_CAN15"
-  if %%C==7 set "NET_NAME=FD_CAN17"
-  if %%C==8 set "NET_NAME=FD_CAN18"
-  if %%C==9 set "NET_NAME=FD_CAN20"
-  if %%C==10 set "NET_NAME=FD_CAN21"
-  if %%C==11 set "NET_NAME=HS_CAN1"
   echo [START] Channel %%C (appCh !APP_CH!)
-  start "CAN_%%C" /min cmd /c ""%EXE%" --test-can --can-listen --can-channel !APP_CH! --can-app %APP_NAME% --can-iface-version %IFACE_VER% --can-duration-ms %CAPTURE_MS% --can-log-format asc --can-log-file "%OUT_DIR%\!NET_NAME!.asc" 1> "%OUT_DIR%\!NET_NAME!_console.log" 2>&1"
+  start "CAN_%%C" /min cmd /c ""%EXE%" --test-can --can-listen --can-channel !APP_CH! --can-app %APP_NAME% --can-iface-version %IFACE_VER% --can-duration-ms %CAPTURE_MS% --can-log-format asc --can-output-dir "%OUT_DIR%" 1> "%OUT_DIR%\channel%%C_console.log" 2>&1"
 )
 
 set /a CAPTURE_SEC=(%CAPTURE_MS% + 999) / 1000 + 5
@@ -0,0 +1,232 @@
+// Vector ASC (ASCII logging) writer.
+// Classic frames use the `<ch> <id> <dir> d <dlc> <data>` record, FD frames the `CANFD` record
+// with BRS/ESI, DLC, data length and the EDL/BRS/ESI flag word, so CANoe/CANalyzer can replay them.
+// Extended identifiers carry the `x` suffix; channels are 1-based.
+
+use crate::can_backend::CanFrame;
+use chrono::{DateTime, Local, SecondsFormat};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const ASC_FD_EDL: u32 = 0x1000;
+const ASC_FD_BRS: u32 = 0x2000;
+const ASC_FD_ESI: u32 = 0x4000;
+
+pub struct AscWriter {
+    out: BufWriter<File>,
+}
+
+impl AscWriter {
+    pub fn create(file: File, start: DateTime<Local>) -> Result<Self, String> {
+        let mut out = BufWriter::new(file);
+        out.write_all(format_header(start).as_bytes())
+            .map_err(|e| format!("Failed to write ASC header: {}", e))?;
+        Ok(Self { out })
+    }
+
+    /// `timestamp_ns` is relative to the start time given to `create`.
+    pub fn write_frame(&mut self, channel: u16, timestamp_ns: u64, frame: &CanFrame) -> Result<(), String> {
+        writeln!(self.out, "{}", format_frame(channel, timestamp_ns, frame))
+            .map_err(|e| format!("Failed to write ASC record: {}", e))
+    }
+
+    pub fn write_error_frame(&mut self, channel: u16, timestamp_ns: u64) -> Result<(), String> {
+        writeln!(self.out, "{}", format_error_frame(channel, timestamp_ns))
+            .map_err(|e| format!("Failed to write ASC record: {}", e))
+    }
+
+    pub fn write_comment(&mut self, timestamp_ns: u64, text: &str) -> Result<(), String> {
+        writeln!(self.out, "{}", format_comment(timestamp_ns, text)).map_err(|e| format!("Failed to write ASC comment: {}", e))
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.out
+            .write_all(b"End TriggerBlock\n")
+            .and_then(|_| self.out.flush())
+            .map_err(|e| format!("Failed to finalize ASC file: {}", e))
+    }
+}
+
+pub fn format_header(start: DateTime<Local>) -> String {
+    let date = start.format("%a %b %d %I:%M:%S%.3f %P %Y");
+    format!(
+        "date {date}\nbase hex  timestamps absolute\ninternal events logged\n// version 13.0.0\n// capture start (host clock) {}\nBegin Triggerblock {date}\n{:>11.6} Start of measurement\n",
+        start.to_rfc3339_opts(SecondsFormat::Nanos, false),
+        0.0
+    )
+}
+
+fn format_timestamp(timestamp_ns: u64) -> String {
+    format!("{:>11.6}", timestamp_ns as f64 / 1e9)
+}
+
+fn format_id(frame: &CanFrame) -> String {
+    if frame.extended {
+        format!("{:X}x", frame.id)
+    } else {
+        format!("{:X}", frame.id)
+    }
+}
+
+fn format_data(data: &[u8]) -> String {
+    data.iter().map(|b| format!(" {:02X}", b)).collect()
+}
+
+pub fn format_frame(channel: u16, timestamp_ns: u64, frame: &CanFrame) -> String {
+    let ts = format_timestamp(timestamp_ns);
+    let dir = if frame.tx { "Tx" } else { "Rx" };
+
+    if frame.fd {
+        let mut flags = ASC_FD_EDL;
+        if frame.brs {
+            flags |= ASC_FD_BRS;
+        }
+        if frame.esi {
+            flags |= ASC_FD_ESI;
+        }
+        // MessageDuration, MessageLength, CRC and the bit timing words are not known here and are written as 0.
+        format!(
+            "{} CANFD {:>3} {:<4} {:>8} {} {} {:X} {:>2}{} {:>8} {:>4} {:>8X} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            ts,
+            channel,
+            dir,
+            format_id(frame),
+            frame.brs as u8,
+            frame.esi as u8,
+            frame.dlc,
+            frame.data.len(),
+            format_data(&frame.data),
+            0,
+            0,
+            flags,
+            0,
+            0,
+            0,
+            0,
+            0
+        )
+    } else if frame.remote {
+        format!("{} {:<2} {:<15} {:<4} r {:X}", ts, channel, format_id(frame), dir, frame.dlc)
+    } else {
+        format!(
+            "{} {:<2} {:<15} {:<4} d {:X}{}",
+            ts,
+            channel,
+            format_id(frame),
+            dir,
+            frame.dlc,
+            format_data(&frame.data)
+        )
+    }
+}
+
+pub fn format_error_frame(channel: u16, timestamp_ns: u64) -> String {
+    format!("{} {:<2} ErrorFrame", format_timestamp(timestamp_ns), channel)
+}
+
+/// `//` comment line with the time it refers to; readers skip it like the header comments.
+pub fn format_comment(timestamp_ns: u64, text: &str) -> String {
+    format!("// {} {}", format_timestamp(timestamp_ns).trim_start(), text.replace(['\r', '\n'], " "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn frame(id: u32, dlc: u8, data: &[u8]) -> CanFrame {
+        CanFrame {
+            channel_index: 0,
+            timestamp_ns: 0,
+            id,
+            extended: false,
+            tx: false,
+            fd: false,
+            brs: false,
+            esi: false,
+            remote: false,
+            dlc,
+            data: data.to_vec(),
+        }
+    }
+
+    fn golden(text: &str) -> &str {
+        text.trim_end_matches(['\r', '\n'])
+    }
+
+    #[test]
+    fn classic_standard_rx() {
+        let f = frame(0x123, 8, &[0x01, 0x02, 0x03, 0x04, 0xA5, 0x5A, 0xFF, 0x00]);
+        assert_eq!(format_frame(1, 1_234_567_000, &f), golden(include_str!("../testdata/asc/classic_standard_rx.asc")));
+    }
+
+    #[test]
+    fn classic_extended_tx() {
+        let mut f = frame(0x18DA_F110, 3, &[0x02, 0x10, 0x03]);
+        f.extended = true;
+        f.tx = true;
+        assert_eq!(format_frame(2, 20_000_000, &f), golden(include_str!("../testdata/asc/classic_extended_tx.asc")));
+    }
+
+    #[test]
+    fn classic_remote() {
+        let mut f = frame(0x7DF, 8, &[]);
+        f.remote = true;
+        assert_eq!(format_frame(1, 500_000, &f), golden(include_str!("../testdata/asc/classic_remote.asc")));
+    }
+
+    #[test]
+    fn classic_empty() {
+        let f = frame(0x0, 0, &[]);
+        assert_eq!(format_frame(3, 0, &f), golden(include_str!("../testdata/asc/classic_empty.asc")));
+    }
+
+    #[test]
+    fn fd_brs_extended_tx() {
+        let mut f = frame(0x1ABC_DEF0, 13, &(0u8..32).collect::<Vec<u8>>());
+        f.fd = true;
+        f.brs = true;
+        f.extended = true;
+        f.tx = true;
+        assert_eq!(format_frame(5, 12_000_001_000, &f), golden(include_str!("../testdata/asc/fd_brs_extended_tx.asc")));
+    }
+
+    #[test]
+    fn fd_without_brs_with_esi() {
+        let mut f = frame(0x321, 9, &[0x11; 12]);
+        f.fd = true;
+        f.esi = true;
+        assert_eq!(format_frame(1, 3_000_000, &f), golden(include_str!("../testdata/asc/fd_esi_rx.asc")));
+    }
+
+    #[test]
+    fn fd_short_payload() {
+        let mut f = frame(0x100, 2, &[0xDE, 0xAD]);
+        f.fd = true;
+        f.brs = true;
+        assert_eq!(format_frame(1, 42_000, &f), golden(include_str!("../testdata/asc/fd_short_rx.asc")));
+    }
+
+    #[test]
+    fn error_frame() {
+        assert_eq!(format_error_frame(4, 7_500_000), golden(include_str!("../testdata/asc/error_frame.asc")));
+    }
+
+    #[test]
+    fn comment() {
+        assert_eq!(
+            format_comment(12_500_000_000, "CDL_MARKER #3 brake\nnoise"),
+            golden(include_str!("../testdata/asc/comment.asc"))
+        );
+    }
+
+    #[test]
+    fn header() {
+        let start = Local.with_ymd_and_hms(2024, 3, 5, 14, 7, 9).unwrap();
+        // The anchor line carries the local UTC offset, so the golden file has a placeholder for it.
+        let expected = include_str!("../testdata/asc/header.asc")
+            .replace("\r\n", "\n")
+            .replace("{anchor}", &start.to_rfc3339_opts(SecondsFormat::Nanos, false));
+        assert_eq!(format_header(start), expected);
+    }
+}
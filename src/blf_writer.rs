@@ -0,0 +1,482 @@
+// Vector BLF (binary logging format) writer.
+// Objects are collected into zlib-compressed LOG_CONTAINER objects of up to 128 KiB; the file
+// header (object count, sizes, start/stop time) is rewritten when the writer is finished.
+// Layout follows Vector's binlog object definitions: CAN_MESSAGE2 for classic frames,
+// CAN_FD_MESSAGE_64 for FD frames and CAN_ERROR_EXT for error frames.
+
+use crate::can_backend::CanFrame;
+use chrono::{DateTime, Datelike, Local, Timelike};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+
+const FILE_HEADER_SIZE: u32 = 144;
+const OBJ_HEADER_BASE_SIZE: u16 = 16;
+const OBJ_HEADER_V1_SIZE: u16 = 16;
+const MAX_CONTAINER_SIZE: usize = 128 * 1024;
+
+const LOG_CONTAINER: u32 = 10;
+const CAN_ERROR_EXT: u32 = 73;
+const CAN_MESSAGE2: u32 = 86;
+const CAN_FD_MESSAGE_64: u32 = 101;
+
+const NO_COMPRESSION: u16 = 0;
+const ZLIB_DEFLATE: u16 = 2;
+const TIME_ONE_NANS: u32 = 0x0000_0002;
+
+const CAN_MSG_EXT: u32 = 0x8000_0000;
+const CAN_MSG_DIR_TX: u8 = 0x01;
+const CAN_MSG_REMOTE: u8 = 0x80;
+const CAN_FD64_REMOTE: u32 = 0x0010;
+const CAN_FD64_EDL: u32 = 0x1000;
+const CAN_FD64_BRS: u32 = 0x2000;
+const CAN_FD64_ESI: u32 = 0x4000;
+
+pub struct BlfWriter {
+    file: File,
+    buffer: Vec<u8>,
+    start: DateTime<Local>,
+    object_count: u32,
+    uncompressed_size: u64,
+}
+
+impl BlfWriter {
+    pub fn create(mut file: File, start: DateTime<Local>) -> Result<Self, String> {
+        let header = file_header(start, start, 0, 0, 0);
+        file.write_all(&header)
+            .map_err(|e| format!("Failed to write BLF header: {}", e))?;
+
+        Ok(Self {
+            file,
+            buffer: Vec::new(),
+            start,
+            object_count: 0,
+            uncompressed_size: FILE_HEADER_SIZE as u64,
+        })
+    }
+
+    /// `timestamp_ns` is relative to the start time given to `create`.
+    pub fn write_frame(&mut self, channel: u16, timestamp_ns: u64, frame: &CanFrame) -> Result<(), String> {
+        let mut data = Vec::with_capacity(112);
+        if frame.fd {
+            let mut flags = CAN_FD64_EDL;
+            if frame.brs {
+                flags |= CAN_FD64_BRS;
+            }
+            if frame.esi {
+                flags |= CAN_FD64_ESI;
+            }
+            if frame.remote {
+                flags |= CAN_FD64_REMOTE;
+            }
+            let valid_bytes = frame.data.len().min(64);
+            data.push(channel as u8);
+            data.push(frame.dlc);
+            data.push(valid_bytes as u8);
+            data.push(0); // tx count
+            data.extend_from_slice(&blf_can_id(frame).to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes()); // frame length (ns)
+            data.extend_from_slice(&flags.to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes()); // arbitration bit rate config
+            data.extend_from_slice(&0u32.to_le_bytes()); // data bit rate config
+            data.extend_from_slice(&0u32.to_le_bytes()); // brs time offset
+            data.extend_from_slice(&0u32.to_le_bytes()); // crc delimiter time offset
+            data.extend_from_slice(&0u16.to_le_bytes()); // bit count
+            data.push(if frame.tx { 1 } else { 0 });
+            data.push(0); // no extended frame data
+            data.extend_from_slice(&0u32.to_le_bytes()); // crc
+            data.extend_from_slice(&frame.data[..valid_bytes]);
+            self.add_object(CAN_FD_MESSAGE_64, timestamp_ns, &data)
+        } else {
+            let mut flags = 0u8;
+            if frame.tx {
+                flags |= CAN_MSG_DIR_TX;
+            }
+            if frame.remote {
+                flags |= CAN_MSG_REMOTE;
+            }
+            let mut payload = [0u8; 8];
+            let len = frame.data.len().min(8);
+            payload[..len].copy_from_slice(&frame.data[..len]);
+            data.extend_from_slice(&channel.to_le_bytes());
+            data.push(flags);
+            data.push(frame.dlc);
+            data.extend_from_slice(&blf_can_id(frame).to_le_bytes());
+            data.extend_from_slice(&payload);
+            data.extend_from_slice(&0u32.to_le_bytes()); // frame length (ns)
+            data.push(0); // bit count
+            data.push(0);
+            data.extend_from_slice(&0u16.to_le_bytes());
+            self.add_object(CAN_MESSAGE2, timestamp_ns, &data)
+        }
+    }
+
+    pub fn write_error_frame(&mut self, channel: u16, timestamp_ns: u64) -> Result<(), String> {
+        let mut data = Vec::with_capacity(32);
+        data.extend_from_slice(&channel.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes()); // length
+        data.extend_from_slice(&0u32.to_le_bytes()); // flags
+        data.push(0); // error code capture
+        data.push(0); // position
+        data.push(0); // dlc
+        data.push(0);
+        data.extend_from_slice(&0u32.to_le_bytes()); // frame length (ns)
+        data.extend_from_slice(&0u32.to_le_bytes()); // id
+        data.extend_from_slice(&0u16.to_le_bytes()); // extended flags
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&[0u8; 8]);
+        self.add_object(CAN_ERROR_EXT, timestamp_ns, &data)
+    }
+
+    /// Flushes the last container and rewrites the header with the final counts and stop time.
+    pub fn finish(mut self, stop: DateTime<Local>) -> Result<(), String> {
+        while !self.buffer.is_empty() {
+            self.flush_container()?;
+        }
+        let file_size = self
+            .file
+            .stream_position()
+            .map_err(|e| format!("Failed to finalize BLF file: {}", e))?;
+        let header = file_header(self.start, stop, file_size, self.uncompressed_size, self.object_count);
+        self.file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.file.write_all(&header))
+            .and_then(|_| self.file.flush())
+            .map_err(|e| format!("Failed to finalize BLF file: {}", e))
+    }
+
+    fn add_object(&mut self, object_type: u32, timestamp_ns: u64, data: &[u8]) -> Result<(), String> {
+        let header_size = OBJ_HEADER_BASE_SIZE + OBJ_HEADER_V1_SIZE;
+        let object_size = header_size as u32 + data.len() as u32;
+        self.buffer.extend_from_slice(b"LOBJ");
+        self.buffer.extend_from_slice(&header_size.to_le_bytes());
+        self.buffer.extend_from_slice(&1u16.to_le_bytes());
+        self.buffer.extend_from_slice(&object_size.to_le_bytes());
+        self.buffer.extend_from_slice(&object_type.to_le_bytes());
+        self.buffer.extend_from_slice(&TIME_ONE_NANS.to_le_bytes());
+        self.buffer.extend_from_slice(&0u16.to_le_bytes()); // client index
+        self.buffer.extend_from_slice(&0u16.to_le_bytes()); // object version
+        self.buffer.extend_from_slice(&timestamp_ns.to_le_bytes());
+        self.buffer.extend_from_slice(data);
+        self.buffer.resize(self.buffer.len() + data.len() % 4, 0);
+        self.object_count += 1;
+
+        while self.buffer.len() >= MAX_CONTAINER_SIZE {
+            self.flush_container()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the next (up to 128 KiB) slice of buffered objects as one LOG_CONTAINER.
+    fn flush_container(&mut self) -> Result<(), String> {
+        let chunk_len = self.buffer.len().min(MAX_CONTAINER_SIZE);
+        let uncompressed: Vec<u8> = self.buffer.drain(..chunk_len).collect();
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&uncompressed)
+            .map_err(|e| format!("Failed to compress BLF container: {}", e))?;
+        let compressed = encoder
+            .finish()
+            .map_err(|e| format!("Failed to compress BLF container: {}", e))?;
+        let (method, payload) = if compressed.len() < uncompressed.len() {
+            (ZLIB_DEFLATE, compressed)
+        } else {
+            (NO_COMPRESSION, uncompressed.clone())
+        };
+
+        let object_size = OBJ_HEADER_BASE_SIZE as u32 + 16 + payload.len() as u32;
+        let mut out = Vec::with_capacity(object_size as usize + 4);
+        out.extend_from_slice(b"LOBJ");
+        out.extend_from_slice(&OBJ_HEADER_BASE_SIZE.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&object_size.to_le_bytes());
+        out.extend_from_slice(&LOG_CONTAINER.to_le_bytes());
+        out.extend_from_slice(&method.to_le_bytes());
+        out.extend_from_slice(&[0u8; 6]);
+        out.extend_from_slice(&(uncompressed.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[0u8; 4]);
+        out.extend_from_slice(&payload);
+        out.resize(out.len() + object_size as usize % 4, 0);
+
+        self.uncompressed_size += OBJ_HEADER_BASE_SIZE as u64 + 16 + uncompressed.len() as u64;
+        self.file
+            .write_all(&out)
+            .map_err(|e| format!("Failed to write BLF container: {}", e))?;
+        Ok(())
+    }
+}
+
+fn blf_can_id(frame: &CanFrame) -> u32 {
+    if frame.extended {
+        frame.id | CAN_MSG_EXT
+    } else {
+        frame.id
+    }
+}
+
+fn system_time(time: DateTime<Local>) -> [u16; 8] {
+    [
+        time.year() as u16,
+        time.month() as u16,
+        time.weekday().num_days_from_sunday() as u16,
+        time.day() as u16,
+        time.hour() as u16,
+        time.minute() as u16,
+        time.second() as u16,
+        (time.nanosecond() / 1_000_000).min(999) as u16,
+    ]
+}
+
+fn file_header(
+    start: DateTime<Local>,
+    stop: DateTime<Local>,
+    file_size: u64,
+    uncompressed_size: u64,
+    object_count: u32,
+) -> Vec<u8> {
+    let mut header = Vec::with_capacity(FILE_HEADER_SIZE as usize);
+    header.extend_from_slice(b"LOGG");
+    header.extend_from_slice(&FILE_HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&[0, 0, 0, 0]); // application id, major, minor, build
+    header.extend_from_slice(&[2, 6, 8, 1]); // binlog major, minor, build, patch
+    header.extend_from_slice(&file_size.to_le_bytes());
+    header.extend_from_slice(&uncompressed_size.to_le_bytes());
+    header.extend_from_slice(&object_count.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes()); // objects read
+    for value in system_time(start).iter().chain(system_time(stop).iter()) {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    header.resize(FILE_HEADER_SIZE as usize, 0);
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    #[derive(Debug, PartialEq)]
+    enum BlfRecord {
+        Frame { channel: u16, timestamp_ns: u64, frame: CanFrame },
+        ErrorFrame { channel: u16, timestamp_ns: u64 },
+    }
+
+    struct BlfLog {
+        containers: usize,
+        object_count: u32,
+        file_size: u64,
+        start: [u16; 8],
+        stop: [u16; 8],
+        records: Vec<BlfRecord>,
+    }
+
+    fn u16_at(b: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([b[at], b[at + 1]])
+    }
+
+    fn u32_at(b: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(b: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+    }
+
+    // Minimal BLF reader: walks the containers, inflates them and decodes the objects we write.
+    fn read_blf(bytes: &[u8]) -> BlfLog {
+        assert_eq!(&bytes[0..4], b"LOGG");
+        let header_size = u32_at(bytes, 4) as usize;
+        let mut start = [0u16; 8];
+        let mut stop = [0u16; 8];
+        for i in 0..8 {
+            start[i] = u16_at(bytes, 40 + i * 2);
+            stop[i] = u16_at(bytes, 56 + i * 2);
+        }
+
+        let mut inner = Vec::new();
+        let mut containers = 0;
+        let mut pos = header_size;
+        while pos + 16 <= bytes.len() {
+            assert_eq!(&bytes[pos..pos + 4], b"LOBJ");
+            let object_size = u32_at(bytes, pos + 8) as usize;
+            assert_eq!(u32_at(bytes, pos + 12), LOG_CONTAINER);
+            let method = u16_at(bytes, pos + 16);
+            let uncompressed_len = u32_at(bytes, pos + 24) as usize;
+            let payload = &bytes[pos + 32..pos + object_size];
+            let mut data = Vec::new();
+            if method == ZLIB_DEFLATE {
+                ZlibDecoder::new(payload).read_to_end(&mut data).unwrap();
+            } else {
+                data.extend_from_slice(payload);
+            }
+            assert_eq!(data.len(), uncompressed_len);
+            inner.extend_from_slice(&data);
+            containers += 1;
+            pos += object_size + object_size % 4;
+        }
+
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < inner.len() {
+            assert_eq!(&inner[pos..pos + 4], b"LOBJ");
+            let header_size = u16_at(&inner, pos + 4) as usize;
+            let object_size = u32_at(&inner, pos + 8) as usize;
+            let object_type = u32_at(&inner, pos + 12);
+            assert_eq!(u32_at(&inner, pos + 16), TIME_ONE_NANS);
+            let timestamp_ns = u64_at(&inner, pos + 24);
+            let d = &inner[pos + header_size..pos + object_size];
+            match object_type {
+                CAN_MESSAGE2 => {
+                    let flags = d[2];
+                    let dlc = d[3];
+                    let raw_id = u32_at(d, 4);
+                    let remote = flags & CAN_MSG_REMOTE != 0;
+                    let len = if remote { 0 } else { (dlc as usize).min(8) };
+                    records.push(BlfRecord::Frame {
+                        channel: u16_at(d, 0),
+                        timestamp_ns,
+                        frame: CanFrame {
+                            channel_index: 0,
+                            timestamp_ns: 0,
+                            id: raw_id & 0x1FFF_FFFF,
+                            extended: raw_id & CAN_MSG_EXT != 0,
+                            tx: flags & CAN_MSG_DIR_TX != 0,
+                            fd: false,
+                            brs: false,
+                            esi: false,
+                            remote,
+                            dlc,
+                            data: d[8..8 + len].to_vec(),
+                        },
+                    });
+                }
+                CAN_FD_MESSAGE_64 => {
+                    let raw_id = u32_at(d, 4);
+                    let flags = u32_at(d, 12);
+                    let valid = d[2] as usize;
+                    records.push(BlfRecord::Frame {
+                        channel: d[0] as u16,
+                        timestamp_ns,
+                        frame: CanFrame {
+                            channel_index: 0,
+                            timestamp_ns: 0,
+                            id: raw_id & 0x1FFF_FFFF,
+                            extended: raw_id & CAN_MSG_EXT != 0,
+                            tx: d[34] == 1,
+                            fd: flags & CAN_FD64_EDL != 0,
+                            brs: flags & CAN_FD64_BRS != 0,
+                            esi: flags & CAN_FD64_ESI != 0,
+                            remote: flags & CAN_FD64_REMOTE != 0,
+                            dlc: d[1],
+                            data: d[40..40 + valid].to_vec(),
+                        },
+                    });
+                }
+                CAN_ERROR_EXT => {
+                    records.push(BlfRecord::ErrorFrame {
+                        channel: u16_at(d, 0),
+                        timestamp_ns,
+                    });
+                }
+                other => panic!("unexpected object type {}", other),
+            }
+            pos += object_size + object_size % 4;
+        }
+
+        BlfLog {
+            containers,
+            object_count: u32_at(bytes, 32),
+            file_size: u64_at(bytes, 16),
+            start,
+            stop,
+            records,
+        }
+    }
+
+    fn frame(id: u32, extended: bool, fd: bool, data: &[u8], dlc: u8) -> CanFrame {
+        CanFrame {
+            channel_index: 0,
+            timestamp_ns: 0,
+            id,
+            extended,
+            tx: false,
+            fd,
+            brs: false,
+            esi: false,
+            remote: false,
+            dlc,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn round_trips_classic_fd_and_error_records() {
+        let path = std::env::temp_dir().join(format!("cdl_blf_round_trip_{}.blf", std::process::id()));
+        let start = Local::now();
+        let file = File::create(&path).unwrap();
+        let mut writer = BlfWriter::create(file, start).unwrap();
+
+        let classic = frame(0x123, false, false, &[1, 2, 3], 3);
+        let mut tx_ext = frame(0x18DA_F110, true, false, &[0xAA; 8], 8);
+        tx_ext.tx = true;
+        let mut remote = frame(0x7DF, false, false, &[], 2);
+        remote.remote = true;
+        let mut fd = frame(0x1A0, false, true, &[0x55; 12], 9);
+        fd.brs = true;
+        fd.esi = true;
+        let fd_ext = frame(0x1FFF_FFFF, true, true, &(0..64).collect::<Vec<u8>>(), 15);
+
+        writer.write_frame(1, 1_000, &classic).unwrap();
+        writer.write_frame(2, 2_000, &tx_ext).unwrap();
+        writer.write_frame(1, 2_500, &remote).unwrap();
+        writer.write_error_frame(3, 3_000).unwrap();
+        writer.write_frame(1, 4_000, &fd).unwrap();
+        writer.write_frame(4, 5_000_000_000, &fd_ext).unwrap();
+        writer.finish(start + chrono::Duration::seconds(5)).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let log = read_blf(&bytes);
+
+        assert_eq!(log.object_count, 6);
+        assert_eq!(log.file_size, bytes.len() as u64);
+        assert_eq!(log.start, system_time(start));
+        assert_eq!(log.stop, system_time(start + chrono::Duration::seconds(5)));
+        assert_eq!(
+            log.records,
+            vec![
+                BlfRecord::Frame { channel: 1, timestamp_ns: 1_000, frame: classic },
+                BlfRecord::Frame { channel: 2, timestamp_ns: 2_000, frame: tx_ext },
+                BlfRecord::Frame { channel: 1, timestamp_ns: 2_500, frame: remote },
+                BlfRecord::ErrorFrame { channel: 3, timestamp_ns: 3_000 },
+                BlfRecord::Frame { channel: 1, timestamp_ns: 4_000, frame: fd },
+                BlfRecord::Frame { channel: 4, timestamp_ns: 5_000_000_000, frame: fd_ext },
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_large_captures_into_multiple_containers() {
+        let path = std::env::temp_dir().join(format!("cdl_blf_containers_{}.blf", std::process::id()));
+        let start = Local::now();
+        let mut writer = BlfWriter::create(File::create(&path).unwrap(), start).unwrap();
+        let payload: Vec<u8> = (0..64).collect();
+        for i in 0..5_000u64 {
+            let fd = frame((i % 0x7FF) as u32, false, true, &payload, 15);
+            writer.write_frame(1, i * 100_000, &fd).unwrap();
+        }
+        writer.finish(Local::now()).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let log = read_blf(&bytes);
+
+        assert!(log.containers > 1);
+        assert_eq!(log.object_count, 5_000);
+        assert_eq!(log.records.len(), 5_000);
+        assert!(bytes.len() < 5_000 * 112);
+    }
+}
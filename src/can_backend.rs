@@ -0,0 +1,176 @@
+// CAN backend abstraction.
+// The capture logic in can_capture only talks to hardware through the CanBackend trait,
+// so it runs the same way against the Vector XL driver or the scripted simulator.
+
+use crate::can_config::CanBusParams;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+pub type CanAccess = u64;
+
+/// XL driver status: the app channel is not assigned, or another application holds it.
+pub const XL_ERR_INVALID_CHAN_INDEX: i32 = 111;
+pub const XL_ERR_INVALID_ACCESS: i32 = 112;
+
+/// A failed driver call, formatted the same way the XL API errors were always reported
+/// (`"<call> failed: status <n> (<text>)"`).
+#[derive(Clone, Debug)]
+pub struct CanStatusError {
+    pub call: &'static str,
+    pub status: i32,
+    pub description: String,
+}
+
+impl fmt::Display for CanStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: status {} ({})", self.call, self.status, self.description)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanHwChannel {
+    pub hw_type: u32,
+    pub hw_index: u32,
+    pub hw_channel: u32,
+}
+
+/// One CAN channel the driver knows about, whether or not an application has it assigned.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanChannelInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub hw: CanHwChannel,
+    /// Serial number of the interface the channel belongs to, 0 if the driver reports none.
+    pub serial_number: u32,
+    /// Vector article number of the interface, 0 if unknown.
+    pub article_number: u32,
+    /// Transceiver (piggyback/cab) name, empty if unknown.
+    pub transceiver: String,
+    pub fd_capable: bool,
+    /// Highest bitrate the channel supports in bit/s, 0 if unknown.
+    pub max_bitrate: u32,
+    /// Driver version reported for the channel, empty if unknown.
+    pub driver_version: String,
+    /// The channel is on bus right now (activated by some application, or the interface is up).
+    pub on_bus: bool,
+}
+
+/// An open port on one or more channels (selected by `access_mask`).
+#[derive(Clone, Debug)]
+pub struct CanPort {
+    pub handle: u64,
+    pub access_mask: CanAccess,
+    /// Channels of `access_mask` this port has init access on (may set output mode and bit timing).
+    pub permission_mask: CanAccess,
+}
+
+/// How the logger takes part on the bus when it has init access.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CanBusMode {
+    /// Normal output mode: the controller ACKs frames and signals errors.
+    Active,
+    /// Silent output mode: listen only, the controller never drives the bus.
+    Passive,
+}
+
+impl CanBusMode {
+    pub const ALL: [CanBusMode; 2] = [CanBusMode::Active, CanBusMode::Passive];
+
+    /// Parses the `--can-mode` value.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "active" | "normal" => Some(CanBusMode::Active),
+            "passive" | "silent" | "listen-only" => Some(CanBusMode::Passive),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CanBusMode::Active => "active",
+            CanBusMode::Passive => "passive",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CanFrame {
+    pub channel_index: u16,
+    pub timestamp_ns: u64,
+    pub id: u32,
+    pub extended: bool,
+    pub tx: bool,
+    pub fd: bool,
+    pub brs: bool,
+    pub esi: bool,
+    pub remote: bool,
+    pub dlc: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CanEvent {
+    Frame(CanFrame),
+    ErrorFrame {
+        channel_index: u16,
+        timestamp_ns: u64,
+        description: String,
+    },
+    Other {
+        channel_index: u16,
+        tag: u16,
+        description: String,
+    },
+}
+
+pub trait CanBackend: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Looks up the hardware channel assigned to `app_channel` for `app_name`.
+    fn app_channel_config(&self, app_name: &str, app_channel: u32) -> Result<CanHwChannel, CanStatusError>;
+
+    /// Access mask of a single hardware channel, 0 if it does not exist.
+    fn channel_mask(&self, hw: &CanHwChannel) -> CanAccess;
+
+    /// Access mask of every channel the backend can see, 0 if none.
+    fn all_channels_mask(&self) -> CanAccess;
+
+    /// Every CAN channel present on the driver, in driver order.
+    fn channel_inventory(&self) -> Result<Vec<CanChannelInfo>, CanStatusError>;
+
+    fn open_port(
+        &self,
+        app_name: &str,
+        access_mask: CanAccess,
+        rx_queue_size: u32,
+        interface_version: u32,
+    ) -> Result<CanPort, CanStatusError>;
+
+    /// Applies output mode and bus parameters to the channels in `channel_mask` before they go on bus.
+    /// Only called for channels in `port.permission_mask`; without init access the bus owner's
+    /// settings stay in place.
+    fn configure_port(
+        &self,
+        port: &CanPort,
+        channel_mask: CanAccess,
+        mode: CanBusMode,
+        params: &CanBusParams,
+    ) -> Result<(), CanStatusError>;
+
+    fn activate_port(&self, port: &CanPort) -> Result<(), CanStatusError>;
+
+    /// Current time of the clock that stamps received events (same base as `CanFrame::timestamp_ns`).
+    /// Read right after activation to anchor a capture.
+    fn clock_ns(&self, port: &CanPort) -> Result<u64, CanStatusError>;
+
+    /// Returns `Ok(None)` when the receive queue is empty.
+    fn receive(&self, port: &CanPort) -> Result<Option<CanEvent>, CanStatusError>;
+
+    /// Blocks until the receive queue of `port` has data or `timeout` elapses.
+    /// Returns `Ok(false)` on timeout.
+    fn wait_for_event(&self, port: &CanPort, timeout: Duration) -> Result<bool, CanStatusError>;
+
+    /// Deactivates the channels of `port` and releases it.
+    fn close_port(&self, port: &CanPort);
+}
@@ -0,0 +1,1468 @@
+// CAN capture, channel probing and diagnostics.
+// Everything here goes through a CanBackend, so it works the same on Vector hardware
+// and against the simulated driver (see can_sim).
+
+use crate::asc_writer::AscWriter;
+use crate::blf_writer::BlfWriter;
+use crate::can_backend::{
+    CanAccess, CanBackend, CanBusMode, CanEvent, CanFrame, CanHwChannel, CanPort, CanStatusError, XL_ERR_INVALID_CHAN_INDEX,
+};
+use crate::can_config::{CanNetworkConfig, DEFAULT_CONFIG_FILE};
+use crate::can_inventory::{collect_inventory, save_inventory};
+use crate::can_layout::verify_layout;
+use crate::mdf4_writer::Mdf4Writer;
+use crate::process_control;
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Set by `--json`: progress text then goes to stderr, so stdout carries only the JSON result.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json_output(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// `println!` for human-readable progress of the CAN commands (stderr in `--json` mode).
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if $crate::can_capture::json_output() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+pub(crate) use progress;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CanLogFormat {
+    Text,
+    Asc,
+    Blf,
+    Mf4,
+}
+
+impl CanLogFormat {
+    pub const ALL: [CanLogFormat; 4] = [CanLogFormat::Asc, CanLogFormat::Blf, CanLogFormat::Mf4, CanLogFormat::Text];
+
+    /// Parses the `--can-log-format` value.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "text" => Some(CanLogFormat::Text),
+            "asc" => Some(CanLogFormat::Asc),
+            "blf" => Some(CanLogFormat::Blf),
+            "mf4" | "mdf" | "mdf4" => Some(CanLogFormat::Mf4),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CanLogFormat::Text => "text",
+            CanLogFormat::Asc => "asc",
+            CanLogFormat::Blf => "blf",
+            CanLogFormat::Mf4 => "mf4",
+        }
+    }
+
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            CanLogFormat::Text => "log",
+            CanLogFormat::Asc => "asc",
+            CanLogFormat::Blf => "blf",
+            CanLogFormat::Mf4 => "mf4",
+        }
+    }
+}
+
+/// Output file of one capture, in the selected format.
+enum CanLogWriter {
+    Text(File),
+    Asc(AscWriter),
+    Blf(BlfWriter),
+    Mf4(Mdf4Writer),
+}
+
+impl CanLogWriter {
+    fn create(path: &str, format: CanLogFormat, start: DateTime<Local>) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open log file '{}': {}", path, e))?;
+
+        match format {
+            CanLogFormat::Text => {
+                let mut file = file;
+                writeln!(file, "# capture start (host clock) {}", start.to_rfc3339_opts(SecondsFormat::Nanos, false))
+                    .map_err(|e| format!("Failed to write log header: {}", e))?;
+                Ok(CanLogWriter::Text(file))
+            }
+            CanLogFormat::Asc => AscWriter::create(file, start).map(CanLogWriter::Asc),
+            CanLogFormat::Blf => BlfWriter::create(file, start).map(CanLogWriter::Blf),
+            CanLogFormat::Mf4 => Mdf4Writer::create(file, start).map(CanLogWriter::Mf4),
+        }
+    }
+
+    /// `line` is the console representation, written by the text format after a timestamp column.
+    fn write_frame(&mut self, timestamp_ns: u64, frame: &CanFrame, line: &str) -> Result<(), String> {
+        match self {
+            CanLogWriter::Text(file) => writeln!(file, "{:>11.6} {}", timestamp_ns as f64 / 1e9, line)
+                .map_err(|e| format!("Failed to write log line: {}", e)),
+            CanLogWriter::Asc(writer) => writer.write_frame(frame.channel_index + 1, timestamp_ns, frame),
+            CanLogWriter::Blf(writer) => writer.write_frame(frame.channel_index + 1, timestamp_ns, frame),
+            CanLogWriter::Mf4(writer) => writer.write_frame(frame.channel_index + 1, timestamp_ns, frame),
+        }
+    }
+
+    /// Free-text annotation (session markers): a comment line in ASC and text logs. BLF and
+    /// MF4 have no comment record here; markers stay in the session's markers file.
+    fn write_comment(&mut self, timestamp_ns: u64, text: &str) -> Result<(), String> {
+        match self {
+            CanLogWriter::Text(file) => writeln!(file, "# {:>11.6} {}", timestamp_ns as f64 / 1e9, text)
+                .map_err(|e| format!("Failed to write log comment: {}", e)),
+            CanLogWriter::Asc(writer) => writer.write_comment(timestamp_ns, text),
+            CanLogWriter::Blf(_) | CanLogWriter::Mf4(_) => Ok(()),
+        }
+    }
+
+    fn write_error_frame(&mut self, timestamp_ns: u64, channel_index: u16, line: &str) -> Result<(), String> {
+        match self {
+            CanLogWriter::Text(file) => writeln!(file, "{:>11.6} {}", timestamp_ns as f64 / 1e9, line)
+                .map_err(|e| format!("Failed to write log line: {}", e)),
+            CanLogWriter::Asc(writer) => writer.write_error_frame(channel_index + 1, timestamp_ns),
+            CanLogWriter::Blf(writer) => writer.write_error_frame(channel_index + 1, timestamp_ns),
+            // The CAN_DataFrame group only carries data frames; error frames stay in the console output.
+            CanLogWriter::Mf4(_) => Ok(()),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self {
+            CanLogWriter::Text(_) => Ok(()),
+            CanLogWriter::Asc(writer) => writer.finish(),
+            CanLogWriter::Blf(writer) => writer.finish(Local::now()),
+            CanLogWriter::Mf4(writer) => writer.finish(),
+        }
+    }
+}
+
+const RX_QUEUE_SIZE: u32 = 16384;
+
+/// How capture ports are opened: XL application name, interface version, bus mode and the
+/// per-network bus parameters.
+#[derive(Clone, Debug)]
+pub struct CanPortSettings {
+    pub app_name: String,
+    pub interface_version: u32,
+    pub mode: CanBusMode,
+    pub networks: CanNetworkConfig,
+    /// Refuse to capture when the harness layout check finds a MOVED or MISSING network.
+    pub refuse_on_drift: bool,
+}
+
+/// How the logger ended up on one channel of `port`, for the capture summary.
+fn bus_participation(port: &CanPort, channel_mask: CanAccess, mode: CanBusMode) -> &'static str {
+    if port.permission_mask & channel_mask == 0 {
+        return "passive (no init access, bus settings owned by another application)";
+    }
+    match mode {
+        CanBusMode::Active => "active (normal output mode)",
+        CanBusMode::Passive => "passive (silent mode)",
+    }
+}
+
+/// A first event further than this from the host-measured elapsed time means the backend
+/// clock is not the one read at activation (e.g. an unsynchronised NIC clock).
+const MAX_CLOCK_SKEW_NS: u64 = 10_000_000_000;
+
+/// Time base of one capture: the host wall-clock anchor and the backend clock read together
+/// right after activation. Event timestamps become nanoseconds since capture start.
+struct CaptureClock {
+    host_anchor: DateTime<Local>,
+    host_start: Instant,
+    device_anchor_ns: Option<u64>,
+    checked: bool,
+}
+
+impl CaptureClock {
+    fn start(backend: &dyn CanBackend, port: &CanPort) -> Self {
+        let device_anchor_ns = match backend.clock_ns(port) {
+            Ok(now) => Some(now),
+            Err(e) => {
+                progress!("{}; anchoring CAN timestamps on the first event", e);
+                None
+            }
+        };
+        Self {
+            host_anchor: Local::now(),
+            host_start: Instant::now(),
+            device_anchor_ns,
+            checked: false,
+        }
+    }
+
+    fn relative_ns(&mut self, device_ns: u64) -> u64 {
+        if !self.checked {
+            self.checked = true;
+            let host_elapsed = self.host_start.elapsed().as_nanos() as u64;
+            let fits = self
+                .device_anchor_ns
+                .is_some_and(|anchor| device_ns.saturating_sub(anchor).abs_diff(host_elapsed) <= MAX_CLOCK_SKEW_NS);
+            if !fits {
+                if self.device_anchor_ns.is_some() {
+                    progress!("CAN event clock does not match the backend clock; anchoring on the first event");
+                }
+                self.device_anchor_ns = Some(device_ns.saturating_sub(host_elapsed));
+            }
+        }
+        device_ns.saturating_sub(self.device_anchor_ns.unwrap_or(0))
+    }
+}
+
+/// A failed capture step. `call` and `status` are set when a driver call failed, so callers
+/// decide on the status code rather than on the message text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureError {
+    pub message: String,
+    pub call: Option<&'static str>,
+    pub status: Option<i32>,
+}
+
+impl From<String> for CaptureError {
+    fn from(message: String) -> Self {
+        CaptureError { message, call: None, status: None }
+    }
+}
+
+impl From<CanStatusError> for CaptureError {
+    fn from(e: CanStatusError) -> Self {
+        CaptureError { message: e.to_string(), call: Some(e.call), status: Some(e.status) }
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+fn resolve_access_mask(backend: &dyn CanBackend, app_name: &str, app_channel: u32) -> Result<CanAccess, CaptureError> {
+    match backend.app_channel_config(app_name, app_channel) {
+        Ok(hw) => {
+            let mapped_mask = backend.channel_mask(&hw);
+            if mapped_mask == 0 {
+                return Err(format!(
+                    "No channel mask for app '{}' channel {} (hwType={}, hwIndex={}, hwChannel={})",
+                    app_name,
+                    app_channel,
+                    hw.hw_type,
+                    hw.hw_index,
+                    hw.hw_channel
+                )
+                .into());
+            }
+            Ok(mapped_mask)
+        }
+        Err(app_cfg_error) => {
+            let all_mask = backend.all_channels_mask();
+            if all_mask == 0 {
+                return Err(CaptureError {
+                    message: format!(
+                        "xlGetApplConfig failed for app '{}' channel {}: status {} ({}), and no wildcard channel mask available",
+                        app_name,
+                        app_channel,
+                        app_cfg_error.status,
+                        app_cfg_error.description
+                    ),
+                    call: Some(app_cfg_error.call),
+                    status: Some(app_cfg_error.status),
+                });
+            }
+
+            match nth_set_bit_mask(all_mask, app_channel as usize) {
+                Some(mask) => {
+                    progress!(
+                        "Using fallback hardware channel ordinal {} (display ch {}, app mapping missing for '{}')",
+                        app_channel,
+                        app_channel + 1,
+                        app_name
+                    );
+                    Ok(mask)
+                }
+                None => Err(format!(
+                    "App mapping missing for '{}' and fallback ordinal {} exceeds available channels",
+                    app_name,
+                    app_channel
+                )
+                .into()),
+            }
+        }
+    }
+}
+
+/// Resolves the channel, opens a port on it, applies the bus configuration and goes on bus.
+fn open_capture_port(
+    backend: &dyn CanBackend,
+    app_channel: u32,
+    settings: &CanPortSettings,
+) -> Result<CanPort, CaptureError> {
+    if app_channel >= 64 {
+        return Err(format!("Invalid app channel {} (must be < 64)", app_channel).into());
+    }
+    if settings.app_name.contains('\0') {
+        return Err("Invalid app name (contains NUL byte)".to_string().into());
+    }
+
+    let access_mask = resolve_access_mask(backend, &settings.app_name, app_channel)?;
+    open_port_on_channels(backend, &[(app_channel, access_mask)], settings)
+}
+
+/// Opens one port on all `(app channel, access mask)` pairs, applies each network's bus
+/// parameters where the port has init access and goes on bus.
+fn open_port_on_channels(
+    backend: &dyn CanBackend,
+    channels: &[(u32, CanAccess)],
+    settings: &CanPortSettings,
+) -> Result<CanPort, CaptureError> {
+    let access_mask = channels.iter().fold(0, |mask, (_, m)| mask | m);
+    let port = backend.open_port(&settings.app_name, access_mask, RX_QUEUE_SIZE, settings.interface_version)?;
+
+    if let Err(e) = configure_channels(backend, &port, channels, settings) {
+        backend.close_port(&port);
+        return Err(e);
+    }
+    if let Err(e) = backend.activate_port(&port) {
+        backend.close_port(&port);
+        return Err(e.into());
+    }
+
+    Ok(port)
+}
+
+fn configure_channels(
+    backend: &dyn CanBackend,
+    port: &CanPort,
+    channels: &[(u32, CanAccess)],
+    settings: &CanPortSettings,
+) -> Result<(), CaptureError> {
+    for (app_channel, mask) in channels {
+        if port.permission_mask & mask == 0 {
+            continue;
+        }
+        let network = settings.networks.channel_name(*app_channel);
+        let params = settings.networks.bus_params(&network);
+        if let Err(e) = backend.configure_port(port, *mask, settings.mode, &params) {
+            return Err(CaptureError { message: format!("{}: {}", network, e), ..e.into() });
+        }
+        progress!("{}: {}", network, params.describe());
+    }
+    Ok(())
+}
+
+/// Opens one channel on its own and applies its bus parameters without going on bus, to find
+/// out which channel made the driver refuse a combined port.
+fn check_channel(backend: &dyn CanBackend, channel: (u32, CanAccess), settings: &CanPortSettings) -> Result<(), CaptureError> {
+    let port = backend.open_port(&settings.app_name, channel.1, RX_QUEUE_SIZE, settings.interface_version)?;
+    let configured = configure_channels(backend, &port, &[channel], settings);
+    backend.close_port(&port);
+    configured
+}
+
+pub fn try_capture_can_on_channel(
+    backend: &dyn CanBackend,
+    app_channel: u32,
+    timeout_ms: u64,
+    settings: &CanPortSettings,
+) -> Result<bool, CaptureError> {
+    let port = open_capture_port(backend, app_channel, settings)?;
+
+    let timeout = Duration::from_millis(timeout_ms);
+    let start = Instant::now();
+    let mut received = false;
+
+    while start.elapsed() < timeout {
+        match backend.receive(&port) {
+            Ok(Some(CanEvent::Frame(frame))) => {
+                progress!(
+                    "Received CAN channel={} (appCh={}) id=0x{:X} dlc={} data={:02X?}",
+                    frame.channel_index as u32 + 1,
+                    app_channel + 1,
+                    frame.id,
+                    frame.dlc,
+                    frame.data
+                );
+                received = true;
+                break;
+            }
+            Ok(Some(CanEvent::ErrorFrame {
+                channel_index,
+                description,
+                ..
+            })) => {
+                progress!(
+                    "CAN error frame channel={} ({})",
+                    channel_index as u32 + 1,
+                    description
+                );
+            }
+            Ok(Some(CanEvent::Other {
+                channel_index,
+                tag,
+                description,
+            })) => {
+                progress!(
+                    "CAN event tag=0x{:X} channel={} ({})",
+                    tag,
+                    channel_index as u32 + 1,
+                    description
+                );
+            }
+            Ok(None) => {
+                if let Err(e) = backend.wait_for_event(&port, timeout.saturating_sub(start.elapsed())) {
+                    backend.close_port(&port);
+                    return Err(e.into());
+                }
+            }
+            Err(e) => {
+                backend.close_port(&port);
+                return Err(e.into());
+            }
+        }
+    }
+
+    backend.close_port(&port);
+
+    Ok(received)
+}
+
+fn nth_set_bit_mask(mask: CanAccess, ordinal: usize) -> Option<CanAccess> {
+    let mut found = 0usize;
+    for bit in 0..64 {
+        let bit_mask = 1u64 << bit;
+        if (mask & bit_mask) != 0 {
+            if found == ordinal {
+                return Some(bit_mask);
+            }
+            found += 1;
+        }
+    }
+    None
+}
+
+/// Outcome of opening one channel and listening on it briefly.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeResult {
+    Frames,
+    NoFrames,
+    Error,
+}
+
+/// One probed channel as reported by `--can-diagnose` and the auto-scan.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChannelProbe {
+    /// 1-based channel as shown to users.
+    pub channel: u32,
+    pub app_channel: u32,
+    pub network: String,
+    pub result: ProbeResult,
+    pub error: Option<String>,
+    /// Driver call that failed with `status`, e.g. `xlOpenPort`.
+    pub call: Option<String>,
+    /// Driver status code of `error`, if it came from a driver call.
+    pub status: Option<i32>,
+}
+
+fn probe_channel(
+    backend: &dyn CanBackend,
+    app_channel: u32,
+    timeout_ms: u64,
+    settings: &CanPortSettings,
+) -> ChannelProbe {
+    let outcome = try_capture_can_on_channel(backend, app_channel, timeout_ms, settings);
+    let (result, error) = match outcome {
+        Ok(true) => (ProbeResult::Frames, None),
+        Ok(false) => (ProbeResult::NoFrames, None),
+        Err(e) => (ProbeResult::Error, Some(e)),
+    };
+    ChannelProbe {
+        channel: app_channel + 1,
+        app_channel,
+        network: settings.networks.channel_name(app_channel),
+        result,
+        call: error.as_ref().and_then(|e| e.call).map(str::to_string),
+        status: error.as_ref().and_then(|e| e.status),
+        error: error.map(|e| e.message),
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub channels: Vec<ChannelProbe>,
+    /// First app channel with traffic; the scan stops there.
+    pub found_app_channel: Option<u32>,
+    pub error: Option<String>,
+}
+
+/// Probes channels in order until one carries traffic.
+pub fn scan_can_channels(
+    backend: &dyn CanBackend,
+    max_channels: u32,
+    timeout_per_channel_ms: u64,
+    settings: &CanPortSettings,
+) -> ScanReport {
+    let mut channels = Vec::new();
+    let mut denied_111_count = 0u32;
+    let mut open_port_error_count = 0u32;
+
+    for channel in 0..max_channels {
+        progress!("Trying CAN channel {}...", channel + 1);
+        let probe = probe_channel(backend, channel, timeout_per_channel_ms, settings);
+        if let Some(e) = &probe.error {
+            if probe.status == Some(XL_ERR_INVALID_CHAN_INDEX) {
+                denied_111_count += 1;
+            }
+            if probe.call.as_deref() == Some("xlOpenPort") {
+                open_port_error_count += 1;
+            }
+            progress!("Channel {} not usable: {}", channel + 1, e);
+        }
+        let found = probe.result == ProbeResult::Frames;
+        channels.push(probe);
+        if found {
+            return ScanReport {
+                channels,
+                found_app_channel: Some(channel),
+                error: None,
+            };
+        }
+    }
+
+    let error = if denied_111_count == max_channels && max_channels > 0 {
+        format!(
+            "All {} channels denied by XL Driver (xlOpenPort status 111). Configure XL channel access for app '{}' and retry.",
+            max_channels,
+            &settings.app_name
+        )
+    } else if open_port_error_count == max_channels && max_channels > 0 {
+        format!(
+            "Unable to open any channel ({} open-port failures). Verify app name '{}', interface version {}, and channel mapping.",
+            open_port_error_count,
+            &settings.app_name,
+            settings.interface_version
+        )
+    } else {
+        format!("No CAN traffic detected on channels 0..{}", max_channels.saturating_sub(1))
+    };
+    ScanReport {
+        channels,
+        found_app_channel: None,
+        error: Some(error),
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DiagnoseReport {
+    pub channels: Vec<ChannelProbe>,
+    pub frames: u32,
+    pub open_no_frames: u32,
+    pub mapping_errors: u32,
+    pub invalid_channel_errors: u32,
+    pub open_port_errors: u32,
+    pub result: String,
+    pub hints: Vec<String>,
+}
+
+impl DiagnoseReport {
+    /// At least one channel could be opened.
+    pub fn channels_open(&self) -> bool {
+        self.frames + self.open_no_frames > 0
+    }
+}
+
+pub fn diagnose_can_setup(backend: &dyn CanBackend, max_channels: u32, settings: &CanPortSettings) -> DiagnoseReport {
+    progress!(
+        "CAN Diagnose: backend={}, app='{}', iface={}, scan-channels=0..{}",
+        backend.name(),
+        &settings.app_name,
+        settings.interface_version,
+        max_channels.saturating_sub(1)
+    );
+
+    let mut report = DiagnoseReport {
+        channels: Vec::new(),
+        frames: 0,
+        open_no_frames: 0,
+        mapping_errors: 0,
+        invalid_channel_errors: 0,
+        open_port_errors: 0,
+        result: String::new(),
+        hints: Vec::new(),
+    };
+
+    for channel in 0..max_channels {
+        let probe = probe_channel(backend, channel, 250, settings);
+        match (&probe.result, &probe.error) {
+            (ProbeResult::Frames, _) => {
+                report.frames += 1;
+                progress!("DIAG channel {}: OK (frames detected)", channel + 1);
+            }
+            (ProbeResult::NoFrames, _) => {
+                report.open_no_frames += 1;
+                progress!("DIAG channel {}: OK (no frames during 250ms)", channel + 1);
+            }
+            (ProbeResult::Error, error) => {
+                match probe.call.as_deref() {
+                    Some("xlGetApplConfig") => report.mapping_errors += 1,
+                    Some("xlOpenPort") => report.open_port_errors += 1,
+                    _ => {}
+                }
+                if probe.status == Some(XL_ERR_INVALID_CHAN_INDEX) {
+                    report.invalid_channel_errors += 1;
+                }
+                progress!("DIAG channel {}: {}", channel + 1, error.as_deref().unwrap_or_default());
+            }
+        }
+        report.channels.push(probe);
+    }
+
+    progress!(
+        "DIAG summary: frames={}, open_no_frames={}, mapping_errors={}, invalid_channel_errors={}, open_port_errors={}",
+        report.frames,
+        report.open_no_frames,
+        report.mapping_errors,
+        report.invalid_channel_errors,
+        report.open_port_errors
+    );
+
+    if report.frames > 0 {
+        report.result = "CAN capture path is working.".to_string();
+    } else if report.open_no_frames > 0 {
+        report.result = "channels open successfully, but no traffic was observed in test window.".to_string();
+    } else {
+        report.result = "no channel could be opened.".to_string();
+        if report.mapping_errors == max_channels {
+            report.hints.push(format!(
+                "application mapping for '{}' is missing. Configure at least one app channel in Vector XL Driver Configuration.",
+                &settings.app_name
+            ));
+        }
+        if report.invalid_channel_errors == max_channels {
+            report
+                .hints
+                .push("channel ordinals are not valid for current setup. Re-check app-channel mapping indices.".to_string());
+        }
+        if report.open_port_errors == max_channels {
+            report
+                .hints
+                .push("all channel opens failed. Verify XL permissions, app name, and interface version.".to_string());
+        }
+    }
+
+    progress!("DIAG result: {}", report.result);
+    for hint in &report.hints {
+        progress!("DIAG hint: {}", hint);
+    }
+    report
+}
+
+/// One app channel as `--can-map` reports it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChannelMapping {
+    /// 1-based channel as shown to users.
+    pub channel: u32,
+    pub app_channel: u32,
+    #[serde(flatten)]
+    pub hw: CanHwChannel,
+    pub mask: CanAccess,
+    pub device: Option<String>,
+    pub network: Option<String>,
+}
+
+/// Prints and returns the hardware channel behind every app channel of `app_name`.
+pub fn print_can_channel_mapping(
+    backend: &dyn CanBackend,
+    max_channels: u32,
+    app_name: &str,
+    networks: &CanNetworkConfig,
+) -> Result<Vec<ChannelMapping>, String> {
+    if app_name.contains('\0') {
+        return Err(format!("Invalid app name '{}': contains NUL byte", app_name));
+    }
+
+    if networks.networks().is_empty() {
+        progress!("No networks configured ({} not found); all channels are UNMAPPED", DEFAULT_CONFIG_FILE);
+    }
+
+    progress!(
+        "CAN mapping for app '{}': channel(1-based) -> hwType/hwIndex/hwChannel (mask) -> VN -> Network",
+        app_name
+    );
+
+    let mut mapping = Vec::new();
+    for app_channel in 0..max_channels {
+        if let Ok(hw) = backend.app_channel_config(app_name, app_channel) {
+            let network = networks.network_for_channel(app_channel);
+            let entry = ChannelMapping {
+                channel: app_channel + 1,
+                app_channel,
+                hw,
+                mask: backend.channel_mask(&hw),
+                device: network.map(|n| n.hardware_label()),
+                network: network.map(|n| n.name.clone()),
+            };
+            progress!(
+                "ch={} (appCh={}) -> hwType={} hwIndex={} hwChannel={} mask=0x{:X} -> {} -> {}",
+                entry.channel,
+                app_channel,
+                hw.hw_type,
+                hw.hw_index,
+                hw.hw_channel,
+                entry.mask,
+                entry.device.as_deref().unwrap_or("UNMAPPED"),
+                entry.network.as_deref().unwrap_or("UNMAPPED")
+            );
+            mapping.push(entry);
+        }
+    }
+
+    if mapping.is_empty() {
+        progress!("No CAN app-channel mappings found for app '{}'.", app_name);
+    }
+    Ok(mapping)
+}
+
+/// One network of a capture: its output file and the frames routed to it.
+struct CaptureTarget {
+    app_channel: u32,
+    network: String,
+    file: String,
+    writer: Option<CanLogWriter>,
+    /// First write or finish failure; the file is closed then and the network no longer written.
+    write_error: Option<String>,
+    frame_count: u64,
+    error_frame_count: u64,
+    participation: &'static str,
+}
+
+impl CaptureTarget {
+    /// Runs `write` on the open file. On failure the file is finished as far as it goes and
+    /// closed, so one full disk or broken file does not end the capture of the other networks.
+    fn write(&mut self, write: impl FnOnce(&mut CanLogWriter) -> Result<(), String>) {
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+        if let Err(e) = write(writer) {
+            progress!("{}: {}", self.file, e);
+            self.write_error = Some(format!("{}: {}", self.file, e));
+            if let Some(writer) = self.writer.take()
+                && let Err(e) = writer.finish()
+            {
+                progress!("{}: {}", self.file, e);
+            }
+        }
+    }
+
+    fn snapshot(&self) -> CapturedNetwork {
+        CapturedNetwork {
+            channel: self.app_channel + 1,
+            app_channel: self.app_channel,
+            network: self.network.clone(),
+            file: self.file.clone(),
+            frames: self.frame_count,
+            error_frames: self.error_frame_count,
+            participation: self.participation.to_string(),
+        }
+    }
+}
+
+/// One network written by `--can-listen-all`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CapturedNetwork {
+    /// 1-based channel as shown to users.
+    pub channel: u32,
+    pub app_channel: u32,
+    pub network: String,
+    pub file: String,
+    pub frames: u64,
+    pub error_frames: u64,
+    pub participation: String,
+}
+
+/// A channel `--can-listen-all` did not capture, with the reason.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SkippedChannel {
+    pub channel: u32,
+    pub app_channel: u32,
+    pub reason: String,
+    /// Driver status code of `reason`, if it came from a driver call.
+    pub status: Option<i32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CaptureSummary {
+    pub output_dir: String,
+    pub format: String,
+    pub networks: Vec<CapturedNetwork>,
+    pub skipped: Vec<SkippedChannel>,
+    /// Set when receiving stopped early; the files hold everything up to that point.
+    pub error: Option<String>,
+}
+
+pub const NO_USABLE_CHANNELS: &str = "No usable channels detected for capture";
+
+/// Progress of a capture running on another thread (see can_engine).
+#[derive(Clone, Debug)]
+pub enum CaptureEvent {
+    /// Port open and files created; `skipped` lists the channels that are not captured.
+    Started {
+        networks: Vec<CapturedNetwork>,
+        skipped: Vec<SkippedChannel>,
+    },
+    /// Frame counts so far, sent every `STATS_INTERVAL`.
+    FrameStats(Vec<CapturedNetwork>),
+    /// The capture could not start, or receiving failed.
+    Error(String),
+    /// Every file is finished; `None` if the capture never started.
+    Stopped(Option<CaptureSummary>),
+}
+
+const STATS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Stop flag and event sink shared with whoever started the capture. The default control
+/// never stops early and sends nothing, which is what the command line uses.
+#[derive(Clone, Debug, Default)]
+pub struct CaptureControl {
+    stop: Arc<AtomicBool>,
+    events: Option<Sender<CaptureEvent>>,
+    /// Comments waiting to be written into every file at the next receive poll.
+    comments: Arc<Mutex<Vec<String>>>,
+}
+
+impl CaptureControl {
+    pub fn with_events(events: Sender<CaptureEvent>) -> Self {
+        CaptureControl {
+            events: Some(events),
+            ..CaptureControl::default()
+        }
+    }
+
+    /// Writes `text` as a comment into every capture file, timestamped with the host time of
+    /// the next receive poll.
+    pub fn add_comment(&self, text: &str) {
+        if let Ok(mut comments) = self.comments.lock() {
+            comments.push(text.to_string());
+        }
+    }
+
+    fn take_comments(&self) -> Vec<String> {
+        self.comments.lock().map(|mut comments| std::mem::take(&mut *comments)).unwrap_or_default()
+    }
+
+    /// Ends the capture at the next receive poll; files are finished as after a timed capture.
+    pub fn request_stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Also true after Ctrl+C once the command line installed its interrupt handler.
+    pub fn stop_requested(&self) -> bool {
+        self.stop.load(Ordering::Relaxed) || process_control::interrupted()
+    }
+
+    pub fn send(&self, event: CaptureEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    /// Per-frame console lines are only printed when nobody receives the counts as events.
+    fn echo_frames(&self) -> bool {
+        self.events.is_none()
+    }
+}
+
+/// Longest single wait on the receive notification, so the duration limit is checked regularly.
+const MAX_RECEIVE_WAIT: Duration = Duration::from_millis(100);
+
+/// Receives from `port` until `duration_ms` elapses, a stop is requested or receive fails,
+/// routing every event to the target of its channel index. Returns the receive error, if any.
+fn run_capture(
+    backend: &dyn CanBackend,
+    port: &CanPort,
+    clock: &mut CaptureClock,
+    targets: &mut [CaptureTarget],
+    target_by_channel: &HashMap<u16, usize>,
+    duration_ms: Option<u64>,
+    control: &CaptureControl,
+) -> Option<String> {
+    let deadline = duration_ms.map(Duration::from_millis);
+    let mut last_stats = Instant::now();
+
+    loop {
+        let elapsed = clock.host_start.elapsed();
+        // Comments are written before a stop is honoured, so a marker set just before STOP is kept.
+        for text in control.take_comments() {
+            let timestamp_ns = elapsed.as_nanos() as u64;
+            for target in targets.iter_mut() {
+                target.write(|writer| writer.write_comment(timestamp_ns, &text));
+            }
+        }
+        if let Some(limit) = deadline
+            && elapsed >= limit
+        {
+            return None;
+        }
+        if control.stop_requested() {
+            return None;
+        }
+        if last_stats.elapsed() >= STATS_INTERVAL {
+            control.send(CaptureEvent::FrameStats(targets.iter().map(CaptureTarget::snapshot).collect()));
+            last_stats = Instant::now();
+        }
+
+        let frame = match backend.receive(port) {
+            Ok(Some(CanEvent::Frame(frame))) => frame,
+            Ok(Some(CanEvent::ErrorFrame {
+                channel_index,
+                timestamp_ns,
+                description,
+            })) => {
+                let line = format!("error channel={} ({})", channel_index, description);
+                if control.echo_frames() {
+                    progress!("{}", line);
+                }
+                let timestamp_ns = clock.relative_ns(timestamp_ns);
+                if let Some(target) = target_by_channel.get(&channel_index).map(|i| &mut targets[*i]) {
+                    target.error_frame_count += 1;
+                    target.write(|writer| writer.write_error_frame(timestamp_ns, channel_index, &line));
+                }
+                continue;
+            }
+            Ok(Some(CanEvent::Other { .. })) => continue,
+            Ok(None) => {
+                let wait = deadline.map_or(MAX_RECEIVE_WAIT, |limit| limit.saturating_sub(elapsed).min(MAX_RECEIVE_WAIT));
+                if let Err(e) = backend.wait_for_event(port, wait) {
+                    return Some(e.to_string());
+                }
+                continue;
+            }
+            Err(e) => return Some(e.to_string()),
+        };
+
+        let timestamp_ns = clock.relative_ns(frame.timestamp_ns);
+        let Some(target) = target_by_channel.get(&frame.channel_index).map(|i| &mut targets[*i]) else {
+            continue;
+        };
+        target.frame_count += 1;
+        let line = format!(
+            "frame={} channel={} id=0x{:X} dlc={} data={:02X?}",
+            target.frame_count,
+            frame.channel_index,
+            frame.id,
+            frame.dlc,
+            frame.data
+        );
+        if control.echo_frames() {
+            progress!("{}", line);
+        }
+        target.write(|writer| writer.write_frame(timestamp_ns, &frame, &line));
+    }
+}
+
+/// Finishes every target file, even after a receive failure, so they stay readable.
+fn finish_targets(targets: &mut [CaptureTarget]) {
+    for target in targets.iter_mut() {
+        if let Some(writer) = target.writer.take()
+            && let Err(e) = writer.finish()
+        {
+            progress!("{}: {}", target.file, e);
+            target.write_error.get_or_insert(format!("{}: {}", target.file, e));
+        }
+    }
+}
+
+/// Receive failure and file write failures, as one capture error.
+fn capture_error(receive_error: Option<String>, targets: &[CaptureTarget]) -> Option<String> {
+    let receive_error = receive_error.map(|e| format!("CAN capture stopped: {}", e));
+    let write_errors = targets.iter().filter_map(|t| t.write_error.as_ref()).map(|e| format!("CAN log not written completely: {}", e));
+    let errors: Vec<String> = receive_error.into_iter().chain(write_errors).collect();
+    if errors.is_empty() { None } else { Some(errors.join("; ")) }
+}
+
+/// Hardware channel index reported in events for a single-channel access mask.
+fn channel_index_of(mask: CanAccess) -> u16 {
+    mask.trailing_zeros() as u16
+}
+
+pub fn listen_can_on_channel(
+    backend: &dyn CanBackend,
+    app_channel: u32,
+    settings: &CanPortSettings,
+    duration_ms: Option<u64>,
+    log_file_path: Option<&str>,
+    log_format: CanLogFormat,
+) -> Result<u64, String> {
+    verify_layout(
+        backend,
+        &settings.app_name,
+        &settings.networks,
+        Some(app_channel),
+        settings.refuse_on_drift,
+    )?;
+    let port = open_capture_port(backend, app_channel, settings).map_err(|e| e.message)?;
+
+    let mut clock = CaptureClock::start(backend, &port);
+    let writer = match log_file_path.map(|path| CanLogWriter::create(path, log_format, clock.host_anchor)) {
+        Some(Ok(writer)) => Some(writer),
+        Some(Err(e)) => {
+            backend.close_port(&port);
+            return Err(e);
+        }
+        None => None,
+    };
+
+    let participation = bus_participation(&port, port.access_mask, settings.mode);
+    progress!(
+        "Listening continuously on CAN app-channel {} ({}). Press Ctrl+C to stop.",
+        app_channel + 1,
+        participation
+    );
+
+    let mut targets = [CaptureTarget {
+        app_channel,
+        network: settings.networks.channel_name(app_channel),
+        file: log_file_path.unwrap_or_default().to_string(),
+        writer,
+        write_error: None,
+        frame_count: 0,
+        error_frame_count: 0,
+        participation,
+    }];
+    let target_by_channel = HashMap::from([(channel_index_of(port.access_mask), 0usize)]);
+    let receive_error = run_capture(
+        backend,
+        &port,
+        &mut clock,
+        &mut targets,
+        &target_by_channel,
+        duration_ms,
+        &CaptureControl::default(),
+    );
+
+    backend.close_port(&port);
+    finish_targets(&mut targets);
+
+    if let Some(e) = receive_error.or_else(|| targets[0].write_error.clone()) {
+        return Err(e);
+    }
+
+    progress!(
+        "Stopped listening. Total frames captured: {} ({})",
+        targets[0].frame_count, targets[0].participation
+    );
+    Ok(targets[0].frame_count)
+}
+
+/// Captures every usable channel through one port opened with the combined access mask, so all
+/// networks share one receive queue and time base. Events are demultiplexed by channel index
+/// into one file per network. `control` can stop the capture early and receives its progress.
+pub fn listen_can_all_connected(
+    backend: &dyn CanBackend,
+    max_channels: u32,
+    settings: &CanPortSettings,
+    duration_ms: Option<u64>,
+    output_dir: Option<&str>,
+    log_format: CanLogFormat,
+    control: &CaptureControl,
+) -> Result<CaptureSummary, String> {
+    verify_layout(backend, &settings.app_name, &settings.networks, None, settings.refuse_on_drift)?;
+    let mut usable_channels: Vec<(u32, CanAccess)> = Vec::new();
+    let mut skipped = Vec::new();
+    let mut skip = |channel: u32, reason: CaptureError| {
+        progress!("Skipping channel {}: {}", channel + 1, reason);
+        skipped.push(SkippedChannel {
+            channel: channel + 1,
+            app_channel: channel,
+            reason: reason.message,
+            status: reason.status,
+        });
+    };
+
+    // Usable channels come from the application configuration; nothing is opened before the
+    // capture port itself.
+    for channel in 0..max_channels {
+        let mask = match resolve_access_mask(backend, &settings.app_name, channel) {
+            Ok(mask) => mask,
+            Err(e) => {
+                skip(channel, e);
+                continue;
+            }
+        };
+        if let Some((other, _)) = usable_channels.iter().find(|(_, m)| *m == mask) {
+            skip(channel, format!("same hardware channel as channel {}", other + 1).into());
+            continue;
+        }
+        usable_channels.push((channel, mask));
+    }
+
+    if usable_channels.is_empty() {
+        return Err(NO_USABLE_CHANNELS.to_string());
+    }
+
+    let base_dir = output_dir.unwrap_or(".");
+    fs::create_dir_all(base_dir)
+        .map_err(|e| format!("Failed to create output directory '{}': {}", base_dir, e))?;
+    // The hardware the recording was made with, kept next to the logs for traceability.
+    match collect_inventory(backend, &settings.app_name, max_channels, &settings.networks)
+        .and_then(|inventory| save_inventory(&inventory, Path::new(base_dir)))
+    {
+        Ok(path) => progress!("Saved CAN inventory to {}", path.display()),
+        Err(e) => progress!("CAN inventory not saved: {}", e),
+    }
+
+    let port = match open_port_on_channels(backend, &usable_channels, settings) {
+        Ok(port) => port,
+        Err(e) => {
+            // Only when the driver refuses the combined port are the channels opened one by one,
+            // to leave out the ones it refuses.
+            progress!("Opening all channels failed ({}); checking them one by one", e);
+            usable_channels.retain(|&channel| match check_channel(backend, channel, settings) {
+                Ok(()) => true,
+                Err(e) => {
+                    skip(channel.0, e);
+                    false
+                }
+            });
+            if usable_channels.is_empty() {
+                return Err(NO_USABLE_CHANNELS.to_string());
+            }
+            open_port_on_channels(backend, &usable_channels, settings).map_err(|e| e.message)?
+        }
+    };
+    let mut clock = CaptureClock::start(backend, &port);
+
+    let mut targets: Vec<CaptureTarget> = Vec::new();
+    let mut target_by_channel: HashMap<u16, usize> = HashMap::new();
+    for (channel, mask) in &usable_channels {
+        let network = settings.networks.channel_name(*channel);
+        let file_name = format!("{}.{}", network, log_format.file_extension());
+        let log_path = PathBuf::from(base_dir).join(file_name);
+        let file = log_path.to_string_lossy().into_owned();
+        let writer = match CanLogWriter::create(&file, log_format, clock.host_anchor) {
+            Ok(writer) => writer,
+            Err(e) => {
+                backend.close_port(&port);
+                finish_targets(&mut targets);
+                return Err(e);
+            }
+        };
+        target_by_channel.insert(channel_index_of(*mask), targets.len());
+        targets.push(CaptureTarget {
+            app_channel: *channel,
+            network,
+            file,
+            writer: Some(writer),
+            write_error: None,
+            frame_count: 0,
+            error_frame_count: 0,
+            participation: bus_participation(&port, *mask, settings.mode),
+        });
+    }
+
+    progress!(
+        "Starting capture on channels {:?} through one port (access mask 0x{:X})",
+        usable_channels.iter().map(|(channel, _)| channel + 1).collect::<Vec<_>>(),
+        port.access_mask
+    );
+
+    control.send(CaptureEvent::Started {
+        networks: targets.iter().map(CaptureTarget::snapshot).collect(),
+        skipped: skipped.clone(),
+    });
+
+    let receive_error = run_capture(backend, &port, &mut clock, &mut targets, &target_by_channel, duration_ms, control);
+
+    backend.close_port(&port);
+    finish_targets(&mut targets);
+
+    for target in &targets {
+        progress!(
+            "Channel {} capture finished: {} frames, {}",
+            target.app_channel + 1,
+            target.frame_count,
+            target.participation
+        );
+    }
+
+    Ok(CaptureSummary {
+        output_dir: base_dir.to_string(),
+        format: log_format.name().to_string(),
+        networks: targets.iter().map(CaptureTarget::snapshot).collect(),
+        skipped,
+        error: capture_error(receive_error, &targets),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::can_config::CanBusParams;
+    use crate::can_sim::SimulatedBackend;
+
+    const TEST_MAPPING: &str = "[[network]]\nname = \"FD_CANW\"\napp_channel = 0\n\
+                                [[network]]\nname = \"FD_CAN5\"\napp_channel = 1\n\
+                                [[network]]\nname = \"FD_CAN9\"\napp_channel = 2\n";
+
+    fn settings(mode: CanBusMode) -> CanPortSettings {
+        CanPortSettings {
+            app_name: "CANoe".to_string(),
+            interface_version: 4,
+            mode,
+            networks: CanNetworkConfig::from_toml(TEST_MAPPING).unwrap(),
+            refuse_on_drift: false,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cdl_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn any_can_reports_access_denied_on_every_channel() {
+        let backend = SimulatedBackend::from_script(
+            "channel 0 59 0 0\nchannel 1 59 0 1\nopen-status 0 111\nopen-status 1 111\n",
+        )
+        .unwrap();
+
+        let report = scan_can_channels(&backend, 2, 20, &settings(CanBusMode::Active));
+        assert_eq!(report.found_app_channel, None);
+        let err = report.error.unwrap();
+        assert!(err.contains("All 2 channels denied"), "{}", err);
+        assert_eq!(report.channels[1].result, ProbeResult::Error);
+        assert_eq!(report.channels[1].status, Some(111));
+        assert_eq!(report.channels[1].call.as_deref(), Some("xlOpenPort"));
+        assert_eq!(report.channels[1].network, "FD_CAN5");
+
+        // diagnose counts on the status code and the failed call, not on the message text
+        let report = diagnose_can_setup(&backend, 2, &settings(CanBusMode::Active));
+        assert_eq!((report.open_port_errors, report.invalid_channel_errors, report.mapping_errors), (2, 2, 0));
+        assert!(!report.channels_open());
+    }
+
+    #[test]
+    fn any_can_finds_first_channel_with_traffic() {
+        let backend = SimulatedBackend::from_script(
+            "channel 0 59 0 0\nchannel 1 59 0 1\nempty 1\nframe 1 10 rx 100 1 AA\n",
+        )
+        .unwrap();
+
+        let report = scan_can_channels(&backend, 2, 50, &settings(CanBusMode::Active));
+        assert_eq!(report.found_app_channel, Some(1));
+        assert_eq!(report.error, None);
+        let results: Vec<ProbeResult> = report.channels.iter().map(|c| c.result).collect();
+        assert_eq!(results, [ProbeResult::NoFrames, ProbeResult::Frames]);
+    }
+
+    #[test]
+    fn listen_reports_receive_failure() {
+        let backend = SimulatedBackend::from_script(
+            "channel 0 59 0 0\nframe 0 10 rx 100 1 AA\nreceive-status 0 210\n",
+        )
+        .unwrap();
+
+        let err = listen_can_on_channel(&backend, 0, &settings(CanBusMode::Active), Some(1_000), None, CanLogFormat::Text)
+            .unwrap_err();
+        assert_eq!(err, "xlCanReceive failed: status 210 (XL_ERR_CONNECTION_BROKEN)");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn listen_reports_a_failed_log_write() {
+        let backend = SimulatedBackend::from_script("channel 0 59 0 0\nframe 0 10 rx 100 1 AA\n").unwrap();
+
+        // /dev/full takes the buffered header, then fails with ENOSPC once the file is flushed
+        let err = listen_can_on_channel(&backend, 0, &settings(CanBusMode::Active), Some(30), Some("/dev/full"), CanLogFormat::Asc)
+            .unwrap_err();
+        assert!(err.starts_with("/dev/full: Failed to finalize ASC file"), "{}", err);
+    }
+
+    #[test]
+    fn listen_all_writes_one_file_per_usable_network() {
+        let backend = SimulatedBackend::from_script(
+            "channel 0 59 0 0\nchannel 1 59 0 1\nchannel 2 59 1 0\nopen-status 2 111\n\
+             frame 0 10 rx 123 2 01 02\nframe 1 20 tx 7DF 1 03\n",
+        )
+        .unwrap();
+        let dir = temp_dir("listen_all");
+
+        let summary = listen_can_all_connected(
+            &backend,
+            3,
+            &settings(CanBusMode::Active),
+            Some(30),
+            Some(dir.to_str().unwrap()),
+            CanLogFormat::Asc,
+            &CaptureControl::default(),
+        )
+        .unwrap();
+        let captured: Vec<(&str, u64)> = summary.networks.iter().map(|n| (n.network.as_str(), n.frames)).collect();
+        assert_eq!(captured, [("FD_CANW", 1), ("FD_CAN5", 1)]);
+        assert_eq!(summary.skipped.len(), 1);
+        assert_eq!((summary.skipped[0].channel, summary.skipped[0].status), (3, Some(111)));
+        assert_eq!(summary.error, None);
+
+        let fd_canw = fs::read_to_string(dir.join("FD_CANW.asc")).unwrap();
+        assert!(fd_canw.contains("   0.000010 1  123             Rx   d 2 01 02\n"), "{}", fd_canw);
+        assert!(fd_canw.ends_with("End TriggerBlock\n"));
+        let fd_can5 = fs::read_to_string(dir.join("FD_CAN5.asc")).unwrap();
+        assert!(fd_can5.contains("   0.000020 2  7DF             Tx   d 1 03\n"), "{}", fd_can5);
+        assert!(!fd_canw.contains("7DF"), "{}", fd_canw);
+        // One port, one clock: both files carry the same capture start anchor.
+        let anchor = |text: &str| text.lines().find(|l| l.starts_with("// capture start")).map(str::to_string);
+        assert_eq!(anchor(&fd_canw), anchor(&fd_can5));
+        assert!(!dir.join("FD_CAN9.asc").exists());
+        assert!(dir.join(crate::can_inventory::INVENTORY_FILE).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn listen_all_opens_only_the_capture_port() {
+        let backend = SimulatedBackend::from_script("channel 0 59 0 0\nchannel 1 59 0 1\nframe 1 10 rx 100 0\n").unwrap();
+        let dir = temp_dir("listen_all_one_port");
+
+        let summary = listen_can_all_connected(
+            &backend,
+            2,
+            &settings(CanBusMode::Active),
+            Some(20),
+            Some(dir.to_str().unwrap()),
+            CanLogFormat::Text,
+            &CaptureControl::default(),
+        )
+        .unwrap();
+        assert_eq!(summary.networks.len(), 2);
+        assert_eq!(backend.ports_opened(), 1, "no per-channel probe ports");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn listen_all_refuses_to_start_on_layout_drift() {
+        let backend = SimulatedBackend::from_script("channel 0 59 0 0\nchannel 1 59 0 1\nframe 0 10 rx 123 0\n").unwrap();
+        let mut port_settings = settings(CanBusMode::Active);
+        port_settings.networks = CanNetworkConfig::from_toml(
+            "[[network]]\nname = \"FD_CANW\"\napp_channel = 0\nhw_channel = 0\n\
+             [[network]]\nname = \"FD_CAN5\"\napp_channel = 1\nhw_channel = 6\n",
+        )
+        .unwrap();
+        port_settings.refuse_on_drift = true;
+        let dir = temp_dir("drift");
+
+        let err = listen_can_all_connected(
+            &backend,
+            2,
+            &port_settings,
+            Some(30),
+            Some(dir.to_str().unwrap()),
+            CanLogFormat::Asc,
+            &CaptureControl::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err, "Harness layout drift (FD_CAN5 MOVED); refusing to start capture");
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn text_log_uses_event_timestamps() {
+        let backend = SimulatedBackend::from_script(
+            "channel 0 59 0 0\nframe 0 1500 rx 100 1 AA\nerror 0 2500000 Bit error\n",
+        )
+        .unwrap();
+        let dir = temp_dir("text_ts");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ch1.log");
+
+        listen_can_on_channel(&backend, 0, &settings(CanBusMode::Active), Some(30), path.to_str(), CanLogFormat::Text).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("# capture start (host clock) "), "{}", text);
+        assert!(lines[1].starts_with("   0.001500 frame=1 channel=0 id=0x100"), "{}", text);
+        assert!(lines[2].starts_with("   2.500000 error channel=0"), "{}", text);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn passive_mode_only_configures_channels_with_init_access() {
+        let backend = SimulatedBackend::from_script(
+            "channel 0 59 0 0\nchannel 1 59 0 1\nno-init-access 1\nframe 1 10 rx 100 0\n",
+        )
+        .unwrap();
+        let dir = temp_dir("passive");
+
+        listen_can_all_connected(
+            &backend,
+            2,
+            &settings(CanBusMode::Passive),
+            Some(30),
+            Some(dir.to_str().unwrap()),
+            CanLogFormat::Text,
+            &CaptureControl::default(),
+        )
+        .unwrap();
+
+        assert_eq!(backend.configured(0), Some((CanBusMode::Passive, CanBusParams::DEFAULT_FD)));
+        assert_eq!(backend.configured(1), None);
+        let port = CanPort {
+            handle: 0,
+            access_mask: 0b11,
+            permission_mask: 0b01,
+        };
+        assert_eq!(bus_participation(&port, 0b01, CanBusMode::Active), "active (normal output mode)");
+        assert!(bus_participation(&port, 0b10, CanBusMode::Active).starts_with("passive (no init access"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn applies_bus_parameters_of_the_network() {
+        let backend = SimulatedBackend::from_script("channel 10 59 0 7\nframe 10 10 rx 100 0\n").unwrap();
+        let mut port_settings = settings(CanBusMode::Active);
+        port_settings.networks = CanNetworkConfig::from_toml(
+            "[[network]]\nname = \"HS_CAN1\"\napp_channel = 10\nbus = \"classic\"\nbitrate = 250000\nsample_point = 0.875\n",
+        )
+        .unwrap();
+
+        listen_can_on_channel(&backend, 10, &port_settings, Some(20), None, CanLogFormat::Text).unwrap();
+
+        let (mode, params) = backend.configured(10).unwrap();
+        assert_eq!(mode, CanBusMode::Active);
+        let CanBusParams::Classic(timing) = params else {
+            panic!("HS_CAN1 should be configured as classic CAN, got {:?}", params);
+        };
+        assert_eq!(timing.bitrate, 250_000);
+        assert_eq!(timing.sample_point(), 0.875);
+    }
+
+    #[test]
+    fn sim_script_rejects_unknown_channel() {
+        let err = SimulatedBackend::from_script("frame 3 0 rx 1 0\n").err().unwrap();
+        assert!(err.contains("line 1: unknown app channel"), "{}", err);
+    }
+}
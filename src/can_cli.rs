@@ -0,0 +1,479 @@
+// `--test-can` command line handling: option parsing, backend selection and dispatch
+// to the capture functions in can_capture. With `--json` every command prints one JSON
+// object on stdout (progress text moves to stderr); the process exit code tells scripts
+// and the GUI how the command went.
+
+use crate::can_backend::{CanBackend, CanBusMode};
+use crate::can_capture;
+use crate::can_capture::{progress, CanLogFormat, CanPortSettings, CaptureControl, ChannelMapping};
+use crate::can_config::CanNetworkConfig;
+use crate::can_inventory;
+use crate::can_layout;
+use crate::can_layout::{LayoutCheck, LayoutState};
+use crate::can_sim::SimulatedBackend;
+use crate::process_control;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+
+/// The command finished and found what it was asked for.
+pub const EXIT_OK: i32 = 0;
+/// The command ran but failed (capture or write error, refused on layout drift).
+pub const EXIT_FAILED: i32 = 1;
+/// Invalid options or network config.
+pub const EXIT_USAGE: i32 = 2;
+/// The backend could not be opened (driver missing, simulation script error).
+pub const EXIT_BACKEND: i32 = 3;
+/// No usable channel: nothing mapped or openable, or no traffic seen.
+pub const EXIT_NO_CHANNEL: i32 = 4;
+/// `--can-map` found MOVED or MISSING networks.
+pub const EXIT_LAYOUT_DRIFT: i32 = 5;
+
+/// `--can-map --json` output.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapReport {
+    pub backend: String,
+    pub app_name: String,
+    pub channels: Vec<ChannelMapping>,
+    pub layout: Vec<LayoutCheck>,
+}
+
+pub struct CanCliOptions {
+    pub backend: String,
+    pub sim_script: Option<String>,
+    pub ifaces: Option<String>,
+    pub selected_channel: Option<u32>,
+    pub timeout_ms: u64,
+    pub app_name: String,
+    pub iface_version: u32,
+    pub max_channels: u32,
+    pub diagnose: bool,
+    pub map: bool,
+    pub inventory: bool,
+    pub listen: bool,
+    pub listen_all: bool,
+    pub duration_ms: Option<u64>,
+    pub log_file: Option<String>,
+    pub log_format: String,
+    pub output_dir: Option<String>,
+    pub bus_mode: String,
+    pub network_config: Option<String>,
+    pub refuse_on_drift: bool,
+    pub json: bool,
+}
+
+impl CanCliOptions {
+    pub fn parse(args: &[String]) -> Self {
+        let mut options = CanCliOptions {
+            backend: String::from("vector"),
+            sim_script: None,
+            ifaces: None,
+            selected_channel: None,
+            timeout_ms: 300,
+            app_name: String::from("CANoe"),
+            iface_version: 4,
+            max_channels: 64,
+            diagnose: false,
+            map: false,
+            inventory: false,
+            listen: false,
+            listen_all: false,
+            duration_ms: None,
+            log_file: None,
+            log_format: String::from("text"),
+            output_dir: None,
+            bus_mode: String::from("active"),
+            network_config: None,
+            refuse_on_drift: false,
+            json: false,
+        };
+
+        let mut index = 0usize;
+        while index < args.len() {
+            let value = args.get(index + 1);
+            match (args[index].as_str(), value) {
+                ("--can-backend", Some(v)) => options.backend = v.to_lowercase(),
+                ("--can-sim-script", Some(v)) => options.sim_script = Some(v.clone()),
+                ("--can-ifaces", Some(v)) => options.ifaces = Some(v.clone()),
+                ("--can-channel", Some(v)) => options.selected_channel = v.parse::<u32>().ok(),
+                ("--can-timeout-ms", Some(v)) => {
+                    if let Ok(parsed) = v.parse::<u64>() {
+                        options.timeout_ms = parsed;
+                    }
+                }
+                ("--can-app", Some(v)) => options.app_name = v.clone(),
+                ("--can-iface-version", Some(v)) => {
+                    if let Ok(parsed) = v.parse::<u32>() {
+                        options.iface_version = parsed;
+                    }
+                }
+                ("--can-max-channels", Some(v)) => {
+                    if let Ok(parsed) = v.parse::<u32>() {
+                        options.max_channels = parsed.clamp(1, 64);
+                    }
+                }
+                ("--can-diagnose", _) => options.diagnose = true,
+                ("--can-map", _) => options.map = true,
+                ("--can-inventory", _) => options.inventory = true,
+                ("--can-listen", _) => options.listen = true,
+                ("--can-listen-all", _) => options.listen_all = true,
+                ("--can-duration-ms", Some(v)) => {
+                    if let Ok(parsed) = v.parse::<u64>() {
+                        options.duration_ms = Some(parsed);
+                    }
+                }
+                ("--can-log-file", Some(v)) => options.log_file = Some(v.clone()),
+                ("--can-output-dir", Some(v)) => options.output_dir = Some(v.clone()),
+                ("--can-log-format", Some(v)) => options.log_format = v.to_lowercase(),
+                ("--can-mode", Some(v)) => options.bus_mode = v.to_lowercase(),
+                ("--can-config", Some(v)) => options.network_config = Some(v.clone()),
+                ("--can-refuse-drift", _) => options.refuse_on_drift = true,
+                ("--json", _) => options.json = true,
+                _ => {}
+            }
+            index += 1;
+        }
+
+        options
+    }
+
+    /// Name of the selected command, as the `command` field of the JSON output.
+    fn command(&self) -> &'static str {
+        if self.inventory {
+            "inventory"
+        } else if self.map {
+            "map"
+        } else if self.diagnose {
+            "diagnose"
+        } else if self.listen_all {
+            "listen_all"
+        } else if self.listen {
+            "listen"
+        } else if self.selected_channel.is_some() {
+            "capture"
+        } else {
+            "scan"
+        }
+    }
+
+    /// Prints the command result: `value` plus a `command` field in `--json` mode, `text` otherwise.
+    fn report<T: Serialize>(&self, value: &T, text: &str) {
+        if self.json {
+            let mut json = serde_json::to_value(value).unwrap_or_else(|e| json!({ "error": e.to_string() }));
+            if let Some(object) = json.as_object_mut() {
+                object.insert("command".to_string(), json!(self.command()));
+            }
+            println!("{}", json);
+        } else if !text.is_empty() {
+            println!("{}", text);
+        }
+    }
+
+    /// Reports a failure and returns `code`.
+    fn fail(&self, code: i32, message: &str) -> i32 {
+        self.report(&json!({ "error": message, "exit_code": code }), message);
+        code
+    }
+
+    pub fn format(&self) -> Result<CanLogFormat, String> {
+        CanLogFormat::from_name(&self.log_format)
+            .ok_or_else(|| format!("Unknown CAN log format '{}' (expected text, asc, blf or mf4)", self.log_format))
+    }
+
+    /// `--can-config <file>`, otherwise can_networks.toml next to the executable if present.
+    pub fn load_network_config(&self) -> Result<CanNetworkConfig, String> {
+        match &self.network_config {
+            Some(path) => CanNetworkConfig::load(Path::new(path)),
+            None => CanNetworkConfig::load_default(),
+        }
+    }
+
+    /// Fails on a `--can-mode` other than active or passive; guessing would risk ACKing on a
+    /// bus another application owns.
+    pub fn port_settings(&self, networks: &CanNetworkConfig) -> Result<CanPortSettings, String> {
+        let mode = CanBusMode::from_name(&self.bus_mode)
+            .ok_or_else(|| format!("Unknown CAN bus mode '{}' (expected active or passive)", self.bus_mode))?;
+        Ok(CanPortSettings {
+            app_name: self.app_name.clone(),
+            interface_version: self.iface_version,
+            mode,
+            networks: networks.clone(),
+            refuse_on_drift: self.refuse_on_drift,
+        })
+    }
+}
+
+/// Opens the backend selected with `--can-backend` and runs `f` on it. Errors carry the exit code.
+pub fn with_backend<R>(options: &CanCliOptions, f: impl FnOnce(&dyn CanBackend) -> R) -> Result<R, (i32, String)> {
+    match options.backend.as_str() {
+        "sim" => {
+            let backend = match &options.sim_script {
+                Some(path) => SimulatedBackend::from_script_file(path)
+                    .map_err(|e| (EXIT_BACKEND, format!("CAN simulation error: {}", e)))?,
+                None => SimulatedBackend::demo(),
+            };
+            progress!("Using simulated CAN backend");
+            Ok(f(&backend))
+        }
+        "vector" => {
+            #[cfg(feature = "vxl-can")]
+            {
+                let backend = crate::vxl_capture::VectorXlBackend::open()
+                    .map_err(|e| (EXIT_BACKEND, format!("vxlapi error: {}", e)))?;
+                progress!("vxlapi driver opened successfully");
+                Ok(f(&backend))
+            }
+
+            #[cfg(not(feature = "vxl-can"))]
+            {
+                Err((
+                    EXIT_BACKEND,
+                    "--test-can requested, but vxl CAN support is disabled. Rebuild with --features vxl-can (or use --can-backend sim)"
+                        .to_string(),
+                ))
+            }
+        }
+        "socketcan" => {
+            #[cfg(all(feature = "socketcan", target_os = "linux"))]
+            {
+                let backend = crate::socketcan_capture::SocketCanBackend::open(options.ifaces.as_deref())
+                    .map_err(|e| (EXIT_BACKEND, format!("SocketCAN error: {}", e)))?;
+                progress!("Using SocketCAN interfaces: {}", backend.interface_names().join(", "));
+                Ok(f(&backend))
+            }
+
+            #[cfg(not(all(feature = "socketcan", target_os = "linux")))]
+            {
+                Err((
+                    EXIT_BACKEND,
+                    "--can-backend socketcan requires a Linux build with --features socketcan".to_string(),
+                ))
+            }
+        }
+        other => Err((
+            EXIT_USAGE,
+            format!("Unknown CAN backend '{}' (expected vector, socketcan or sim)", other),
+        )),
+    }
+}
+
+/// The `--can-map` result: the channel mapping of `options.app_name` and the harness layout check.
+pub fn map_report(backend: &dyn CanBackend, options: &CanCliOptions, networks: &CanNetworkConfig) -> Result<MapReport, String> {
+    let channels = can_capture::print_can_channel_mapping(backend, options.max_channels, &options.app_name, networks)?;
+    let layout = can_layout::check_layout(backend, &options.app_name, networks);
+    can_layout::print_layout_report(&layout);
+    Ok(MapReport {
+        backend: backend.name().to_string(),
+        app_name: options.app_name.clone(),
+        channels,
+        layout,
+    })
+}
+
+/// Opens the backend selected with `--can-backend`, runs the requested CAN command on it and
+/// returns the process exit code.
+pub fn run(options: &CanCliOptions) -> i32 {
+    can_capture::set_json_output(options.json);
+    let networks = match options.load_network_config() {
+        Ok(networks) => networks,
+        Err(e) => return options.fail(EXIT_USAGE, &e),
+    };
+    if let Some(path) = &networks.source {
+        progress!("Using CAN network config {}", path.display());
+    }
+    if let Err(e) = options.port_settings(&networks).and(options.format()) {
+        return options.fail(EXIT_USAGE, &e);
+    }
+    if options.listen || options.listen_all {
+        // Ctrl+C then stops the capture instead of the process, and the files get finished.
+        process_control::install_interrupt_handler();
+    }
+
+    match with_backend(options, |backend| run_with_backend(backend, options, &networks)) {
+        Ok(code) => code,
+        Err((code, e)) => options.fail(code, &e),
+    }
+}
+
+pub fn run_with_backend(backend: &dyn CanBackend, options: &CanCliOptions, networks: &CanNetworkConfig) -> i32 {
+    let (settings, format) = match options.port_settings(networks).and_then(|settings| Ok((settings, options.format()?))) {
+        Ok(checked) => checked,
+        Err(e) => return options.fail(EXIT_USAGE, &e),
+    };
+    if options.inventory {
+        match can_inventory::collect_inventory(backend, &options.app_name, options.max_channels, networks) {
+            Ok(inventory) => {
+                can_inventory::print_inventory(&inventory);
+                if let Some(dir) = &options.output_dir {
+                    match can_inventory::save_inventory(&inventory, Path::new(dir)) {
+                        Ok(path) => progress!("Saved CAN inventory to {}", path.display()),
+                        Err(e) => return options.fail(EXIT_FAILED, &e),
+                    }
+                }
+                options.report(&inventory, "");
+                EXIT_OK
+            }
+            Err(e) => options.fail(EXIT_FAILED, &format!("CAN inventory error: {}", e)),
+        }
+    } else if options.map {
+        let report = match map_report(backend, options, networks) {
+            Ok(report) => report,
+            Err(e) => return options.fail(EXIT_USAGE, &e),
+        };
+        let code = if !report.channels.iter().any(|c| c.hw.hw_type != 0) {
+            EXIT_NO_CHANNEL
+        } else if report.layout.iter().any(|c| c.state != LayoutState::Ok) {
+            EXIT_LAYOUT_DRIFT
+        } else {
+            EXIT_OK
+        };
+        options.report(&report, "");
+        code
+    } else if options.diagnose {
+        let report = can_capture::diagnose_can_setup(backend, options.max_channels, &settings);
+        options.report(&report, "");
+        if report.channels_open() { EXIT_OK } else { EXIT_NO_CHANNEL }
+    } else if options.listen_all {
+        match can_capture::listen_can_all_connected(
+            backend,
+            options.max_channels,
+            &settings,
+            options.duration_ms,
+            options.output_dir.as_deref(),
+            format,
+            &CaptureControl::default(),
+        ) {
+            Ok(summary) => {
+                let text = summary
+                    .error
+                    .as_ref()
+                    .map(|e| format!("CAN listen-all error: {}", e))
+                    .unwrap_or_default();
+                options.report(&summary, &text);
+                if summary.error.is_some() { EXIT_FAILED } else { EXIT_OK }
+            }
+            Err(e) if e == can_capture::NO_USABLE_CHANNELS => {
+                options.fail(EXIT_NO_CHANNEL, &format!("CAN listen-all error: {}", e))
+            }
+            Err(e) => options.fail(EXIT_FAILED, &format!("CAN listen-all error: {}", e)),
+        }
+    } else if options.listen {
+        let Some(channel) = options.selected_channel else {
+            return options.fail(EXIT_USAGE, "--can-listen requires --can-channel <n>");
+        };
+        // Without --can-log-file, --can-output-dir gets a file named after the channel's network.
+        let log_file = match (&options.log_file, &options.output_dir) {
+            (Some(file), _) => Some(file.clone()),
+            (None, Some(dir)) => {
+                if let Err(e) = std::fs::create_dir_all(dir) {
+                    return options.fail(EXIT_FAILED, &format!("Failed to create output directory '{}': {}", dir, e));
+                }
+                let file_name = format!("{}.{}", networks.channel_name(channel), format.file_extension());
+                Some(Path::new(dir).join(file_name).to_string_lossy().to_string())
+            }
+            (None, None) => None,
+        };
+        match can_capture::listen_can_on_channel(
+            backend,
+            channel,
+            &settings,
+            options.duration_ms,
+            log_file.as_deref(),
+            format,
+        ) {
+            Ok(frames) => {
+                let result = json!({
+                    "channel": channel + 1,
+                    "app_channel": channel,
+                    "network": networks.channel_name(channel),
+                    "file": log_file,
+                    "frames": frames,
+                });
+                options.report(&result, "");
+                EXIT_OK
+            }
+            Err(e) => options.fail(EXIT_FAILED, &format!("CAN listen error: {}", e)),
+        }
+    } else if let Some(channel) = options.selected_channel {
+        progress!(
+            "Listening on CAN channel {} for {} ms (app: {}, iface: {}, mode: {})...",
+            channel,
+            options.timeout_ms,
+            options.app_name,
+            options.iface_version,
+            settings.mode.name()
+        );
+        match can_capture::try_capture_can_on_channel(
+            backend,
+            channel,
+            options.timeout_ms,
+            &settings,
+        ) {
+            Ok(frames) => {
+                let result = json!({ "app_channel": channel, "frames": frames });
+                if frames {
+                    options.report(&result, &format!("CAN capture complete on channel {}", channel));
+                    EXIT_OK
+                } else {
+                    options.report(&result, &format!("No CAN frame received on channel {}", channel));
+                    EXIT_NO_CHANNEL
+                }
+            }
+            Err(e) => options.fail(EXIT_FAILED, &format!("CAN capture error on channel {}: {}", channel, e)),
+        }
+    } else {
+        progress!(
+            "Auto-scanning CAN channels 0..{} for traffic (app: {}, iface: {})...",
+            options.max_channels.saturating_sub(1),
+            options.app_name,
+            options.iface_version
+        );
+        let report = can_capture::scan_can_channels(
+            backend,
+            options.max_channels,
+            options.timeout_ms,
+            &settings,
+        );
+        let text = match (report.found_app_channel, &report.error) {
+            (Some(channel), _) => format!("Detected traffic on CAN channel {}", channel),
+            (None, error) => format!("CAN auto-scan result: {}", error.as_deref().unwrap_or_default()),
+        };
+        options.report(&report, &text);
+        if report.found_app_channel.is_some() { EXIT_OK } else { EXIT_NO_CHANNEL }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &str) -> CanCliOptions {
+        let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+        CanCliOptions::parse(&args)
+    }
+
+    #[test]
+    fn exit_codes_follow_the_command_result() {
+        let backend = SimulatedBackend::from_script("channel 0 59 0 0\nchannel 1 59 0 1\nempty 0\nempty 1\n").unwrap();
+        let networks = CanNetworkConfig::from_toml(
+            "[[network]]\nname = \"FD_CANW\"\napp_channel = 0\nhw_channel = 0\n\
+             [[network]]\nname = \"FD_CAN5\"\napp_channel = 1\nhw_channel = 1\n",
+        )
+        .unwrap();
+
+        let map = options("--test-can --can-map --json --can-max-channels 2");
+        assert_eq!(map.command(), "map");
+        assert_eq!(run_with_backend(&backend, &map, &networks), EXIT_OK);
+        let moved = CanNetworkConfig::from_toml("[[network]]\nname = \"FD_CAN5\"\napp_channel = 1\nhw_channel = 4\n").unwrap();
+        assert_eq!(run_with_backend(&backend, &map, &moved), EXIT_LAYOUT_DRIFT);
+
+        let scan = options("--test-can --json --can-max-channels 2 --can-timeout-ms 20");
+        assert_eq!(scan.command(), "scan");
+        assert_eq!(run_with_backend(&backend, &scan, &networks), EXIT_NO_CHANNEL);
+        assert_eq!(run_with_backend(&backend, &options("--can-listen"), &networks), EXIT_USAGE);
+        let typo = options("--test-can --can-listen-all --can-mode pasive --can-max-channels 2");
+        assert_eq!(run_with_backend(&backend, &typo, &networks), EXIT_USAGE);
+        let typo = options("--test-can --can-listen-all --can-log-format blff --can-max-channels 2");
+        assert_eq!(run_with_backend(&backend, &typo, &networks), EXIT_USAGE);
+    }
+}
@@ -0,0 +1,584 @@
+// CAN network configuration (can_networks.toml or .json): which app channel carries which
+// network on which interface, and the bus parameters of each network.
+// Every network is classic CAN or CAN FD with its own bitrates and either a sample point or
+// explicit time segments. Everything is validated when the file is loaded, so only timings the
+// controller can actually run ever reach the driver.
+//
+//   [[network]]
+//   name = "HS_CAN1"
+//   app_channel = 10         # 0-based app channel (user channel 11)
+//   device = "vn 1670 1"     # label shown in the mapping
+//   hw_type = 59             # expected location (optional, any subset): hwType/hwIndex/hwChannel
+//   hw_index = 0             # as printed by --can-map, and the interface serial number
+//   hw_channel = 7
+//   hw_serial = 12345
+//   bus = "classic"
+//   bitrate = 500000
+//   sample_point = 0.8
+//
+//   [[network]]
+//   name = "FD_CAN5"
+//   app_channel = 1
+//   bus = "fd"
+//   bitrate = 500000
+//   sample_point = 0.8
+//   data_bitrate = 5000000
+//   data_tseg1 = 11          # explicit segments instead of data_sample_point
+//   data_tseg2 = 4
+//   data_sjw = 4
+//
+// Networks without `bus` use CAN FD 500 kbit/s / 2 Mbit/s (the previous built-in timing).
+// App channels without a network are written as `channel<N>` (1-based).
+// The JSON form is the same structure: {"network": [{"name": "HS_CAN1", "app_channel": 10, ...}]}.
+
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_CONFIG_FILE: &str = "can_networks.toml";
+
+/// CAN FD controller clock of the Vector interfaces; every bit must be a whole number of
+/// prescaled clock periods.
+const CAN_CLOCK_HZ: u64 = 80_000_000;
+
+/// Largest accepted deviation between a requested sample point and the one the segments give.
+const MAX_SAMPLE_POINT_ERROR: f64 = 0.02;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CanBitTiming {
+    pub bitrate: u32,
+    pub sjw: u32,
+    pub tseg1: u32,
+    pub tseg2: u32,
+}
+
+impl CanBitTiming {
+    pub fn sample_point(&self) -> f64 {
+        (1 + self.tseg1) as f64 / (1 + self.tseg1 + self.tseg2) as f64
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CanBusParams {
+    Classic(CanBitTiming),
+    Fd { arbitration: CanBitTiming, data: CanBitTiming },
+}
+
+impl CanBusParams {
+    /// CAN FD 500 kbit/s (80 %) / 2 Mbit/s (75 %).
+    pub const DEFAULT_FD: CanBusParams = CanBusParams::Fd {
+        arbitration: CanBitTiming {
+            bitrate: 500_000,
+            sjw: 16,
+            tseg1: 63,
+            tseg2: 16,
+        },
+        data: CanBitTiming {
+            bitrate: 2_000_000,
+            sjw: 10,
+            tseg1: 29,
+            tseg2: 10,
+        },
+    };
+
+    pub fn describe(&self) -> String {
+        match self {
+            CanBusParams::Classic(timing) => format!(
+                "classic {} bit/s, sample point {:.1} %",
+                timing.bitrate,
+                timing.sample_point() * 100.0
+            ),
+            CanBusParams::Fd { arbitration, data } => format!(
+                "FD {}/{} bit/s, sample points {:.1} %/{:.1} %",
+                arbitration.bitrate,
+                data.bitrate,
+                arbitration.sample_point() * 100.0,
+                data.sample_point() * 100.0
+            ),
+        }
+    }
+}
+
+/// Segment ranges of one bit-timing phase (XLcanFdConf limits).
+struct PhaseLimits {
+    name: &'static str,
+    max_bitrate: u32,
+    sjw_max: u32,
+    tseg1_max: u32,
+    tseg2_max: u32,
+}
+
+const NOMINAL_LIMITS: PhaseLimits = PhaseLimits {
+    name: "arbitration",
+    max_bitrate: 1_000_000,
+    sjw_max: 128,
+    tseg1_max: 255,
+    tseg2_max: 128,
+};
+
+const DATA_LIMITS: PhaseLimits = PhaseLimits {
+    name: "data",
+    max_bitrate: 8_000_000,
+    sjw_max: 64,
+    tseg1_max: 127,
+    tseg2_max: 64,
+};
+
+/// Checks explicit segments against the phase limits and the controller clock.
+fn validate_timing(timing: &CanBitTiming, limits: &PhaseLimits) -> Result<(), String> {
+    let phase = limits.name;
+    if timing.bitrate == 0 || timing.bitrate > limits.max_bitrate {
+        return Err(format!(
+            "{} bitrate {} is outside 1..={} bit/s",
+            phase, timing.bitrate, limits.max_bitrate
+        ));
+    }
+    if timing.tseg1 == 0 || timing.tseg1 > limits.tseg1_max {
+        return Err(format!("{} tseg1 {} is outside 1..={}", phase, timing.tseg1, limits.tseg1_max));
+    }
+    if timing.tseg2 == 0 || timing.tseg2 > limits.tseg2_max {
+        return Err(format!("{} tseg2 {} is outside 1..={}", phase, timing.tseg2, limits.tseg2_max));
+    }
+    if timing.sjw == 0 || timing.sjw > limits.sjw_max {
+        return Err(format!("{} sjw {} is outside 1..={}", phase, timing.sjw, limits.sjw_max));
+    }
+    if timing.sjw > timing.tseg2 || timing.sjw > timing.tseg1 {
+        return Err(format!(
+            "{} sjw {} must not exceed tseg1 ({}) or tseg2 ({})",
+            phase, timing.sjw, timing.tseg1, timing.tseg2
+        ));
+    }
+    let quanta = (1 + timing.tseg1 + timing.tseg2) as u64;
+    let bit_clocks = timing.bitrate as u64 * quanta;
+    if !CAN_CLOCK_HZ.is_multiple_of(bit_clocks) {
+        return Err(format!(
+            "{} bitrate {} with {} time quanta per bit needs a prescaler of {:.3}; it must divide the {} MHz CAN clock exactly",
+            phase,
+            timing.bitrate,
+            quanta,
+            CAN_CLOCK_HZ as f64 / bit_clocks as f64,
+            CAN_CLOCK_HZ / 1_000_000
+        ));
+    }
+    Ok(())
+}
+
+/// Picks segments for `sample_point`, preferring the most time quanta among the closest matches.
+fn timing_for_sample_point(bitrate: u32, sample_point: f64, limits: &PhaseLimits) -> Result<CanBitTiming, String> {
+    if !(0.5..=0.95).contains(&sample_point) {
+        return Err(format!(
+            "{} sample point {} is outside 0.5..=0.95",
+            limits.name, sample_point
+        ));
+    }
+    if bitrate == 0 || bitrate > limits.max_bitrate {
+        return Err(format!(
+            "{} bitrate {} is outside 1..={} bit/s",
+            limits.name, bitrate, limits.max_bitrate
+        ));
+    }
+
+    let max_quanta = 1 + limits.tseg1_max + limits.tseg2_max;
+    let mut best: Option<(f64, CanBitTiming)> = None;
+    for quanta in (4..=max_quanta).rev() {
+        if !CAN_CLOCK_HZ.is_multiple_of(bitrate as u64 * quanta as u64) {
+            continue;
+        }
+        let tseg2 = ((quanta as f64) * (1.0 - sample_point)).round() as u32;
+        if tseg2 == 0 || tseg2 > limits.tseg2_max || tseg2 + 1 >= quanta {
+            continue;
+        }
+        let tseg1 = quanta - 1 - tseg2;
+        if tseg1 > limits.tseg1_max {
+            continue;
+        }
+        let timing = CanBitTiming {
+            bitrate,
+            sjw: tseg2.min(tseg1).min(limits.sjw_max),
+            tseg1,
+            tseg2,
+        };
+        let error = (timing.sample_point() - sample_point).abs();
+        if best.as_ref().is_none_or(|(best_error, _)| error < *best_error - 1e-9) {
+            best = Some((error, timing));
+        }
+    }
+
+    match best {
+        Some((error, timing)) if error <= MAX_SAMPLE_POINT_ERROR => Ok(timing),
+        Some((_, timing)) => Err(format!(
+            "{} sample point {} is not reachable at {} bit/s (closest is {:.3})",
+            limits.name,
+            sample_point,
+            bitrate,
+            timing.sample_point()
+        )),
+        None => Err(format!(
+            "{} bitrate {} cannot be derived from the {} MHz CAN clock",
+            limits.name,
+            bitrate,
+            CAN_CLOCK_HZ / 1_000_000
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    #[serde(default)]
+    network: Vec<RawNetwork>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawNetwork {
+    name: String,
+    app_channel: Option<u32>,
+    device: Option<String>,
+    hw_type: Option<u32>,
+    hw_index: Option<u32>,
+    hw_channel: Option<u32>,
+    hw_serial: Option<u32>,
+    bus: Option<String>,
+    bitrate: Option<u32>,
+    sample_point: Option<f64>,
+    sjw: Option<u32>,
+    tseg1: Option<u32>,
+    tseg2: Option<u32>,
+    data_bitrate: Option<u32>,
+    data_sample_point: Option<f64>,
+    data_sjw: Option<u32>,
+    data_tseg1: Option<u32>,
+    data_tseg2: Option<u32>,
+}
+
+/// One phase from either explicit segments or a sample point (default 80 %).
+fn phase_timing(
+    bitrate: u32,
+    sample_point: Option<f64>,
+    segments: (Option<u32>, Option<u32>, Option<u32>),
+    limits: &PhaseLimits,
+) -> Result<CanBitTiming, String> {
+    match segments {
+        (None, None, None) => timing_for_sample_point(bitrate, sample_point.unwrap_or(0.8), limits),
+        (Some(tseg1), Some(tseg2), sjw) => {
+            if sample_point.is_some() {
+                return Err(format!("{} phase sets both a sample point and explicit segments", limits.name));
+            }
+            let timing = CanBitTiming {
+                bitrate,
+                sjw: sjw.unwrap_or(tseg2.min(tseg1)),
+                tseg1,
+                tseg2,
+            };
+            validate_timing(&timing, limits)?;
+            Ok(timing)
+        }
+        _ => Err(format!("{} phase needs both tseg1 and tseg2 when segments are given", limits.name)),
+    }
+}
+
+impl RawNetwork {
+    /// `None` when the network only maps a channel and keeps the default timing.
+    fn bus_params(&self) -> Result<Option<CanBusParams>, String> {
+        let Some(bus) = &self.bus else {
+            let timing_fields = self.bitrate.is_some()
+                || self.sample_point.is_some()
+                || self.tseg1.is_some()
+                || self.tseg2.is_some()
+                || self.sjw.is_some()
+                || self.data_bitrate.is_some()
+                || self.data_sample_point.is_some()
+                || self.data_tseg1.is_some()
+                || self.data_tseg2.is_some()
+                || self.data_sjw.is_some();
+            if timing_fields {
+                return Err("timing settings need bus = \"classic\" or \"fd\"".to_string());
+            }
+            return Ok(None);
+        };
+        let bitrate = self.bitrate.ok_or("bitrate is missing")?;
+        let arbitration = phase_timing(
+            bitrate,
+            self.sample_point,
+            (self.tseg1, self.tseg2, self.sjw),
+            &NOMINAL_LIMITS,
+        )?;
+        let data_fields = self.data_sample_point.is_some()
+            || self.data_sjw.is_some()
+            || self.data_tseg1.is_some()
+            || self.data_tseg2.is_some();
+
+        match bus.to_lowercase().as_str() {
+            "classic" | "can" => {
+                if self.data_bitrate.is_some() || data_fields {
+                    return Err("classic network has data phase settings".to_string());
+                }
+                Ok(Some(CanBusParams::Classic(arbitration)))
+            }
+            "fd" | "canfd" => {
+                let data_bitrate = self.data_bitrate.ok_or("FD network needs data_bitrate")?;
+                if data_bitrate < bitrate {
+                    return Err(format!(
+                        "data bitrate {} is below the arbitration bitrate {}",
+                        data_bitrate, bitrate
+                    ));
+                }
+                let data = phase_timing(
+                    data_bitrate,
+                    self.data_sample_point,
+                    (self.data_tseg1, self.data_tseg2, self.data_sjw),
+                    &DATA_LIMITS,
+                )?;
+                Ok(Some(CanBusParams::Fd { arbitration, data }))
+            }
+            other => Err(format!("unknown bus '{}' (expected classic or fd)", other)),
+        }
+    }
+}
+
+/// One configured network: where it is connected and, optionally, its own bus parameters.
+/// The `hw_*` fields are the expected harness layout; unset fields are not checked.
+#[derive(Clone, Debug)]
+pub struct CanNetwork {
+    pub name: String,
+    pub app_channel: Option<u32>,
+    pub device: Option<String>,
+    pub hw_type: Option<u32>,
+    pub hw_index: Option<u32>,
+    pub hw_channel: Option<u32>,
+    pub hw_serial: Option<u32>,
+    params: Option<CanBusParams>,
+}
+
+impl CanNetwork {
+    /// Interface column of the channel mapping, e.g. `vn 1670 1 (SN 12345 hwChannel 3)`.
+    pub fn hardware_label(&self) -> String {
+        let location = match (self.hw_serial, self.hw_channel) {
+            (Some(serial), Some(channel)) => Some(format!("SN {} hwChannel {}", serial, channel)),
+            (Some(serial), None) => Some(format!("SN {}", serial)),
+            (None, Some(channel)) => Some(format!("hwChannel {}", channel)),
+            (None, None) => None,
+        };
+        match (&self.device, location) {
+            (Some(device), Some(location)) => format!("{} ({})", device, location),
+            (Some(device), None) => device.clone(),
+            (None, Some(location)) => location,
+            (None, None) => "-".to_string(),
+        }
+    }
+}
+
+/// Validated network configuration. Unknown networks fall back to `CanBusParams::DEFAULT_FD`.
+#[derive(Clone, Debug, Default)]
+pub struct CanNetworkConfig {
+    networks: Vec<CanNetwork>,
+    pub source: Option<PathBuf>,
+}
+
+impl CanNetworkConfig {
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        Self::from_raw(toml::from_str(text).map_err(|e| e.to_string())?)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        Self::from_raw(serde_json::from_str(text).map_err(|e| e.to_string())?)
+    }
+
+    fn from_raw(raw: RawConfig) -> Result<Self, String> {
+        let mut networks: Vec<CanNetwork> = Vec::new();
+        for network in raw.network {
+            if networks.iter().any(|n| n.name.eq_ignore_ascii_case(&network.name)) {
+                return Err(format!("network '{}' is configured twice", network.name));
+            }
+            if let Some(other) = networks
+                .iter()
+                .find(|n| n.app_channel.is_some() && n.app_channel == network.app_channel)
+            {
+                return Err(format!(
+                    "network '{}': app channel {} is already mapped to '{}'",
+                    network.name,
+                    network.app_channel.unwrap_or_default(),
+                    other.name
+                ));
+            }
+            let params = network
+                .bus_params()
+                .map_err(|e| format!("network '{}': {}", network.name, e))?;
+            networks.push(CanNetwork {
+                name: network.name,
+                app_channel: network.app_channel,
+                device: network.device,
+                hw_type: network.hw_type,
+                hw_index: network.hw_index,
+                hw_channel: network.hw_channel,
+                hw_serial: network.hw_serial,
+                params,
+            });
+        }
+        Ok(Self { networks, source: None })
+    }
+
+    /// Reads a `.json` file as JSON and anything else as TOML.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read CAN network config '{}': {}", path.display(), e))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let parsed = if is_json { Self::from_json(&text) } else { Self::from_toml(&text) };
+        let mut config = parsed.map_err(|e| format!("Invalid CAN network config '{}': {}", path.display(), e))?;
+        config.source = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Loads `can_networks.toml` from the executable's folder, then the working directory, or
+    /// returns an empty configuration (no mapping, default timing) if neither has one.
+    pub fn load_default() -> Result<Self, String> {
+        let exe_dir = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(DEFAULT_CONFIG_FILE)));
+        let candidates = exe_dir.into_iter().chain(Some(PathBuf::from(DEFAULT_CONFIG_FILE)));
+        for path in candidates {
+            if path.exists() {
+                return Self::load(&path);
+            }
+        }
+        Ok(Self::default())
+    }
+
+    pub fn networks(&self) -> &[CanNetwork] {
+        &self.networks
+    }
+
+    pub fn network_for_channel(&self, app_channel: u32) -> Option<&CanNetwork> {
+        self.networks.iter().find(|n| n.app_channel == Some(app_channel))
+    }
+
+    /// Network name of an app channel, or `channel<N>` (1-based) when it is not mapped.
+    pub fn channel_name(&self, app_channel: u32) -> String {
+        match self.network_for_channel(app_channel) {
+            Some(network) => network.name.clone(),
+            None => format!("channel{}", app_channel + 1),
+        }
+    }
+
+    pub fn bus_params(&self, network: &str) -> CanBusParams {
+        self.networks
+            .iter()
+            .find(|n| n.name.eq_ignore_ascii_case(network))
+            .and_then(|n| n.params)
+            .unwrap_or(CanBusParams::DEFAULT_FD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_fd_timing_is_valid() {
+        let CanBusParams::Fd { arbitration, data } = CanBusParams::DEFAULT_FD else {
+            panic!("default is FD");
+        };
+        validate_timing(&arbitration, &NOMINAL_LIMITS).unwrap();
+        validate_timing(&data, &DATA_LIMITS).unwrap();
+        assert_eq!(CanNetworkConfig::default().bus_params("FD_CAN5"), CanBusParams::DEFAULT_FD);
+    }
+
+    #[test]
+    fn parses_classic_and_fd_networks() {
+        let config = CanNetworkConfig::from_toml(
+            "[[network]]\nname = \"HS_CAN1\"\nbus = \"classic\"\nbitrate = 500000\nsample_point = 0.75\n\n\
+             [[network]]\nname = \"FD_CAN5\"\nbus = \"fd\"\nbitrate = 500000\ndata_bitrate = 5000000\n\
+             data_tseg1 = 11\ndata_tseg2 = 4\ndata_sjw = 4\n",
+        )
+        .unwrap();
+
+        let CanBusParams::Classic(classic) = config.bus_params("hs_can1") else {
+            panic!("HS_CAN1 is classic");
+        };
+        assert_eq!(classic.bitrate, 500_000);
+        assert!((classic.sample_point() - 0.75).abs() < 1e-9);
+        assert_eq!(
+            config.bus_params("FD_CAN5"),
+            CanBusParams::Fd {
+                arbitration: timing_for_sample_point(500_000, 0.8, &NOMINAL_LIMITS).unwrap(),
+                data: CanBitTiming {
+                    bitrate: 5_000_000,
+                    sjw: 4,
+                    tseg1: 11,
+                    tseg2: 4,
+                },
+            }
+        );
+        assert_eq!(config.bus_params("FD_CANW"), CanBusParams::DEFAULT_FD);
+    }
+
+    #[test]
+    fn rejects_impossible_timings() {
+        let cases = [
+            // prescaler would not be an integer
+            ("bus = \"fd\"\nbitrate = 500000\ndata_bitrate = 3000000\ndata_tseg1 = 20\ndata_tseg2 = 5", "prescaler"),
+            ("bus = \"classic\"\nbitrate = 500000\nsjw = 20\ntseg1 = 63\ntseg2 = 16", "sjw 20"),
+            ("bus = \"fd\"\nbitrate = 2000000\ndata_bitrate = 4000000", "arbitration bitrate"),
+            ("bus = \"fd\"\nbitrate = 500000\ndata_bitrate = 250000", "below the arbitration"),
+            ("bus = \"fd\"\nbitrate = 500000", "data_bitrate"),
+            ("bus = \"classic\"\nbitrate = 500000\ndata_bitrate = 2000000", "data phase"),
+            ("bus = \"classic\"\nbitrate = 500000\nsample_point = 0.3", "sample point"),
+            ("bus = \"classic\"\nbitrate = 500000\ntseg1 = 63", "both tseg1 and tseg2"),
+            ("bus = \"flexray\"\nbitrate = 500000", "unknown bus"),
+            // timing fields without a bus would be ignored
+            ("sjw = 4", "need bus"),
+            ("tseg2 = 4", "need bus"),
+            ("data_sjw = 4", "need bus"),
+            ("data_tseg2 = 4", "need bus"),
+        ];
+        for (body, expected) in cases {
+            let err = CanNetworkConfig::from_toml(&format!("[[network]]\nname = \"N\"\n{}\n", body)).unwrap_err();
+            assert!(err.starts_with("network 'N': "), "{}", err);
+            assert!(err.contains(expected), "{} -> {}", body, err);
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_and_unknown_keys() {
+        let duplicate = "[[network]]\nname = \"A\"\nbus = \"classic\"\nbitrate = 500000\n\
+                         [[network]]\nname = \"a\"\nbus = \"classic\"\nbitrate = 250000\n";
+        assert!(CanNetworkConfig::from_toml(duplicate).unwrap_err().contains("configured twice"));
+        let unknown = "[[network]]\nname = \"A\"\nbus = \"classic\"\nbitrate = 500000\nbaud = 1\n";
+        assert!(CanNetworkConfig::from_toml(unknown).unwrap_err().contains("baud"));
+    }
+
+    #[test]
+    fn maps_app_channels_to_networks() {
+        let config = CanNetworkConfig::from_toml(
+            "[[network]]\nname = \"FD_CANW\"\napp_channel = 0\ndevice = \"vn 1670 1\"\nhw_serial = 12345\nhw_channel = 2\n\n\
+             [[network]]\nname = \"HS_CAN1\"\napp_channel = 10\nbus = \"classic\"\nbitrate = 500000\n",
+        )
+        .unwrap();
+
+        let fd_canw = config.network_for_channel(0).unwrap();
+        assert_eq!(fd_canw.name, "FD_CANW");
+        assert_eq!(fd_canw.hardware_label(), "vn 1670 1 (SN 12345 hwChannel 2)");
+        assert_eq!(config.bus_params("FD_CANW"), CanBusParams::DEFAULT_FD);
+        assert_eq!(config.channel_name(10), "HS_CAN1");
+        assert_eq!(config.channel_name(4), "channel5");
+        assert!(matches!(config.bus_params("HS_CAN1"), CanBusParams::Classic(_)));
+
+        let json = CanNetworkConfig::from_json(
+            r#"{"network": [{"name": "FD_CANW", "app_channel": 0, "device": "vn 1670 1", "hw_serial": 12345, "hw_channel": 2}]}"#,
+        )
+        .unwrap();
+        assert_eq!(json.network_for_channel(0).unwrap().hardware_label(), fd_canw.hardware_label());
+    }
+
+    #[test]
+    fn rejects_conflicting_mappings() {
+        let twice = "[[network]]\nname = \"A\"\napp_channel = 1\n[[network]]\nname = \"B\"\napp_channel = 1\n";
+        let err = CanNetworkConfig::from_toml(twice).unwrap_err();
+        assert_eq!(err, "network 'B': app channel 1 is already mapped to 'A'");
+        let timing_without_bus = "[[network]]\nname = \"A\"\napp_channel = 1\nbitrate = 500000\n";
+        assert!(CanNetworkConfig::from_toml(timing_without_bus).unwrap_err().contains("need bus"));
+    }
+}
@@ -0,0 +1,188 @@
+// In-process CAN capture for the GUI.
+// Runs the `--can-listen-all` capture on a worker thread inside the GUI process and hands its
+// progress to the UI as `CaptureEvent`s, so the UI can show live per-network counts and errors
+// and stop the capture cooperatively instead of killing a child process.
+
+use crate::can_capture::{listen_can_all_connected, CaptureControl, CaptureEvent};
+use crate::can_cli::{with_backend, CanCliOptions};
+use chrono::Local;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+
+pub struct CanEngine {
+    control: CaptureControl,
+    events: Receiver<CaptureEvent>,
+    worker: Option<JoinHandle<()>>,
+    /// Text log of the start, error and stop events (the former child's console log).
+    console: Option<File>,
+}
+
+impl CanEngine {
+    /// Starts a listen-all capture with the settings of a `--test-can --can-listen-all` command
+    /// line. The backend is opened on the worker thread; failures arrive as events.
+    pub fn start(options: CanCliOptions, console_log: &Path) -> Self {
+        let (sender, events) = mpsc::channel();
+        let control = CaptureControl::with_events(sender);
+        let worker_control = control.clone();
+        let worker = thread::Builder::new()
+            .name("can-capture".to_string())
+            .spawn(move || run_worker(&options, &worker_control));
+
+        let engine = CanEngine {
+            control,
+            events,
+            worker: None,
+            console: File::create(console_log).ok(),
+        };
+        match worker {
+            Ok(worker) => CanEngine {
+                worker: Some(worker),
+                ..engine
+            },
+            Err(e) => {
+                engine.control.send(CaptureEvent::Error(format!("Failed to start CAN capture thread: {}", e)));
+                engine.control.send(CaptureEvent::Stopped(None));
+                engine
+            }
+        }
+    }
+
+    /// Events received since the last call, oldest first.
+    pub fn poll(&mut self) -> Vec<CaptureEvent> {
+        let events: Vec<CaptureEvent> = self.events.try_iter().collect();
+        for event in &events {
+            self.log(event);
+        }
+        events
+    }
+
+    /// Writes `text` as a comment into every CAN file of the running capture.
+    pub fn add_comment(&self, text: &str) {
+        self.control.add_comment(text);
+    }
+
+    /// Asks the capture to stop, waits until every file is finished and returns the remaining events.
+    pub fn stop(mut self) -> Vec<CaptureEvent> {
+        self.control.request_stop();
+        if let Some(worker) = self.worker.take()
+            && worker.join().is_err()
+        {
+            self.control.send(CaptureEvent::Error("CAN capture thread panicked".to_string()));
+            self.control.send(CaptureEvent::Stopped(None));
+        }
+        self.poll()
+    }
+
+    fn log(&mut self, event: &CaptureEvent) {
+        let Some(console) = &mut self.console else {
+            return;
+        };
+        let text = match event {
+            CaptureEvent::Started { networks, skipped } => {
+                let mut text = format!("capture started on {} networks", networks.len());
+                for network in networks {
+                    text.push_str(&format!(
+                        "\n  {} (ch={}) -> {} ({})",
+                        network.network, network.channel, network.file, network.participation
+                    ));
+                }
+                for channel in skipped {
+                    text.push_str(&format!("\n  skipped ch={}: {}", channel.channel, channel.reason));
+                }
+                text
+            }
+            CaptureEvent::FrameStats(_) => return,
+            CaptureEvent::Error(e) => format!("error: {}", e),
+            CaptureEvent::Stopped(None) => "capture stopped".to_string(),
+            CaptureEvent::Stopped(Some(summary)) => {
+                let mut text = "capture stopped".to_string();
+                for network in &summary.networks {
+                    text.push_str(&format!(
+                        "\n  {} (ch={}): {} frames, {} error frames",
+                        network.network, network.channel, network.frames, network.error_frames
+                    ));
+                }
+                text
+            }
+        };
+        let _ = writeln!(console, "{} {}", Local::now().format("%Y-%m-%d %H:%M:%S%.3f"), text);
+    }
+}
+
+fn run_worker(options: &CanCliOptions, control: &CaptureControl) {
+    let result = options.load_network_config().and_then(|networks| {
+        let settings = options.port_settings(&networks)?;
+        let format = options.format()?;
+        with_backend(options, |backend| {
+            listen_can_all_connected(
+                backend,
+                options.max_channels,
+                &settings,
+                options.duration_ms,
+                options.output_dir.as_deref(),
+                format,
+                control,
+            )
+        })
+        .map_err(|(_, e)| e)?
+    });
+    match result {
+        Ok(summary) => {
+            if let Some(e) = &summary.error {
+                control.send(CaptureEvent::Error(e.clone()));
+            }
+            control.send(CaptureEvent::Stopped(Some(summary)));
+        }
+        Err(e) => {
+            control.send(CaptureEvent::Error(e));
+            control.send(CaptureEvent::Stopped(None));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn streams_events_and_stops_on_request() {
+        let dir = std::env::temp_dir().join(format!("cdl_engine_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let args: Vec<String> = format!(
+            "--test-can --can-listen-all --can-backend sim --can-max-channels 3 --can-output-dir {}",
+            dir.display()
+        )
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+        let mut engine = CanEngine::start(CanCliOptions::parse(&args), &dir.join("console.log"));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut started = None;
+        while started.is_none() && Instant::now() < deadline {
+            started = engine.poll().into_iter().find_map(|event| match event {
+                CaptureEvent::Started { networks, .. } => Some(networks),
+                _ => None,
+            });
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(started.map(|networks| networks.len()), Some(3));
+
+        engine.add_comment("CDL_MARKER #1 door opened");
+        let events = engine.stop();
+        let Some(CaptureEvent::Stopped(Some(summary))) = events.last() else {
+            panic!("{:?}", events);
+        };
+        assert_eq!(summary.error, None);
+        assert!(summary.networks.iter().any(|n| n.frames > 0));
+        for network in &summary.networks {
+            assert!(std::fs::read_to_string(&network.file).unwrap().contains(" CDL_MARKER #1 door opened"));
+        }
+        assert!(std::fs::read_to_string(dir.join("console.log")).unwrap().contains("capture stopped"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,157 @@
+// CAN hardware inventory.
+// Lists every CAN channel the driver knows (xlGetDriverConfig on Vector) with serial number,
+// transceiver, FD capability and which app channel and network currently use it. The inventory
+// is printed by `--can-inventory` and saved as can_inventory.json next to every capture.
+
+use crate::can_backend::{CanBackend, CanChannelInfo};
+use crate::can_capture::progress;
+use crate::can_config::CanNetworkConfig;
+use chrono::{Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const INVENTORY_FILE: &str = "can_inventory.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InventoryChannel {
+    #[serde(flatten)]
+    pub info: CanChannelInfo,
+    /// 0-based app channel of the capture application assigned to this channel.
+    pub app_channel: Option<u32>,
+    pub network: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CanInventory {
+    pub captured_at: String,
+    pub backend: String,
+    pub app_name: String,
+    pub channels: Vec<InventoryChannel>,
+}
+
+/// Reads the driver's channel list and joins it with the `app_name` assignments of app
+/// channels `0..max_channels`.
+pub fn collect_inventory(
+    backend: &dyn CanBackend,
+    app_name: &str,
+    max_channels: u32,
+    networks: &CanNetworkConfig,
+) -> Result<CanInventory, String> {
+    let infos = backend.channel_inventory().map_err(|e| e.to_string())?;
+    let assignments: Vec<(u32, _)> = (0..max_channels)
+        .filter_map(|app_channel| {
+            let hw = backend.app_channel_config(app_name, app_channel).ok()?;
+            (hw.hw_type != 0).then_some((app_channel, hw))
+        })
+        .collect();
+
+    let channels = infos
+        .into_iter()
+        .map(|info| {
+            let app_channel = assignments
+                .iter()
+                .find(|(_, hw)| *hw == info.hw)
+                .map(|(app_channel, _)| *app_channel);
+            let network = app_channel
+                .and_then(|ch| networks.network_for_channel(ch))
+                .map(|n| n.name.clone());
+            InventoryChannel {
+                info,
+                app_channel,
+                network,
+            }
+        })
+        .collect();
+
+    Ok(CanInventory {
+        captured_at: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+        backend: backend.name().to_string(),
+        app_name: app_name.to_string(),
+        channels,
+    })
+}
+
+/// One line per channel, as printed by `--can-inventory` and shown in the GUI.
+pub fn format_channel(channel: &InventoryChannel) -> String {
+    let info = &channel.info;
+    let assignment = match (channel.app_channel, &channel.network) {
+        (Some(app_channel), Some(network)) => format!("ch={} {}", app_channel + 1, network),
+        (Some(app_channel), None) => format!("ch={} UNMAPPED", app_channel + 1),
+        (None, _) => "unassigned".to_string(),
+    };
+    let text_or_dash = |text: &str| if text.is_empty() { "-".to_string() } else { text.to_string() };
+    let max_bitrate = if info.max_bitrate == 0 {
+        "-".to_string()
+    } else {
+        info.max_bitrate.to_string()
+    };
+    format!(
+        "{} | SN {} | article {} | hwType={} hwIndex={} hwChannel={} | transceiver {} | {} | max {} bit/s | driver {} | {} | {}",
+        info.name,
+        info.serial_number,
+        info.article_number,
+        info.hw.hw_type,
+        info.hw.hw_index,
+        info.hw.hw_channel,
+        text_or_dash(&info.transceiver),
+        if info.fd_capable { "CAN FD" } else { "classic only" },
+        max_bitrate,
+        text_or_dash(&info.driver_version),
+        if info.on_bus { "on bus" } else { "off bus" },
+        assignment
+    )
+}
+
+pub fn print_inventory(inventory: &CanInventory) {
+    progress!(
+        "CAN inventory ({} backend, assignments of app '{}'): {} channels",
+        inventory.backend,
+        inventory.app_name,
+        inventory.channels.len()
+    );
+    for channel in &inventory.channels {
+        progress!("  {}", format_channel(channel));
+    }
+}
+
+/// Writes `can_inventory.json` into `dir` and returns its path.
+pub fn save_inventory(inventory: &CanInventory, dir: &Path) -> Result<PathBuf, String> {
+    let path = dir.join(INVENTORY_FILE);
+    let json = serde_json::to_string_pretty(inventory).map_err(|e| format!("Failed to encode CAN inventory: {}", e))?;
+    fs::write(&path, json).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::can_sim::SimulatedBackend;
+
+    #[test]
+    fn joins_driver_channels_with_app_assignments() {
+        let backend =
+            SimulatedBackend::from_script("channel 0 59 0 0\nhw 59 0 1\nchannel 1 59 1 0\nserial 59 0 4242\n").unwrap();
+        let networks = CanNetworkConfig::from_toml("[[network]]\nname = \"FD_CANW\"\napp_channel = 0\n").unwrap();
+
+        let inventory = collect_inventory(&backend, "CANoe", 4, &networks).unwrap();
+
+        assert_eq!(inventory.channels.len(), 3);
+        assert_eq!(
+            format_channel(&inventory.channels[0]),
+            "SIM 59/0 Channel 1 | SN 4242 | article 0 | hwType=59 hwIndex=0 hwChannel=0 | transceiver SIM CANFD | CAN FD | max 8000000 bit/s | driver - | off bus | ch=1 FD_CANW"
+        );
+        assert!(format_channel(&inventory.channels[1]).ends_with("| unassigned"));
+        assert!(format_channel(&inventory.channels[2]).ends_with("| ch=2 UNMAPPED"));
+
+        let dir = std::env::temp_dir().join(format!("cdl_inventory_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = save_inventory(&inventory, &dir).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["channels"][0]["serial_number"], 4242);
+        assert_eq!(json["channels"][0]["hw_index"], 0);
+        assert_eq!(json["channels"][0]["network"], "FD_CANW");
+        assert_eq!(json["channels"][1]["app_channel"], serde_json::Value::Null);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,250 @@
+// Harness layout check.
+// Compares the expected location of every mapped network (hw_type/hw_index/hw_channel/hw_serial
+// in can_networks.toml) with what the driver reports for its app channel right now, so a
+// re-plugged interface cannot record one network under another network's file name unnoticed.
+
+use crate::can_backend::{CanBackend, CanChannelInfo, CanHwChannel};
+use crate::can_capture::progress;
+use crate::can_config::{CanNetwork, CanNetworkConfig};
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum LayoutState {
+    /// Assigned and, where an expected location is configured, found there.
+    Ok,
+    /// Assigned to different hardware than expected.
+    Moved,
+    /// Not assigned, or the expected hardware is not connected.
+    Missing,
+}
+
+impl LayoutState {
+    pub fn name(self) -> &'static str {
+        match self {
+            LayoutState::Ok => "OK",
+            LayoutState::Moved => "MOVED",
+            LayoutState::Missing => "MISSING",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LayoutCheck {
+    pub network: String,
+    pub app_channel: u32,
+    pub state: LayoutState,
+    pub detail: String,
+}
+
+impl LayoutCheck {
+    /// `Layout <STATE>: <network> (ch=<1-based>) <detail>`
+    pub fn report_line(&self) -> String {
+        format!(
+            "Layout {}: {} (ch={}) {}",
+            self.state.name(),
+            self.network,
+            self.app_channel + 1,
+            self.detail
+        )
+    }
+}
+
+fn describe_hw(hw: &CanHwChannel, serial: Option<u32>) -> String {
+    let mut text = format!("hwType={} hwIndex={} hwChannel={}", hw.hw_type, hw.hw_index, hw.hw_channel);
+    if let Some(serial) = serial {
+        text.push_str(&format!(" SN {}", serial));
+    }
+    text
+}
+
+fn describe_expected(network: &CanNetwork) -> String {
+    let mut parts = Vec::new();
+    if let Some(hw_type) = network.hw_type {
+        parts.push(format!("hwType={}", hw_type));
+    }
+    if let Some(hw_index) = network.hw_index {
+        parts.push(format!("hwIndex={}", hw_index));
+    }
+    if let Some(hw_channel) = network.hw_channel {
+        parts.push(format!("hwChannel={}", hw_channel));
+    }
+    if let Some(serial) = network.hw_serial {
+        parts.push(format!("SN {}", serial));
+    }
+    parts.join(" ")
+}
+
+fn check_network(
+    backend: &dyn CanBackend,
+    app_name: &str,
+    network: &CanNetwork,
+    app_channel: u32,
+    inventory: &Result<Vec<CanChannelInfo>, String>,
+) -> (LayoutState, String) {
+    let hw = match backend.app_channel_config(app_name, app_channel) {
+        Ok(hw) if hw.hw_type != 0 => hw,
+        Ok(_) => return (LayoutState::Missing, "app channel is not assigned to any hardware".to_string()),
+        Err(e) => return (LayoutState::Missing, e.to_string()),
+    };
+    if backend.channel_mask(&hw) == 0 {
+        return (LayoutState::Missing, format!("{} is not connected", describe_hw(&hw, None)));
+    }
+
+    let found_serial = inventory
+        .as_ref()
+        .ok()
+        .and_then(|channels| channels.iter().find(|c| c.hw == hw))
+        .map(|c| c.serial_number);
+    let found = describe_hw(&hw, found_serial);
+    let expected = describe_expected(network);
+    if expected.is_empty() {
+        return (LayoutState::Ok, format!("{} (no expected location configured)", found));
+    }
+
+    if let (Some(serial), Ok(channels)) = (network.hw_serial, inventory)
+        && !channels.iter().any(|c| c.serial_number == serial)
+    {
+        return (
+            LayoutState::Missing,
+            format!("interface SN {} is not connected (app channel is on {})", serial, found),
+        );
+    }
+
+    let moved = network.hw_type.is_some_and(|v| v != hw.hw_type)
+        || network.hw_index.is_some_and(|v| v != hw.hw_index)
+        || network.hw_channel.is_some_and(|v| v != hw.hw_channel)
+        || network
+            .hw_serial
+            .is_some_and(|v| found_serial.is_some_and(|found| found != v));
+    if moved {
+        return (LayoutState::Moved, format!("expected {}, found {}", expected, found));
+    }
+
+    match (network.hw_serial, inventory) {
+        (Some(_), Err(e)) => (LayoutState::Ok, format!("{} (serial not checked: {})", found, e)),
+        _ => (LayoutState::Ok, found),
+    }
+}
+
+/// Checks every network that has an app channel in `networks`.
+pub fn check_layout(backend: &dyn CanBackend, app_name: &str, networks: &CanNetworkConfig) -> Vec<LayoutCheck> {
+    let inventory = backend.channel_inventory().map_err(|e| e.to_string());
+    networks
+        .networks()
+        .iter()
+        .filter_map(|network| {
+            let app_channel = network.app_channel?;
+            let (state, detail) = check_network(backend, app_name, network, app_channel, &inventory);
+            Some(LayoutCheck {
+                network: network.name.clone(),
+                app_channel,
+                state,
+                detail,
+            })
+        })
+        .collect()
+}
+
+pub fn print_layout_report(checks: &[LayoutCheck]) {
+    if checks.is_empty() {
+        return;
+    }
+    let drifted = checks.iter().filter(|c| c.state != LayoutState::Ok).count();
+    progress!(
+        "Harness layout check: {} networks, {} OK, {} drifted",
+        checks.len(),
+        checks.len() - drifted,
+        drifted
+    );
+    for check in checks {
+        progress!("{}", check.report_line());
+    }
+}
+
+/// Runs the check before a capture (on every mapped network, or only the one on `app_channel`)
+/// and prints the report. With `refuse_on_drift` any MOVED or MISSING network is an error, so
+/// nothing is recorded under a wrong name.
+pub fn verify_layout(
+    backend: &dyn CanBackend,
+    app_name: &str,
+    networks: &CanNetworkConfig,
+    app_channel: Option<u32>,
+    refuse_on_drift: bool,
+) -> Result<(), String> {
+    let mut checks = check_layout(backend, app_name, networks);
+    if let Some(app_channel) = app_channel {
+        checks.retain(|c| c.app_channel == app_channel);
+    }
+    print_layout_report(&checks);
+    let drifted: Vec<String> = checks
+        .iter()
+        .filter(|c| c.state != LayoutState::Ok)
+        .map(|c| format!("{} {}", c.network, c.state.name()))
+        .collect();
+    if refuse_on_drift && !drifted.is_empty() {
+        return Err(format!(
+            "Harness layout drift ({}); refusing to start capture",
+            drifted.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::can_sim::SimulatedBackend;
+
+    const HARNESS: &str = "channel 0 59 0 0\nchannel 1 59 1 6\nchannel 2 59 0 2\nhw 59 1 0\n\
+                           serial 59 0 1111\nserial 59 1 2222\n";
+
+    fn states(config: &str) -> Vec<(String, LayoutState)> {
+        let backend = SimulatedBackend::from_script(HARNESS).unwrap();
+        let networks = CanNetworkConfig::from_toml(config).unwrap();
+        check_layout(&backend, "CANoe", &networks)
+            .into_iter()
+            .map(|c| (c.network, c.state))
+            .collect()
+    }
+
+    #[test]
+    fn reports_ok_moved_and_missing() {
+        let checks = states(
+            "[[network]]\nname = \"FD_CANW\"\napp_channel = 0\nhw_type = 59\nhw_index = 0\nhw_channel = 0\nhw_serial = 1111\n\
+             [[network]]\nname = \"FD_CAN9\"\napp_channel = 1\nhw_index = 1\nhw_channel = 0\n\
+             [[network]]\nname = \"FD_CAN13\"\napp_channel = 2\nhw_serial = 3333\n\
+             [[network]]\nname = \"HS_CAN1\"\napp_channel = 7\n\
+             [[network]]\nname = \"FD_CAN5\"\n",
+        );
+        assert_eq!(
+            checks,
+            vec![
+                ("FD_CANW".to_string(), LayoutState::Ok),
+                ("FD_CAN9".to_string(), LayoutState::Moved),
+                ("FD_CAN13".to_string(), LayoutState::Missing),
+                ("HS_CAN1".to_string(), LayoutState::Missing),
+            ]
+        );
+    }
+
+    #[test]
+    fn serial_mismatch_is_moved() {
+        let backend = SimulatedBackend::from_script(HARNESS).unwrap();
+        let networks = CanNetworkConfig::from_toml(
+            "[[network]]\nname = \"FD_CAN9\"\napp_channel = 1\nhw_channel = 6\nhw_serial = 1111\n",
+        )
+        .unwrap();
+        let checks = check_layout(&backend, "CANoe", &networks);
+        assert_eq!(checks[0].state, LayoutState::Moved);
+        assert_eq!(
+            checks[0].report_line(),
+            "Layout MOVED: FD_CAN9 (ch=2) expected hwChannel=6 SN 1111, found hwType=59 hwIndex=1 hwChannel=6 SN 2222"
+        );
+
+        let err = verify_layout(&backend, "CANoe", &networks, None, true).unwrap_err();
+        assert!(err.contains("FD_CAN9 MOVED"), "{}", err);
+        assert!(verify_layout(&backend, "CANoe", &networks, None, false).is_ok());
+        assert!(verify_layout(&backend, "CANoe", &networks, Some(0), true).is_ok());
+    }
+}
@@ -0,0 +1,498 @@
+// Simulated CAN backend.
+// Replays a deterministic script of frames, events and XL status codes per channel, so the
+// capture and diagnose paths can run on machines without Vector hardware or vxlapi64.dll.
+//
+// Script format (one directive per line, `#` starts a comment, numbers are decimal unless noted):
+//
+//   channel <app-ch> <hwType> <hwIndex> <hwChannel>     app channel mapping (hardware channel present)
+//   hw <hwType> <hwIndex> <hwChannel>                   hardware channel without an app mapping
+//   serial <hwType> <hwIndex> <serial>                  serial number of an interface declared above
+//   open-status <app-ch> <status>                       xlOpenPort returns <status> for this channel
+//   open-delay <app-ch> <ms>                            xlOpenPort takes <ms> for this channel
+//   no-init-access <app-ch>                             another application owns init access
+//   frame <app-ch> <time-us> <rx|tx> <id-hex>[x] <dlc> [bytes-hex...]
+//   fdframe <app-ch> <time-us> <rx|tx> <id-hex>[x] <dlc> <flags> [bytes-hex...]
+//                                                       flags: `-` or a comma list of brs,esi
+//   error <app-ch> <time-us> <description...>           error frame
+//   event <app-ch> <tag-hex> <description...>           non-frame event (chip state etc.)
+//   empty <app-ch>                                      one queue-empty poll
+//   receive-status <app-ch> <status>                    xlCanReceive fails with <status>
+//
+// A trailing `x` on the id marks a 29-bit extended identifier.
+
+use crate::can_backend::{
+    CanAccess, CanBackend, CanBusMode, CanChannelInfo, CanEvent, CanFrame, CanHwChannel, CanPort, CanStatusError,
+    XL_ERR_INVALID_ACCESS, XL_ERR_INVALID_CHAN_INDEX,
+};
+use crate::can_config::CanBusParams;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimStep {
+    Event(CanEvent),
+    QueueEmpty,
+    Status(i32),
+}
+
+#[derive(Clone, Debug)]
+pub struct SimChannel {
+    pub app_channel: Option<u32>,
+    pub hw: CanHwChannel,
+    pub open_status: i32,
+    pub open_delay_ms: u64,
+    pub init_access: bool,
+    pub serial_number: u32,
+    pub script: Vec<SimStep>,
+}
+
+impl SimChannel {
+    pub fn new(app_channel: Option<u32>, hw_type: u32, hw_index: u32, hw_channel: u32) -> Self {
+        Self {
+            app_channel,
+            hw: CanHwChannel {
+                hw_type,
+                hw_index,
+                hw_channel,
+            },
+            open_status: 0,
+            open_delay_ms: 0,
+            init_access: true,
+            serial_number: 0,
+            script: Vec::new(),
+        }
+    }
+}
+
+struct SimPort {
+    // (channel index, remaining script) for every channel in the port's access mask
+    queues: Vec<(usize, VecDeque<SimStep>)>,
+}
+
+pub struct SimulatedBackend {
+    channels: Vec<SimChannel>,
+    ports: Mutex<HashMap<u64, SimPort>>,
+    next_handle: Mutex<u64>,
+    // last output mode and bus parameters applied per channel index
+    configured: Mutex<HashMap<usize, (CanBusMode, CanBusParams)>>,
+}
+
+const DEMO_SCRIPT: &str = "\
+# Built-in demo harness: 11 mapped channels on two VN1670 boxes, one denied channel.
+channel 0 59 0 0
+channel 1 59 0 1
+channel 2 59 1 0
+channel 3 59 1 1
+channel 4 59 1 2
+channel 5 59 0 2
+channel 6 59 0 3
+channel 7 59 0 4
+channel 8 59 0 5
+channel 9 59 0 6
+channel 10 59 0 7
+serial 59 0 101234
+serial 59 1 105678
+open-status 9 111
+frame 0 1000 rx 123 8 01 02 03 04 05 06 07 08
+fdframe 0 2500 rx 18DAF110x 15 brs 00 11 22 33 44 55 66 77 88 99 AA BB CC DD EE FF 00 11 22 33 44 55 66 77 88 99 AA BB CC DD EE FF 00 11 22 33 44 55 66 77 88 99 AA BB CC DD EE FF 00 11 22 33 44 55 66 77 88 99 AA BB CC DD EE FF
+frame 1 1200 tx 7DF 8 02 01 0D 00 00 00 00 00
+event 2 0409 CHIP_STATE: ERROR_ACTIVE
+frame 2 3000 rx 1A0 4 DE AD BE EF
+error 2 3500 bit error
+frame 10 500 rx 3C0 2 00 01
+";
+
+impl SimulatedBackend {
+    pub fn new(channels: Vec<SimChannel>) -> Self {
+        Self {
+            channels,
+            ports: Mutex::new(HashMap::new()),
+            next_handle: Mutex::new(1),
+            configured: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Output mode and bus parameters last applied to the channel mapped to `app_channel`.
+    #[cfg(test)]
+    pub fn configured(&self, app_channel: u32) -> Option<(CanBusMode, CanBusParams)> {
+        let index = self.channels.iter().position(|c| c.app_channel == Some(app_channel))?;
+        self.configured.lock().unwrap().get(&index).copied()
+    }
+
+    /// Ports opened so far, including ones already closed.
+    #[cfg(test)]
+    pub fn ports_opened(&self) -> u64 {
+        *self.next_handle.lock().unwrap() - 1
+    }
+
+    pub fn demo() -> Self {
+        Self::from_script(DEMO_SCRIPT).expect("built-in demo script is valid")
+    }
+
+    pub fn from_script_file(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read simulation script '{}': {}", path, e))?;
+        Self::from_script(&text)
+    }
+
+    pub fn from_script(text: &str) -> Result<Self, String> {
+        let mut channels: Vec<SimChannel> = Vec::new();
+
+        for (line_index, raw_line) in text.lines().enumerate() {
+            let line = raw_line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let line_no = line_index + 1;
+            let err = |msg: &str| format!("Simulation script line {}: {} ('{}')", line_no, msg, line);
+
+            match parts[0] {
+                "channel" | "hw" => {
+                    let (app_channel, hw_parts) = if parts[0] == "channel" {
+                        if parts.len() != 5 {
+                            return Err(err("expected: channel <app-ch> <hwType> <hwIndex> <hwChannel>"));
+                        }
+                        (Some(parse_u32(parts[1]).ok_or_else(|| err("invalid app channel"))?), &parts[2..])
+                    } else {
+                        if parts.len() != 4 {
+                            return Err(err("expected: hw <hwType> <hwIndex> <hwChannel>"));
+                        }
+                        (None, &parts[1..])
+                    };
+                    let values: Option<Vec<u32>> = hw_parts.iter().map(|p| parse_u32(p)).collect();
+                    let values = values.ok_or_else(|| err("invalid hardware tuple"))?;
+                    if channels.len() >= 64 {
+                        return Err(err("at most 64 hardware channels are supported"));
+                    }
+                    channels.push(SimChannel::new(app_channel, values[0], values[1], values[2]));
+                }
+                "serial" => {
+                    if parts.len() != 4 {
+                        return Err(err("expected: serial <hwType> <hwIndex> <serial>"));
+                    }
+                    let values: Option<Vec<u32>> = parts[1..].iter().map(|p| parse_u32(p)).collect();
+                    let values = values.ok_or_else(|| err("invalid serial"))?;
+                    let mut found = false;
+                    for channel in channels
+                        .iter_mut()
+                        .filter(|c| c.hw.hw_type == values[0] && c.hw.hw_index == values[1])
+                    {
+                        channel.serial_number = values[2];
+                        found = true;
+                    }
+                    if !found {
+                        return Err(err("unknown interface"));
+                    }
+                }
+                "open-status" => {
+                    if parts.len() != 3 {
+                        return Err(err("expected: open-status <app-ch> <status>"));
+                    }
+                    let status = parts[2].parse::<i32>().map_err(|_| err("invalid status"))?;
+                    let index = find_app_channel(&channels, parts[1]).ok_or_else(|| err("unknown app channel"))?;
+                    channels[index].open_status = status;
+                }
+                "open-delay" => {
+                    if parts.len() != 3 {
+                        return Err(err("expected: open-delay <app-ch> <ms>"));
+                    }
+                    let delay = parts[2].parse::<u64>().map_err(|_| err("invalid delay"))?;
+                    let index = find_app_channel(&channels, parts[1]).ok_or_else(|| err("unknown app channel"))?;
+                    channels[index].open_delay_ms = delay;
+                }
+                "no-init-access" => {
+                    if parts.len() != 2 {
+                        return Err(err("expected: no-init-access <app-ch>"));
+                    }
+                    let index = find_app_channel(&channels, parts[1]).ok_or_else(|| err("unknown app channel"))?;
+                    channels[index].init_access = false;
+                }
+                "frame" | "fdframe" => {
+                    let fd = parts[0] == "fdframe";
+                    let fixed = if fd { 7 } else { 6 };
+                    if parts.len() < fixed {
+                        return Err(err("missing frame fields"));
+                    }
+                    let index = find_app_channel(&channels, parts[1]).ok_or_else(|| err("unknown app channel"))?;
+                    let time_us = parts[2].parse::<u64>().map_err(|_| err("invalid time"))?;
+                    let tx = match parts[3] {
+                        "rx" => false,
+                        "tx" => true,
+                        _ => return Err(err("direction must be rx or tx")),
+                    };
+                    let (id_text, extended) = match parts[4].strip_suffix(['x', 'X']) {
+                        Some(id) => (id, true),
+                        None => (parts[4], false),
+                    };
+                    let id = u32::from_str_radix(id_text, 16).map_err(|_| err("invalid id"))?;
+                    let dlc = parts[5].parse::<u8>().map_err(|_| err("invalid dlc"))?;
+                    let (brs, esi) = if fd {
+                        let flags = parts[6];
+                        let brs = flags.split(',').any(|f| f == "brs");
+                        let esi = flags.split(',').any(|f| f == "esi");
+                        (brs, esi)
+                    } else {
+                        (false, false)
+                    };
+                    let data: Option<Vec<u8>> = parts[fixed..]
+                        .iter()
+                        .map(|b| u8::from_str_radix(b, 16).ok())
+                        .collect();
+                    let data = data.ok_or_else(|| err("invalid data byte"))?;
+                    channels[index].script.push(SimStep::Event(CanEvent::Frame(CanFrame {
+                        channel_index: index as u16,
+                        timestamp_ns: time_us * 1_000,
+                        id,
+                        extended,
+                        tx,
+                        fd,
+                        brs,
+                        esi,
+                        remote: false,
+                        dlc,
+                        data,
+                    })));
+                }
+                "error" => {
+                    if parts.len() < 3 {
+                        return Err(err("expected: error <app-ch> <time-us> <description>"));
+                    }
+                    let index = find_app_channel(&channels, parts[1]).ok_or_else(|| err("unknown app channel"))?;
+                    let time_us = parts[2].parse::<u64>().map_err(|_| err("invalid time"))?;
+                    channels[index].script.push(SimStep::Event(CanEvent::ErrorFrame {
+                        channel_index: index as u16,
+                        timestamp_ns: time_us * 1_000,
+                        description: parts[3..].join(" "),
+                    }));
+                }
+                "event" => {
+                    if parts.len() < 3 {
+                        return Err(err("expected: event <app-ch> <tag-hex> <description>"));
+                    }
+                    let index = find_app_channel(&channels, parts[1]).ok_or_else(|| err("unknown app channel"))?;
+                    let tag = u16::from_str_radix(parts[2], 16).map_err(|_| err("invalid tag"))?;
+                    channels[index].script.push(SimStep::Event(CanEvent::Other {
+                        channel_index: index as u16,
+                        tag,
+                        description: parts[3..].join(" "),
+                    }));
+                }
+                "empty" => {
+                    let index = parts
+                        .get(1)
+                        .and_then(|p| find_app_channel(&channels, p))
+                        .ok_or_else(|| err("unknown app channel"))?;
+                    channels[index].script.push(SimStep::QueueEmpty);
+                }
+                "receive-status" => {
+                    if parts.len() != 3 {
+                        return Err(err("expected: receive-status <app-ch> <status>"));
+                    }
+                    let index = find_app_channel(&channels, parts[1]).ok_or_else(|| err("unknown app channel"))?;
+                    let status = parts[2].parse::<i32>().map_err(|_| err("invalid status"))?;
+                    channels[index].script.push(SimStep::Status(status));
+                }
+                _ => return Err(err("unknown directive")),
+            }
+        }
+
+        Ok(Self::new(channels))
+    }
+}
+
+fn parse_u32(text: &str) -> Option<u32> {
+    text.parse::<u32>().ok()
+}
+
+fn find_app_channel(channels: &[SimChannel], text: &str) -> Option<usize> {
+    let app_channel = parse_u32(text)?;
+    channels.iter().position(|c| c.app_channel == Some(app_channel))
+}
+
+/// Names of the XL status codes the simulator can produce, as xlGetErrorString reports them.
+pub fn sim_status_string(status: i32) -> &'static str {
+    match status {
+        0 => "XL_SUCCESS",
+        10 => "XL_ERR_QUEUE_IS_EMPTY",
+        11 => "XL_ERR_QUEUE_IS_FULL",
+        101 => "XL_ERR_WRONG_PARAMETER",
+        111 => "XL_ERR_INVALID_CHAN_INDEX",
+        112 => "XL_ERR_INVALID_ACCESS",
+        113 => "XL_ERR_PORT_IS_OFFLINE",
+        118 => "XL_ERR_INVALID_PORT",
+        120 => "XL_ERR_HW_NOT_READY",
+        121 => "XL_ERR_CMD_TIMEOUT",
+        129 => "XL_ERR_HW_NOT_PRESENT",
+        158 => "XL_ERR_INIT_ACCESS_MISSING",
+        201 => "XL_ERR_CANNOT_OPEN_DRIVER",
+        210 => "XL_ERR_CONNECTION_BROKEN",
+        _ => "unknown error",
+    }
+}
+
+fn sim_error(call: &'static str, status: i32) -> CanStatusError {
+    CanStatusError {
+        call,
+        status,
+        description: sim_status_string(status).to_string(),
+    }
+}
+
+impl CanBackend for SimulatedBackend {
+    fn name(&self) -> &'static str {
+        "sim"
+    }
+
+    fn app_channel_config(&self, _app_name: &str, app_channel: u32) -> Result<CanHwChannel, CanStatusError> {
+        self.channels
+            .iter()
+            .find(|c| c.app_channel == Some(app_channel))
+            .map(|c| c.hw)
+            .ok_or_else(|| sim_error("xlGetApplConfig", XL_ERR_INVALID_CHAN_INDEX))
+    }
+
+    fn channel_mask(&self, hw: &CanHwChannel) -> CanAccess {
+        self.channels
+            .iter()
+            .position(|c| c.hw == *hw)
+            .map(|index| 1u64 << index)
+            .unwrap_or(0)
+    }
+
+    fn all_channels_mask(&self) -> CanAccess {
+        (0..self.channels.len()).fold(0, |mask, index| mask | (1u64 << index))
+    }
+
+    fn channel_inventory(&self) -> Result<Vec<CanChannelInfo>, CanStatusError> {
+        Ok(self
+            .channels
+            .iter()
+            .map(|c| CanChannelInfo {
+                name: format!("SIM {}/{} Channel {}", c.hw.hw_type, c.hw.hw_index, c.hw.hw_channel + 1),
+                hw: c.hw,
+                serial_number: c.serial_number,
+                article_number: 0,
+                transceiver: "SIM CANFD".to_string(),
+                fd_capable: true,
+                max_bitrate: 8_000_000,
+                driver_version: String::new(),
+                on_bus: !c.init_access,
+            })
+            .collect())
+    }
+
+    fn open_port(
+        &self,
+        _app_name: &str,
+        access_mask: CanAccess,
+        _rx_queue_size: u32,
+        _interface_version: u32,
+    ) -> Result<CanPort, CanStatusError> {
+        let mut queues = Vec::new();
+        let mut permission_mask: CanAccess = 0;
+        for (index, channel) in self.channels.iter().enumerate() {
+            if access_mask & (1u64 << index) == 0 {
+                continue;
+            }
+            thread::sleep(Duration::from_millis(channel.open_delay_ms));
+            if channel.open_status != 0 {
+                return Err(sim_error("xlOpenPort", channel.open_status));
+            }
+            if channel.init_access {
+                permission_mask |= 1u64 << index;
+            }
+            queues.push((index, channel.script.iter().cloned().collect::<VecDeque<_>>()));
+        }
+        if queues.is_empty() {
+            return Err(sim_error("xlOpenPort", XL_ERR_INVALID_ACCESS));
+        }
+
+        let handle = {
+            let mut next = self.next_handle.lock().unwrap();
+            let handle = *next;
+            *next += 1;
+            handle
+        };
+        self.ports.lock().unwrap().insert(handle, SimPort { queues });
+
+        Ok(CanPort {
+            handle,
+            access_mask,
+            permission_mask,
+        })
+    }
+
+    fn configure_port(
+        &self,
+        port: &CanPort,
+        channel_mask: CanAccess,
+        mode: CanBusMode,
+        params: &CanBusParams,
+    ) -> Result<(), CanStatusError> {
+        if channel_mask & !(port.access_mask & port.permission_mask) != 0 {
+            return Err(sim_error("xlCanFdSetConfiguration", XL_ERR_INVALID_ACCESS));
+        }
+        let mut configured = self.configured.lock().unwrap();
+        for index in 0..self.channels.len() {
+            if channel_mask & (1u64 << index) != 0 {
+                configured.insert(index, (mode, *params));
+            }
+        }
+        Ok(())
+    }
+
+    fn activate_port(&self, port: &CanPort) -> Result<(), CanStatusError> {
+        if port.access_mask & !self.all_channels_mask() != 0 {
+            return Err(sim_error("xlActivateChannel", XL_ERR_INVALID_ACCESS));
+        }
+        Ok(())
+    }
+
+    /// Script timestamps are relative to port activation.
+    fn clock_ns(&self, _port: &CanPort) -> Result<u64, CanStatusError> {
+        Ok(0)
+    }
+
+    fn receive(&self, port: &CanPort) -> Result<Option<CanEvent>, CanStatusError> {
+        let mut ports = self.ports.lock().unwrap();
+        let sim_port = ports
+            .get_mut(&port.handle)
+            .ok_or_else(|| sim_error("xlCanReceive", 118))?;
+
+        for (_, queue) in sim_port.queues.iter_mut() {
+            match queue.pop_front() {
+                Some(SimStep::Event(event)) => return Ok(Some(event)),
+                Some(SimStep::QueueEmpty) => return Ok(None),
+                Some(SimStep::Status(status)) => return Err(sim_error("xlCanReceive", status)),
+                None => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Scripted events are always ready; an exhausted or `empty` queue waits out the timeout.
+    fn wait_for_event(&self, port: &CanPort, timeout: Duration) -> Result<bool, CanStatusError> {
+        let ready = {
+            let ports = self.ports.lock().unwrap();
+            let sim_port = ports
+                .get(&port.handle)
+                .ok_or_else(|| sim_error("xlCanReceive", 118))?;
+            sim_port
+                .queues
+                .iter()
+                .any(|(_, queue)| matches!(queue.front(), Some(SimStep::Event(_)) | Some(SimStep::Status(_))))
+        };
+        if !ready {
+            thread::sleep(timeout);
+        }
+        Ok(ready)
+    }
+
+    fn close_port(&self, port: &CanPort) {
+        self.ports.lock().unwrap().remove(&port.handle);
+    }
+}
@@ -0,0 +1,959 @@
+// Cross-domain capture session.
+// Starts and stops everything one session records - slog2info over ssh, logcat over adb,
+// dumpcap, the in-process CAN capture, the target clock samples and markers - and keeps the
+// session manifest up to date. The GUI and the headless `--capture` command both drive a
+// session through `CaptureSession`; `SessionConfig` holds the options of either.
+
+use crate::can_capture::{CanLogFormat, CaptureEvent, ChannelMapping};
+use crate::can_backend::CanBusMode;
+use crate::can_cli::{CanCliOptions, EXIT_FAILED, EXIT_OK, EXIT_USAGE};
+use crate::can_config::CanNetworkConfig;
+use crate::can_engine::CanEngine;
+use crate::can_layout::LayoutCheck;
+use crate::clock_sync::{self, ClockMonitor, DeviceTarget};
+use crate::markers::{self, Marker};
+use crate::pcapng;
+use crate::preflight;
+use crate::process_control::{self, StopOutcome};
+use crate::session::{CanSessionInfo, SessionManifest, SourceStatus};
+use crate::supervisor::{ReconnectPolicy, SourceCommand, SourceHealth, SourceState, Supervisor};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_LOGS_DIR: &str = "logs";
+pub const SOURCES: [&str; 4] = ["qnx", "android", "can", "ethernet"];
+/// Order in which the sources of a session start.
+const START_ORDER: [&str; 4] = ["qnx", "android", "ethernet", "can"];
+/// A required stream that ends this soon after it started aborts the start.
+const START_SETTLE: Duration = Duration::from_millis(500);
+#[cfg(not(test))]
+const CAN_START_TIMEOUT: Duration = Duration::from_secs(10);
+/// Short in tests, so a simulated driver that hangs times out quickly.
+#[cfg(test)]
+const CAN_START_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// CAN options of a session; the same settings as the `--can-*` flags of `--test-can`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CanSettings {
+    pub backend: String,
+    pub app_name: String,
+    pub iface_version: u32,
+    pub max_channels: u32,
+    pub log_format: String,
+    pub mode: String,
+    /// Network config file; None uses can_networks.toml next to the exe if present.
+    pub config: Option<String>,
+    pub refuse_drift: bool,
+    pub sim_script: Option<String>,
+    pub ifaces: Option<String>,
+}
+
+impl Default for CanSettings {
+    fn default() -> Self {
+        CanSettings {
+            backend: "vector".to_string(),
+            app_name: "CANoe".to_string(),
+            iface_version: 4,
+            max_channels: 64,
+            log_format: CanLogFormat::Asc.name().to_string(),
+            mode: CanBusMode::Active.name().to_string(),
+            config: None,
+            refuse_drift: false,
+            sim_script: None,
+            ifaces: None,
+        }
+    }
+}
+
+impl CanSettings {
+    /// `--test-can <command>` arguments for these settings, e.g. `--can-map` or `--can-listen-all`.
+    pub fn cli_args(&self, command: &str) -> Vec<String> {
+        let mut args: Vec<String> = [
+            "--test-can",
+            command,
+            "--can-backend",
+            &self.backend,
+            "--can-max-channels",
+            &self.max_channels.to_string(),
+            "--can-app",
+            &self.app_name,
+            "--can-iface-version",
+            &self.iface_version.to_string(),
+            "--can-log-format",
+            &self.log_format,
+            "--can-mode",
+            &self.mode,
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        for (flag, value) in [
+            ("--can-config", &self.config),
+            ("--can-sim-script", &self.sim_script),
+            ("--can-ifaces", &self.ifaces),
+        ] {
+            if let Some(value) = value {
+                args.extend([flag.to_string(), value.clone()]);
+            }
+        }
+        if self.refuse_drift {
+            args.push("--can-refuse-drift".to_string());
+        }
+        args
+    }
+
+    /// Parsed `--test-can <command>` options, writing into `output_dir` if given.
+    pub fn cli_options(&self, command: &str, output_dir: Option<&Path>) -> CanCliOptions {
+        let mut args = self.cli_args(command);
+        if let Some(dir) = output_dir {
+            args.extend(["--can-output-dir".to_string(), dir.to_string_lossy().to_string()]);
+        }
+        CanCliOptions::parse(&args)
+    }
+}
+
+/// Options of one session, from the GUI fields, a config file (TOML, or JSON by extension)
+/// and/or `--capture` flags.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Folder name under `logs_dir`; empty gives `session_<date>_<time>`.
+    pub session_name: String,
+    pub logs_dir: String,
+    pub qnx_ip: String,
+    /// Empty uses the only connected device.
+    pub android_serial: String,
+    pub interface: String,
+    /// Any of `qnx`, `android`, `can`, `ethernet`.
+    pub sources: Vec<String>,
+    /// Sources that may fail to start without aborting the session; all others are required.
+    pub optional: Vec<String>,
+    /// Headless capture only; None runs until Ctrl+C.
+    pub duration_s: Option<u64>,
+    pub qnx_marker_command: String,
+    pub can: CanSettings,
+    /// How QNX and Android streams that end during the session are reconnected.
+    pub reconnect: ReconnectPolicy,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            session_name: String::new(),
+            logs_dir: DEFAULT_LOGS_DIR.to_string(),
+            qnx_ip: String::new(),
+            android_serial: String::new(),
+            interface: String::new(),
+            sources: SOURCES.iter().map(|s| s.to_string()).collect(),
+            optional: Vec::new(),
+            duration_s: None,
+            qnx_marker_command: markers::DEFAULT_QNX_MARKER_COMMAND.to_string(),
+            can: CanSettings::default(),
+            reconnect: ReconnectPolicy::default(),
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+        let parsed = if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|e| format!("Invalid capture config '{}': {}", path.display(), e))
+    }
+
+    /// `--capture-config <file>` first, then the other flags on top of it. Not validated, so
+    /// `--serve` can start from an incomplete configuration.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut config = match args.iter().position(|a| a == "--capture-config") {
+            Some(i) => SessionConfig::load(Path::new(args.get(i + 1).ok_or("--capture-config requires a file")?))?,
+            None => SessionConfig::default(),
+        };
+
+        let mut index = 1usize;
+        while index < args.len() {
+            let flag = args[index].as_str();
+            let value = args.get(index + 1).cloned();
+            let takes_value = !matches!(flag, "--capture" | "--serve" | "--can-refuse-drift");
+            if takes_value && value.is_none() {
+                return Err(format!("{} requires a value", flag));
+            }
+            let value = value.unwrap_or_default();
+            let number = |v: &str| v.parse::<u64>().map_err(|_| format!("{} expects a number, got '{}'", flag, v));
+            match flag {
+                "--capture" | "--serve" => {}
+                "--can-refuse-drift" => config.can.refuse_drift = true,
+                "--capture-config" | "--control-port" => {}
+                "--session-name" => config.session_name = value,
+                "--logs-dir" => config.logs_dir = value,
+                "--qnx-ip" => config.qnx_ip = value,
+                "--android-serial" => config.android_serial = value,
+                "--interface" => config.interface = value,
+                "--sources" => config.sources = source_list(&value),
+                "--optional" => config.optional = source_list(&value),
+                "--duration-s" => config.duration_s = Some(number(&value)?),
+                "--qnx-marker-command" => config.qnx_marker_command = value,
+                "--can-backend" => config.can.backend = value.to_lowercase(),
+                "--can-app" => config.can.app_name = value,
+                "--can-iface-version" => config.can.iface_version = number(&value)? as u32,
+                "--can-max-channels" => config.can.max_channels = number(&value)?.clamp(1, 64) as u32,
+                "--can-log-format" => config.can.log_format = value.to_lowercase(),
+                "--can-mode" => config.can.mode = value.to_lowercase(),
+                "--can-config" => config.can.config = Some(value),
+                "--can-sim-script" => config.can.sim_script = Some(value),
+                "--can-ifaces" => config.can.ifaces = Some(value),
+                "--reconnect-attempts" => config.reconnect.max_attempts = number(&value)? as u32,
+                "--reconnect-backoff-ms" => config.reconnect.backoff_ms = number(&value)?,
+                _ => return Err(format!("Unknown capture option '{}'", flag)),
+            }
+            index += if takes_value { 2 } else { 1 };
+        }
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sources.is_empty() {
+            return Err("Select at least one log source (QNX/Android/CAN/Ethernet).".to_string());
+        }
+        if let Some(unknown) = self.sources.iter().chain(&self.optional).find(|s| !SOURCES.contains(&s.as_str())) {
+            return Err(format!("Unknown source '{}' (expected {})", unknown, SOURCES.join(", ")));
+        }
+        if self.has("qnx") && self.qnx_ip.trim().is_empty() {
+            return Err("The qnx source needs a QNX IP".to_string());
+        }
+        if self.has("ethernet") && self.interface.trim().is_empty() {
+            return Err("The ethernet source needs an interface".to_string());
+        }
+        if CanLogFormat::from_name(&self.can.log_format).is_none() {
+            return Err(format!("Unknown CAN log format '{}'", self.can.log_format));
+        }
+        if CanBusMode::from_name(&self.can.mode).is_none() {
+            return Err(format!("Unknown CAN bus mode '{}'", self.can.mode));
+        }
+        Ok(())
+    }
+
+    pub fn has(&self, source: &str) -> bool {
+        self.sources.iter().any(|s| s == source)
+    }
+
+    pub fn is_required(&self, source: &str) -> bool {
+        !self.optional.iter().any(|s| s == source)
+    }
+
+    /// QNX IP, Android serial, ethernet interface or CAN app name, as recorded in the manifest.
+    fn target(&self, source: &str) -> Option<String> {
+        let target = match source {
+            "qnx" => self.qnx_ip.trim(),
+            "android" => self.android_serial.trim(),
+            "ethernet" => self.interface.trim(),
+            _ => self.can.app_name.trim(),
+        };
+        Some(target.to_string()).filter(|t| !t.is_empty())
+    }
+
+    /// `<logs_dir>/<session name>`, with a time suffix if that folder already exists.
+    fn folder(&self) -> PathBuf {
+        let default_name = Local::now().format("session_%Y_%m_%d_%H_%M_%S").to_string();
+        let cleaned = sanitize_folder_name(self.session_name.trim());
+        let base_name = if cleaned.is_empty() { default_name } else { cleaned };
+        let folder = Path::new(&self.logs_dir).join(&base_name);
+        if folder.exists() {
+            let suffix = Local::now().format("%Y_%m_%d_%H_%M_%S").to_string();
+            return Path::new(&self.logs_dir).join(format!("{}_{}", base_name, suffix));
+        }
+        folder
+    }
+}
+
+/// `qnx, CAN` -> `["qnx", "can"]`
+fn source_list(value: &str) -> Vec<String> {
+    value.split(',').map(|s| s.trim().to_lowercase()).filter(|s| !s.is_empty()).collect()
+}
+
+pub fn sanitize_folder_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    sanitized.trim_matches('_').to_string()
+}
+
+/// How a session ended.
+pub struct StopReport {
+    pub folder: PathBuf,
+    pub manifest: SessionManifest,
+    /// Where the final manifest was written, or why it was not.
+    pub manifest_path: Result<PathBuf, String>,
+    /// Sources killed after `STOP_TIMEOUT`.
+    pub killed: Vec<String>,
+    /// The CAN events that arrived while stopping.
+    pub events: Vec<CaptureEvent>,
+    /// Final state of every source.
+    pub health: Vec<SourceHealth>,
+    pub warnings: Vec<String>,
+}
+
+pub struct CaptureSession {
+    folder: PathBuf,
+    manifest: SessionManifest,
+    /// Running child processes by source name (`qnx`, `android`, `ethernet`), and the state of
+    /// every source.
+    supervisor: Supervisor,
+    can_engine: Option<CanEngine>,
+    can_error: Option<String>,
+    /// CAN events that arrived while starting, handed out by the next `poll`.
+    pending_events: Vec<CaptureEvent>,
+    clock_monitor: Option<ClockMonitor>,
+    /// Targets markers are written to, by source name.
+    marker_targets: Vec<(&'static str, DeviceTarget)>,
+    qnx_marker_command: String,
+    markers: Vec<Marker>,
+    marker_jobs: Vec<JoinHandle<Vec<String>>>,
+    /// Problems since the last `take_warnings`, such as a manifest that could not be written.
+    warnings: Vec<String>,
+}
+
+impl CaptureSession {
+    /// Starts a session as one transaction. Every source is checked first (see `preflight`): a
+    /// required source that fails its check refuses the start before the folder is created, an
+    /// optional one is recorded as failed and skipped. The sources then start in `START_ORDER`;
+    /// if a required one fails to start, or ends within `START_SETTLE`, the ones already running
+    /// are stopped again and the folder keeps a manifest marked as aborted. `can_mapping` and
+    /// `can_layout` are recorded as found before start.
+    pub fn start(config: &SessionConfig, can_mapping: &[ChannelMapping], can_layout: &[LayoutCheck]) -> Result<Self, String> {
+        config.validate()?;
+        let checks = preflight::run(config);
+        if let Some((source, Err(e))) = checks.iter().find(|(source, result)| result.is_err() && config.is_required(source)) {
+            return Err(format!("{} pre-flight check failed: {}", source, e));
+        }
+
+        let folder = config.folder();
+        fs::create_dir_all(&folder).map_err(|e| format!("Failed to create log folder: {}", e))?;
+        if config.has("can") {
+            fs::create_dir_all(folder.join("CAN_LOGS")).map_err(|e| format!("Failed to create CAN_LOGS folder: {}", e))?;
+        }
+
+        let name = folder.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let mut session = CaptureSession {
+            manifest: SessionManifest::new(&name),
+            supervisor: Supervisor::new(&folder, config.reconnect.clone()),
+            folder,
+            can_engine: None,
+            can_error: None,
+            pending_events: Vec::new(),
+            clock_monitor: None,
+            marker_targets: Vec::new(),
+            qnx_marker_command: config.qnx_marker_command.clone(),
+            markers: Vec::new(),
+            marker_jobs: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        for source in START_ORDER.iter().filter(|source| config.has(source)) {
+            let started = match checks.iter().find(|(checked, _)| checked == source) {
+                Some((_, Err(e))) => {
+                    let detail = format!("pre-flight check failed: {}", e);
+                    session.manifest.add_source(source, config.target(source).as_deref(), &[], Err(detail.clone()));
+                    session.supervisor.record(source, SourceState::Failed, None, Some(detail));
+                    continue;
+                }
+                _ => session.start_source(config, source, can_mapping, can_layout),
+            };
+            if let Err(e) = started
+                && config.is_required(source)
+            {
+                return Err(session.roll_back(format!("{} failed to start: {}", source, e)));
+            }
+        }
+
+        // a required stream that dies right away (wrong key, device offline) aborts the start too
+        if session.supervisor.has_children() {
+            thread::sleep(START_SETTLE);
+            let events = session.poll();
+            session.pending_events = events;
+            let ended = session
+                .health()
+                .iter()
+                .find(|h| h.source != "can" && h.state != SourceState::Running && config.is_required(&h.source))
+                .map(SourceHealth::describe);
+            if let Some(ended) = ended {
+                return Err(session.roll_back(ended));
+            }
+        }
+
+        if !session.marker_targets.is_empty() {
+            session.clock_monitor = Some(ClockMonitor::start(
+                session.marker_targets.iter().map(|(_, target)| target.clone()).collect(),
+                &session.folder,
+                clock_sync::SAMPLE_INTERVAL,
+            ));
+        }
+        session.save_manifest();
+        Ok(session)
+    }
+
+    fn start_source(&mut self, config: &SessionConfig, source: &str, can_mapping: &[ChannelMapping], can_layout: &[LayoutCheck]) -> Result<(), String> {
+        match source {
+            "qnx" => {
+                let ip = config.qnx_ip.trim();
+                let args = [format!("root@{}", ip), "sh -l -c 'exec slog2info -w'".to_string()];
+                let command = SourceCommand::new("ssh", &args, Some("qnx.log"));
+                self.spawn("qnx", Some(ip), &["qnx.log"], command.clone(), Some(command))?;
+                self.marker_targets.push(("qnx", DeviceTarget::qnx(ip)));
+            }
+            "android" => {
+                let serial = config.android_serial.trim();
+                let target = Some(serial).filter(|s| !s.is_empty());
+                let device: Vec<String> = target.map(|s| vec!["-s".to_string(), s.to_string()]).unwrap_or_default();
+                let logcat = ["logcat", "-v", "threadtime"].map(String::from);
+                let command = SourceCommand::new("adb", &[device.clone(), logcat.to_vec()].concat(), Some("android.log"));
+                // a reconnect waits until the device is back, like `adb wait-for-device`
+                let wait = vec!["wait-for-device".to_string()];
+                let restart = SourceCommand::new("adb", &[device, wait, logcat.to_vec()].concat(), Some("android.log"));
+                self.spawn("android", target, &["android.log"], command, Some(restart))?;
+                self.marker_targets.push(("android", DeviceTarget::android(serial)));
+            }
+            "ethernet" => {
+                let iface = config.interface.trim();
+                let eth_path = self.folder.join("ethernet.pcapng").to_string_lossy().to_string();
+                // dumpcap writes the file itself, and a restart would overwrite it
+                let args = ["-i".to_string(), iface.to_string(), "-w".to_string(), eth_path];
+                self.spawn("ethernet", Some(iface), &["ethernet.pcapng"], SourceCommand::new("dumpcap", &args, None), None)?;
+            }
+            _ => {
+                self.can_engine = Some(CanEngine::start(
+                    config.can.cli_options("--can-listen-all", Some(&self.folder.join("CAN_LOGS"))),
+                    &self.folder.join("can_capture_console.log"),
+                ));
+                let network_config = match &config.can.config {
+                    Some(path) => Some(PathBuf::from(path)),
+                    None => CanNetworkConfig::load_default().ok().and_then(|c| c.source),
+                };
+                self.manifest.can = Some(CanSessionInfo {
+                    app_name: config.can.app_name.clone(),
+                    iface_version: config.can.iface_version,
+                    log_format: config.can.log_format.clone(),
+                    bus_mode: config.can.mode.clone(),
+                    refuse_on_drift: config.can.refuse_drift,
+                    config: network_config.map(|p| p.display().to_string()),
+                    mapping: can_mapping.to_vec(),
+                    layout: can_layout.to_vec(),
+                    ..CanSessionInfo::default()
+                });
+                // CAN counts as running once its channels are open; a capture that failed or
+                // timed out is stopped again, so an optional CAN source is not left behind
+                let started = self.wait_for_can();
+                if started.is_err()
+                    && let Some(engine) = self.can_engine.take()
+                {
+                    let events = engine.stop();
+                    self.apply_can_events(&events, SourceState::Failed);
+                    self.pending_events.extend(events);
+                }
+                self.manifest.add_source("can", Some(&config.can.app_name), &["CAN_LOGS/"], started.clone());
+                match &started {
+                    Ok(()) => self.supervisor.record("can", SourceState::Running, None, None),
+                    Err(e) => self.supervisor.record("can", SourceState::Failed, None, Some(e.clone())),
+                };
+                started?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until the CAN capture has opened its channels, or failed to.
+    fn wait_for_can(&mut self) -> Result<(), String> {
+        let deadline = Instant::now() + CAN_START_TIMEOUT;
+        while Instant::now() < deadline {
+            let events = self.poll();
+            let error = events.iter().find_map(|event| match event {
+                CaptureEvent::Error(e) => Some(e.clone()),
+                _ => None,
+            });
+            let started = events.iter().any(|event| matches!(event, CaptureEvent::Started { .. }));
+            self.pending_events.extend(events);
+            if let Some(e) = error {
+                return Err(e);
+            }
+            if started {
+                return Ok(());
+            }
+            if self.can_engine.is_none() {
+                return Err("CAN capture ended before it started".to_string());
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        Err(format!("CAN capture did not start within {} s", CAN_START_TIMEOUT.as_secs()))
+    }
+
+    /// Stops the sources started so far and finalises the manifest as aborted; returns the
+    /// error to show.
+    fn roll_back(mut self, reason: String) -> String {
+        self.manifest.aborted = Some(reason.clone());
+        let report = self.stop();
+        format!("{}; stopped the sources already started (see {})", reason, report.folder.display())
+    }
+
+    /// Starts a source process through the supervisor and records it in the manifest; its
+    /// stderr goes to `<name>_stderr.log`.
+    fn spawn(&mut self, name: &str, target: Option<&str>, files: &[&str], command: SourceCommand, restart: Option<SourceCommand>) -> Result<(), String> {
+        let stderr_file = Supervisor::stderr_file(name);
+        let started = self.supervisor.start(name, command, restart);
+        self.manifest.add_source(name, target, &[files, &[stderr_file.as_str()]].concat(), started.clone());
+        started
+    }
+
+    pub fn folder(&self) -> &Path {
+        &self.folder
+    }
+
+    pub fn manifest(&self) -> &SessionManifest {
+        &self.manifest
+    }
+
+    /// Whether `source` was started and has neither ended nor been stopped yet.
+    pub fn is_running(&self, source: &str) -> bool {
+        match source {
+            "can" => self.can_engine.is_some(),
+            _ => self.supervisor.is_running(source),
+        }
+    }
+
+    /// State of every source since its last transition.
+    pub fn health(&self) -> &[SourceHealth] {
+        self.supervisor.health()
+    }
+
+    /// Sources that did not start, with the reason.
+    pub fn failures(&self) -> Vec<String> {
+        self.manifest
+            .sources
+            .iter()
+            .filter(|s| s.status == SourceStatus::Failed)
+            .map(|s| format!("{}: {}", s.name, s.detail.as_deref().unwrap_or("failed")))
+            .collect()
+    }
+
+    /// True once every child process has exited and the CAN capture has ended by itself.
+    pub fn all_sources_ended(&self) -> bool {
+        self.can_engine.is_none() && !self.supervisor.has_children()
+    }
+
+    fn save_manifest(&mut self) {
+        if let Err(e) = self.manifest.write(&self.folder) {
+            self.warnings.push(format!("session manifest not written: {}", e));
+        }
+    }
+
+    /// Checks every source and returns the CAN events since the last call. A child process or
+    /// CAN capture that ended by itself is recorded in the manifest, reported through
+    /// `take_warnings` and released.
+    pub fn poll(&mut self) -> Vec<CaptureEvent> {
+        for health in self.supervisor.check() {
+            if let Some(source) = self.manifest.source_mut(&health.source) {
+                source.reconnects = health.reconnects;
+                let status = match health.state {
+                    SourceState::Running | SourceState::Restarting => None,
+                    SourceState::Exited | SourceState::Stopped => Some(SourceStatus::Stopped),
+                    SourceState::Failed => Some(SourceStatus::Failed),
+                };
+                if let Some(status) = status {
+                    source.status = status;
+                    source.exit_code = health.exit_code;
+                    source.detail = health.detail.clone();
+                }
+            }
+            self.warnings.push(health.describe());
+            self.save_manifest();
+        }
+        self.warnings.extend(self.supervisor.take_errors());
+
+        let Some(engine) = &mut self.can_engine else {
+            return std::mem::take(&mut self.pending_events);
+        };
+        let mut events = std::mem::take(&mut self.pending_events);
+        events.extend(engine.poll());
+        if events.iter().any(|event| matches!(event, CaptureEvent::Stopped(_)))
+            && let Some(engine) = self.can_engine.take()
+        {
+            events.extend(engine.stop());
+        }
+        self.apply_can_events(&events, SourceState::Exited);
+        events
+    }
+
+    /// `ended` is the state of a CAN capture that finishes without an error.
+    fn apply_can_events(&mut self, events: &[CaptureEvent], ended: SourceState) {
+        for event in events {
+            match event {
+                CaptureEvent::Error(e) => {
+                    self.can_error = Some(e.clone());
+                    let health = self.supervisor.record("can", SourceState::Failed, None, Some(e.clone()));
+                    self.warnings.push(health.describe());
+                }
+                CaptureEvent::Stopped(summary) => {
+                    self.manifest.finish_can(summary.as_ref(), self.can_error.as_deref());
+                    if self.can_error.is_none() {
+                        self.supervisor.record("can", ended, None, None);
+                    }
+                    self.save_manifest();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Records a marker in the markers file and writes it into every live stream: the CAN
+    /// files directly, QNX and Android over ssh/adb in the background. The pcapng comment is
+    /// added at stop, once dumpcap has closed the file.
+    pub fn add_marker(&mut self, label: &str) -> Result<Marker, String> {
+        let marker = Marker::new(self.markers.len() as u32 + 1, label);
+        markers::append_marker(&self.folder, &marker)?;
+        if let Some(engine) = &self.can_engine {
+            engine.add_comment(&marker.text());
+        }
+        let targets: Vec<DeviceTarget> = self
+            .marker_targets
+            .iter()
+            .filter(|(source, _)| self.is_running(source))
+            .map(|(_, target)| target.clone())
+            .collect();
+        if !targets.is_empty() {
+            self.marker_jobs.push(markers::inject_remote(&marker, targets, &self.qnx_marker_command));
+        }
+        self.markers.push(marker.clone());
+        Ok(marker)
+    }
+
+    /// Warnings since the last call, including the targets a marker could not be written to
+    /// once their ssh/adb call has finished.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        let (finished, running): (Vec<_>, Vec<_>) = self.marker_jobs.drain(..).partition(|job| job.is_finished());
+        self.marker_jobs = running;
+        for job in finished {
+            self.warnings.extend(job.join().unwrap_or_else(|_| vec!["marker injection panicked".to_string()]));
+        }
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Stops every source so that each file in the session folder is complete: the target clocks
+    /// get their stop sample, the CAN capture finishes its files, the child processes get an
+    /// interrupt and are killed only if they do not exit within `STOP_TIMEOUT`. Then the
+    /// markers go into the pcapng and the manifest is finalised.
+    pub fn stop(mut self) -> StopReport {
+        let mut warnings = std::mem::take(&mut self.warnings);
+        if let Some(monitor) = self.clock_monitor.take() {
+            monitor.stop();
+        }
+
+        let mut events = Vec::new();
+        if let Some(engine) = self.can_engine.take() {
+            events = engine.stop();
+            self.apply_can_events(&events, SourceState::Stopped);
+        }
+
+        let mut killed = Vec::new();
+        let timeout = process_control::STOP_TIMEOUT;
+        for (name, outcome) in process_control::stop_children(self.supervisor.take_children(), timeout) {
+            match outcome {
+                StopOutcome::Exited(code) => self.supervisor.record(&name, SourceState::Stopped, code, None),
+                StopOutcome::Killed => {
+                    killed.push(name.clone());
+                    let detail = format!("killed after {} s", timeout.as_secs());
+                    self.supervisor.record(&name, SourceState::Failed, None, Some(detail))
+                }
+            };
+            if let Some(source) = self.manifest.source_mut(&name) {
+                match outcome {
+                    StopOutcome::Exited(code) => {
+                        source.status = SourceStatus::Stopped;
+                        source.exit_code = code;
+                    }
+                    StopOutcome::Killed => source.status = SourceStatus::Killed,
+                }
+            }
+        }
+
+        // sources that were waiting to reconnect, and the reconnect counts
+        for health in self.supervisor.health() {
+            if let Some(source) = self.manifest.source_mut(&health.source) {
+                source.reconnects = health.reconnects;
+                if source.status == SourceStatus::Running && health.state == SourceState::Stopped {
+                    source.status = SourceStatus::Stopped;
+                    source.detail = health.detail.clone();
+                }
+            }
+        }
+
+        for job in self.marker_jobs.drain(..) {
+            warnings.extend(job.join().unwrap_or_default());
+        }
+        let pcapng_path = self.folder.join("ethernet.pcapng");
+        if !self.markers.is_empty() && pcapng_path.exists() {
+            let comments: Vec<_> = self
+                .markers
+                .iter()
+                .filter_map(|marker| Some((marker.host_time()?, marker.text())))
+                .collect();
+            if let Err(e) = pcapng::append_comments(&pcapng_path, &comments) {
+                warnings.push(format!("markers not added to ethernet.pcapng: {}", e));
+            }
+        }
+
+        warnings.extend(self.supervisor.take_errors());
+        self.manifest.finish();
+        let manifest_path = self.manifest.write(&self.folder);
+        StopReport {
+            folder: self.folder,
+            manifest: self.manifest,
+            manifest_path,
+            killed,
+            events,
+            health: self.supervisor.health().to_vec(),
+            warnings,
+        }
+    }
+}
+
+/// A `CaptureSession::start` running on a worker thread, so the GUI keeps drawing while the
+/// sources are checked and started; `poll` hands out the result once it arrived.
+pub struct PendingStart {
+    receiver: Receiver<Result<CaptureSession, String>>,
+}
+
+impl PendingStart {
+    pub fn spawn(config: SessionConfig, can_mapping: Vec<ChannelMapping>, can_layout: Vec<LayoutCheck>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let worker_sender = sender.clone();
+        let spawned = thread::Builder::new().name("session-start".to_string()).spawn(move || {
+            let _ = worker_sender.send(CaptureSession::start(&config, &can_mapping, &can_layout));
+        });
+        if let Err(e) = spawned {
+            let _ = sender.send(Err(format!("Failed to start session thread: {}", e)));
+        }
+        PendingStart { receiver }
+    }
+
+    /// The result of the start, once it finished.
+    pub fn poll(&self) -> Option<Result<CaptureSession, String>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("Session start thread panicked".to_string())),
+        }
+    }
+
+    /// Waits for the start to finish.
+    pub fn wait(self) -> Result<CaptureSession, String> {
+        self.receiver.recv().unwrap_or_else(|_| Err("Session start thread panicked".to_string()))
+    }
+}
+
+/// `--capture [--capture-config <file>] [options]`: records a full session without the GUI
+/// until `--duration-s` elapses, Ctrl+C is pressed or every source has ended. Prints the
+/// manifest path last; exits 0 if every source stopped cleanly, 1 if one failed or was killed.
+pub fn run_capture(args: &[String]) -> i32 {
+    let config = match SessionConfig::from_args(args).and_then(|config| config.validate().map(|_| config)) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+    process_control::install_interrupt_handler();
+    record(&config)
+}
+
+fn record(config: &SessionConfig) -> i32 {
+    let mut session = match CaptureSession::start(config, &[], &[]) {
+        Ok(session) => session,
+        Err(e) => {
+            println!("{}", e);
+            return EXIT_FAILED;
+        }
+    };
+    println!("Session folder: {}", session.folder().display());
+    for failure in session.failures() {
+        println!("Failed to start {}", failure);
+    }
+
+    let deadline = config.duration_s.map(|s| Instant::now() + Duration::from_secs(s));
+    while !process_control::interrupted() && deadline.is_none_or(|d| Instant::now() < d) {
+        for event in session.poll() {
+            if let CaptureEvent::Error(e) = event {
+                println!("CAN error: {}", e);
+            }
+        }
+        for warning in session.take_warnings() {
+            println!("{}", warning);
+        }
+        if session.all_sources_ended() {
+            println!("Every source has ended");
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let report = session.stop();
+    for line in report.manifest.summary_lines().iter().chain(&report.warnings) {
+        println!("{}", line);
+    }
+    let failed = report
+        .manifest
+        .sources
+        .iter()
+        .any(|s| matches!(s.status, SourceStatus::Failed | SourceStatus::Killed));
+    match report.manifest_path {
+        Ok(path) => {
+            println!("Session manifest: {}", path.display());
+            if failed { EXIT_FAILED } else { EXIT_OK }
+        }
+        Err(e) => {
+            println!("{}", e);
+            EXIT_FAILED
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        std::iter::once("cross_domain_logger").chain(line.split_whitespace()).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn config_file_and_flags_combine() {
+        let dir = std::env::temp_dir().join(format!("cdl_capture_config_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("rig.toml");
+        fs::write(
+            &file,
+            "session_name = \"hil\"\nqnx_ip = \"192.168.164.115\"\nsources = [\"qnx\", \"can\"]\nduration_s = 600\n\n[can]\nmode = \"passive\"\nlog_format = \"blf\"\n",
+        )
+        .unwrap();
+
+        let config = SessionConfig::from_args(&args(&format!(
+            "--capture --capture-config {} --duration-s 30 --can-log-format asc",
+            file.display()
+        )))
+        .unwrap();
+        assert_eq!(config.session_name, "hil");
+        assert_eq!(config.sources, ["qnx", "can"]);
+        assert_eq!(config.duration_s, Some(30));
+        assert_eq!((config.can.mode.as_str(), config.can.log_format.as_str()), ("passive", "asc"));
+
+        let invalid = |line: &str| SessionConfig::from_args(&args(line)).unwrap().validate().unwrap_err();
+        assert!(invalid("--capture --sources qnx").contains("QNX IP"));
+        assert!(invalid("--capture --sources can,lin").contains("'lin'"));
+        assert!(SessionConfig::from_args(&args("--capture --bogus 1")).unwrap_err().contains("--bogus"));
+        fs::write(&file, "sources = [\"can\"]\ncan_mode = \"passive\"\n").unwrap();
+        assert!(SessionConfig::load(&file).unwrap_err().contains("can_mode"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn headless_capture_writes_a_finished_session() {
+        let dir = std::env::temp_dir().join(format!("cdl_capture_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = SessionConfig::from_args(&args(&format!(
+            "--capture --sources can --can-backend sim --can-max-channels 2 --logs-dir {} --session-name bench/run --duration-s 1",
+            dir.display()
+        )))
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(record(&config), EXIT_OK);
+
+        let manifest = SessionManifest::read(&dir.join("bench_run")).unwrap();
+        assert!(manifest.stopped_at.is_some());
+        assert_eq!(manifest.sources.len(), 1);
+        assert_eq!(manifest.sources[0].status, SourceStatus::Stopped);
+        assert_eq!(manifest.can.unwrap().captured.len(), 2);
+        let asc_files = fs::read_dir(dir.join("bench_run").join("CAN_LOGS"))
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|e| e == "asc"))
+            .count();
+        assert_eq!(asc_files, 2);
+        let events = fs::read_to_string(dir.join("bench_run").join(crate::supervisor::SOURCE_EVENTS_FILE)).unwrap();
+        assert_eq!(events.lines().count(), 2, "running, then stopped");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn start_rolls_back_when_a_required_source_fails() {
+        let dir = std::env::temp_dir().join(format!("cdl_capture_rollback_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("broken.sim");
+        fs::write(&script, "channel 0 59 0 0\nopen-status 0 111\n").unwrap();
+        let config = |line: &str| {
+            let line = format!("--capture --can-backend sim --can-max-channels 2 --logs-dir {} --interface cdl-no-such-if0 {}", dir.display(), line);
+            SessionConfig::from_args(&args(&line)).unwrap()
+        };
+
+        // a required source failing its pre-flight check refuses the start before the folder exists
+        let error = CaptureSession::start(&config("--sources can,ethernet --session-name refused"), &[], &[]).err().unwrap();
+        assert!(error.starts_with("ethernet pre-flight check failed"), "{}", error);
+        assert!(!dir.join("refused").exists());
+
+        // an optional one is skipped and recorded as failed
+        let session = CaptureSession::start(&config("--sources can,ethernet --optional ethernet --session-name optional"), &[], &[]).unwrap();
+        assert!(session.is_running("can"));
+        assert_eq!(session.failures().len(), 1);
+        assert!(session.failures()[0].starts_with("ethernet: pre-flight check failed"));
+        session.stop();
+
+        // a required source failing to start stops the session and marks its folder
+        let line = format!("--sources can --can-sim-script {} --session-name aborted", script.display());
+        let error = CaptureSession::start(&config(&line), &[], &[]).err().unwrap();
+        assert!(error.starts_with("can failed to start"), "{}", error);
+        let manifest = SessionManifest::read(&dir.join("aborted")).unwrap();
+        assert!(manifest.aborted.unwrap().starts_with("can failed to start"));
+        assert!(manifest.stopped_at.is_some());
+        assert_eq!(manifest.sources[0].status, SourceStatus::Failed);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn optional_can_that_does_not_start_is_stopped() {
+        let dir = std::env::temp_dir().join(format!("cdl_capture_can_timeout_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("hanging.sim");
+        fs::write(&script, "channel 0 59 0 0\nopen-delay 0 2000\n").unwrap();
+        let line = format!(
+            "--capture --sources can --optional can --can-backend sim --can-sim-script {} --logs-dir {} --session-name slow",
+            script.display(),
+            dir.display()
+        );
+
+        let session = CaptureSession::start(&SessionConfig::from_args(&args(&line)).unwrap(), &[], &[]).unwrap();
+        assert!(!session.is_running("can"));
+        assert_eq!(session.failures(), ["can: CAN capture did not start within 1 s"]);
+        let health = session.health().iter().find(|h| h.source == "can").unwrap();
+        assert_eq!(health.state, SourceState::Failed);
+        let manifest = SessionManifest::read(&dir.join("slow")).unwrap();
+        assert_eq!(manifest.sources[0].status, SourceStatus::Failed);
+        session.stop();
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,414 @@
+// Device clock offset measurement.
+// slog2info and logcat print the target's wall clock, which drifts and is often wrong after
+// boot. While a session runs, the host asks each target for its time over ssh/adb at start,
+// every SAMPLE_INTERVAL and at stop, and appends the samples to clock_samples.jsonl in the
+// session folder. `--merge` fits a drift model to them to put target records on host time.
+//
+// A query keeps one ssh/adb shell open: the target prints `ready`, then answers every line the
+// host sends with its `date`. Only the answer round trip is timed (not the connection setup);
+// the host time is the midpoint of the round trip and the best of PROBES_PER_SAMPLE answers is kept.
+
+use crate::timeline::Domain;
+use chrono::{DateTime, Local, NaiveDateTime, SecondsFormat, TimeDelta, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const CLOCK_SAMPLES_FILE: &str = "clock_samples.jsonl";
+/// Time between samples while a session runs.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+/// Connection plus all answers of one sample.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+const PROBES_PER_SAMPLE: usize = 5;
+
+/// Runs on the target: wall clock with nanoseconds where `date` supports `%N`.
+const TARGET_SCRIPT: &str = "echo ready; while read x; do date '+%Y-%m-%d %H:%M:%S.%N'; done";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplePhase {
+    Start,
+    Periodic,
+    Stop,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClockSample {
+    pub domain: Domain,
+    /// QNX IP or Android serial.
+    pub target: String,
+    pub phase: SamplePhase,
+    /// Host time at the middle of the round trip.
+    pub host_time: String,
+    /// Target wall clock as printed by `date`; None if the query failed.
+    pub device_time: Option<String>,
+    /// Host minus target, in milliseconds.
+    pub offset_ms: Option<f64>,
+    pub round_trip_ms: Option<f64>,
+    /// 1000 when the target `date` has no `%N` and only prints whole seconds.
+    pub resolution_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// A target reachable over ssh or adb; `program args <script>` runs a shell script on it.
+#[derive(Clone, Debug)]
+pub struct DeviceTarget {
+    pub domain: Domain,
+    pub name: String,
+    program: String,
+    args: Vec<String>,
+}
+
+impl DeviceTarget {
+    pub fn qnx(ip: &str) -> Self {
+        DeviceTarget {
+            domain: Domain::Qnx,
+            name: ip.to_string(),
+            program: "ssh".to_string(),
+            args: vec![
+                "-o".to_string(),
+                "BatchMode=yes".to_string(),
+                "-o".to_string(),
+                "ConnectTimeout=5".to_string(),
+                format!("root@{}", ip),
+            ],
+        }
+    }
+
+    /// `serial` empty means the only connected device.
+    pub fn android(serial: &str) -> Self {
+        let mut args = Vec::new();
+        if !serial.is_empty() {
+            args.extend(["-s".to_string(), serial.to_string()]);
+        }
+        args.push("shell".to_string());
+        DeviceTarget {
+            domain: Domain::Android,
+            name: if serial.is_empty() { "default".to_string() } else { serial.to_string() },
+            program: "adb".to_string(),
+            args,
+        }
+    }
+
+    /// Command that runs `script` in a shell on the target.
+    pub fn command(&self, script: &str) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).arg(script);
+        command
+    }
+
+    /// Takes one sample; a failed query gives a sample with `error` set.
+    pub fn sample(&self, phase: SamplePhase) -> ClockSample {
+        let mut sample = ClockSample {
+            domain: self.domain,
+            target: self.name.clone(),
+            phase,
+            host_time: Local::now().to_rfc3339_opts(SecondsFormat::Micros, false),
+            device_time: None,
+            offset_ms: None,
+            round_trip_ms: None,
+            resolution_ms: None,
+            error: None,
+        };
+        match self.query() {
+            Ok(probe) => {
+                sample.host_time = probe.host_time.to_rfc3339_opts(SecondsFormat::Micros, false);
+                sample.offset_ms = Some(probe.offset_ms);
+                sample.round_trip_ms = Some(probe.round_trip_ms);
+                sample.resolution_ms = Some(probe.resolution_ms);
+                sample.device_time = Some(probe.device_time);
+            }
+            Err(e) => sample.error = Some(e),
+        }
+        sample
+    }
+
+    fn query(&self) -> Result<Probe, String> {
+        let mut child = self
+            .command(TARGET_SCRIPT)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Failed to start {}: {}", self.program, e))?;
+        let mut stdin = child.stdin.take().ok_or("no stdin")?;
+        let stdout = child.stdout.take().ok_or("no stdout")?;
+
+        // Reads happen on a thread so a target that stops answering cannot block the caller.
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let deadline = Instant::now() + QUERY_TIMEOUT;
+        let next_line = || lines.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        let result = (|| {
+            while next_line().map_err(|_| "no answer from target".to_string())?.trim() != "ready" {}
+            let mut best: Option<Probe> = None;
+            for _ in 0..PROBES_PER_SAMPLE {
+                let sent = Local::now();
+                stdin.write_all(b"\n").and_then(|_| stdin.flush()).map_err(|e| format!("write failed: {}", e))?;
+                let answer = next_line().map_err(|_| "no answer from target".to_string())?;
+                let received = Local::now();
+                let probe = Probe::new(sent, received, answer.trim())?;
+                if best.as_ref().is_none_or(|b| probe.round_trip_ms < b.round_trip_ms) {
+                    best = Some(probe);
+                }
+            }
+            best.ok_or_else(|| "no answer from target".to_string())
+        })();
+
+        drop(stdin);
+        let wait_until = Instant::now() + Duration::from_secs(1);
+        while matches!(child.try_wait(), Ok(None)) && Instant::now() < wait_until {
+            thread::sleep(Duration::from_millis(20));
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+        result
+    }
+}
+
+struct Probe {
+    host_time: DateTime<Local>,
+    device_time: String,
+    offset_ms: f64,
+    round_trip_ms: f64,
+    resolution_ms: f64,
+}
+
+impl Probe {
+    fn new(sent: DateTime<Local>, received: DateTime<Local>, answer: &str) -> Result<Self, String> {
+        let (device, resolution_ms) = parse_device_time(answer)?;
+        let round_trip = received - sent;
+        let host_time = sent + round_trip / 2;
+        Ok(Probe {
+            host_time,
+            device_time: answer.to_string(),
+            offset_ms: millis(host_time - device),
+            round_trip_ms: millis(round_trip),
+            resolution_ms,
+        })
+    }
+}
+
+fn millis(delta: TimeDelta) -> f64 {
+    delta.num_microseconds().unwrap_or(i64::MAX) as f64 / 1000.0
+}
+
+/// `date` output read as host local time, like the log timestamps in `timeline`. Without `%N`
+/// support the target prints whole seconds (`.%N` or `.N`); the middle of that second is used.
+fn parse_device_time(answer: &str) -> Result<(DateTime<Local>, f64), String> {
+    let (seconds, fraction) = answer.split_once('.').unwrap_or((answer, ""));
+    let whole = NaiveDateTime::parse_from_str(seconds, "%Y-%m-%d %H:%M:%S")
+        .map_err(|_| format!("unexpected date output '{}'", answer))?;
+    let whole = Local
+        .from_local_datetime(&whole)
+        .earliest()
+        .ok_or_else(|| format!("unexpected date output '{}'", answer))?;
+    if !fraction.is_empty() && fraction.bytes().all(|b| b.is_ascii_digit()) {
+        let nanos: i64 = format!("{:0<9}", &fraction[..fraction.len().min(9)]).parse().unwrap_or(0);
+        Ok((whole + TimeDelta::nanoseconds(nanos), 0.001))
+    } else {
+        Ok((whole + TimeDelta::milliseconds(500), 1000.0))
+    }
+}
+
+/// Appends samples to the session's clock_samples.jsonl.
+pub fn append_samples(folder: &Path, samples: &[ClockSample]) -> Result<(), String> {
+    let path = folder.join(CLOCK_SAMPLES_FILE);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    for sample in samples {
+        let line = serde_json::to_string(sample).map_err(|e| format!("Failed to encode clock sample: {}", e))?;
+        writeln!(file, "{}", line).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Samples of a session folder; a missing file gives no samples.
+pub fn read_samples(folder: &Path) -> Result<Vec<ClockSample>, String> {
+    let path = folder.join(CLOCK_SAMPLES_FILE);
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| format!("Invalid clock sample in '{}': {}", path.display(), e)))
+        .collect()
+}
+
+/// Host minus target clock as a straight line over host time: the offset at `reference` plus
+/// a constant drift.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClockModel {
+    pub reference: DateTime<Local>,
+    pub offset_ms: f64,
+    /// Milliseconds per second, i.e. thousands of ppm.
+    pub drift_ms_per_s: f64,
+}
+
+impl ClockModel {
+    pub fn constant(offset: TimeDelta) -> Self {
+        ClockModel {
+            reference: Local::now(),
+            offset_ms: millis(offset),
+            drift_ms_per_s: 0.0,
+        }
+    }
+
+    /// Least-squares fit to the successful samples of `domain`; None if there are none.
+    pub fn fit(samples: &[ClockSample], domain: Domain) -> Option<Self> {
+        let points: Vec<(DateTime<Local>, f64)> = samples
+            .iter()
+            .filter(|s| s.domain == domain)
+            .filter_map(|s| {
+                let time = DateTime::parse_from_rfc3339(&s.host_time).ok()?.with_timezone(&Local);
+                Some((time, s.offset_ms?))
+            })
+            .collect();
+        let reference = points.first()?.0;
+        let xs: Vec<f64> = points.iter().map(|(t, _)| millis(*t - reference) / 1000.0).collect();
+        let n = points.len() as f64;
+        let mean_x = xs.iter().sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let variance: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+        let drift_ms_per_s = if variance > 1e-6 {
+            xs.iter().zip(&points).map(|(x, (_, y))| (x - mean_x) * (y - mean_y)).sum::<f64>() / variance
+        } else {
+            0.0
+        };
+        Some(ClockModel {
+            reference,
+            offset_ms: mean_y - drift_ms_per_s * mean_x,
+            drift_ms_per_s,
+        })
+    }
+
+    pub fn offset_at(&self, time: DateTime<Local>) -> TimeDelta {
+        let seconds = millis(time - self.reference) / 1000.0;
+        TimeDelta::microseconds(((self.offset_ms + self.drift_ms_per_s * seconds) * 1000.0).round() as i64)
+    }
+
+    pub fn describe(&self) -> String {
+        format!("offset {:+.3} ms, drift {:+.1} ppm", self.offset_ms, self.drift_ms_per_s * 1000.0)
+    }
+}
+
+/// Samples the targets of a running session: once at start, every `interval` and once at stop.
+pub struct ClockMonitor {
+    stop: Sender<()>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl ClockMonitor {
+    pub fn start(targets: Vec<DeviceTarget>, folder: &Path, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let folder = folder.to_path_buf();
+        let worker = thread::Builder::new()
+            .name("clock-sync".to_string())
+            .spawn(move || {
+                let record = |phase| {
+                    let samples: Vec<ClockSample> = targets.iter().map(|t| t.sample(phase)).collect();
+                    let _ = append_samples(&folder, &samples);
+                };
+                record(SamplePhase::Start);
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    record(SamplePhase::Periodic);
+                }
+                record(SamplePhase::Stop);
+            })
+            .ok();
+        ClockMonitor { stop, worker }
+    }
+
+    /// Takes the stop samples and waits for them to be written.
+    pub fn stop(mut self) {
+        let _ = self.stop.send(());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_a_target_and_fits_drift() {
+        let dir = std::env::temp_dir().join(format!("cdl_clock_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // The host itself as target: offset close to zero.
+        let local = DeviceTarget {
+            domain: Domain::Qnx,
+            name: "localhost".to_string(),
+            program: "sh".to_string(),
+            args: vec!["-c".to_string()],
+        };
+        let unreachable = DeviceTarget {
+            domain: Domain::Android,
+            name: "missing".to_string(),
+            program: "cdl-no-such-program".to_string(),
+            args: Vec::new(),
+        };
+        ClockMonitor::start(vec![local, unreachable], &dir, Duration::from_secs(3600)).stop();
+
+        let samples = read_samples(&dir).unwrap();
+        let phases: Vec<(Domain, SamplePhase)> = samples.iter().map(|s| (s.domain, s.phase)).collect();
+        assert_eq!(
+            phases,
+            [
+                (Domain::Qnx, SamplePhase::Start),
+                (Domain::Android, SamplePhase::Start),
+                (Domain::Qnx, SamplePhase::Stop),
+                (Domain::Android, SamplePhase::Stop)
+            ]
+        );
+        let offset = samples[0].offset_ms.unwrap();
+        assert!(offset.abs() < samples[0].resolution_ms.unwrap() + samples[0].round_trip_ms.unwrap() + 50.0, "{:?}", samples[0]);
+        assert!(samples[1].error.as_deref().unwrap().contains("Failed to start"));
+        assert!(ClockModel::fit(&samples, Domain::Android).is_none());
+
+        // Target 2 s behind at the start, losing 100 ms per 1000 s (100 ppm).
+        let start = Local.with_ymd_and_hms(2024, 5, 2, 10, 0, 0).unwrap();
+        let synthetic: Vec<ClockSample> = [(0, 2000.0), (500, 2050.0), (1000, 2100.0)]
+            .into_iter()
+            .map(|(s, offset)| ClockSample {
+                host_time: (start + TimeDelta::seconds(s)).to_rfc3339(),
+                offset_ms: Some(offset),
+                ..samples[0].clone()
+            })
+            .collect();
+        let model = ClockModel::fit(&synthetic, Domain::Qnx).unwrap();
+        assert!((model.drift_ms_per_s * 1000.0 - 100.0).abs() < 1e-6, "{}", model.describe());
+        assert_eq!(model.offset_at(start + TimeDelta::seconds(2000)), TimeDelta::milliseconds(2200));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reads_date_output_with_and_without_nanoseconds() {
+        let (time, resolution) = parse_device_time("2024-05-02 10:00:01.250000000").unwrap();
+        assert_eq!(time, Local.with_ymd_and_hms(2024, 5, 2, 10, 0, 1).unwrap() + TimeDelta::milliseconds(250));
+        assert_eq!(resolution, 0.001);
+        let (time, resolution) = parse_device_time("2024-05-02 10:00:01.%N").unwrap();
+        assert_eq!(time, Local.with_ymd_and_hms(2024, 5, 2, 10, 0, 1).unwrap() + TimeDelta::milliseconds(500));
+        assert_eq!(resolution, 1000.0);
+        assert!(parse_device_time("date: bad format").is_err());
+    }
+}
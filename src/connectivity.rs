@@ -0,0 +1,165 @@
+// Background connectivity checks for the GUI indicators.
+// ssh waits up to its connect timeout and adb or the CAN driver can take seconds, so each
+// source is checked on its own worker thread and the window keeps drawing. Results come back over
+// a channel as they finish, each failure with the reason the indicator is red. The CAN hardware
+// inventory scan runs the same way.
+
+use crate::can_cli::{map_report, with_backend, MapReport};
+use crate::can_inventory::{self, CanInventory};
+use crate::capture_session::CanSettings;
+use crate::preflight;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval of the optional periodic re-check.
+pub const RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What to check, copied from the GUI fields so the workers own their inputs.
+#[derive(Clone, Debug, Default)]
+pub struct CheckRequest {
+    pub qnx_ip: String,
+    pub android_serial: String,
+    pub interface: String,
+    /// Backend, app and network config the session would capture CAN with.
+    pub can: CanSettings,
+}
+
+pub enum CheckResult {
+    Qnx(Result<(), String>),
+    Android(Result<(), String>),
+    Ethernet(Result<(), String>),
+    Can(Result<MapReport, String>),
+    Inventory(Result<CanInventory, String>),
+}
+
+impl CheckResult {
+    pub fn source(&self) -> &'static str {
+        match self {
+            CheckResult::Qnx(_) => "qnx",
+            CheckResult::Android(_) => "android",
+            CheckResult::Ethernet(_) => "ethernet",
+            CheckResult::Can(_) => "can",
+            CheckResult::Inventory(_) => "inventory",
+        }
+    }
+}
+
+/// Runs checks on worker threads; `poll` hands out the results that have arrived.
+pub struct ConnectivityChecker {
+    sender: Sender<CheckResult>,
+    receiver: Receiver<CheckResult>,
+    checking: Vec<&'static str>,
+    last_started: Option<Instant>,
+}
+
+impl ConnectivityChecker {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        ConnectivityChecker { sender, receiver, checking: Vec::new(), last_started: None }
+    }
+
+    /// Starts a worker checking `source`, unless one is still running for it.
+    pub fn start(&mut self, source: &'static str, request: &CheckRequest) {
+        if self.is_checking(source) {
+            return;
+        }
+        self.checking.push(source);
+        self.last_started = Some(Instant::now());
+        let request = request.clone();
+        let sender = self.sender.clone();
+        let spawned = thread::Builder::new().name(format!("check-{}", source)).spawn(move || {
+            let result = match source {
+                "qnx" if request.qnx_ip.trim().is_empty() => CheckResult::Qnx(Ok(())),
+                "qnx" => CheckResult::Qnx(preflight::check_qnx(request.qnx_ip.trim())),
+                "android" => CheckResult::Android(preflight::check_android(request.android_serial.trim())),
+                "ethernet" => CheckResult::Ethernet(preflight::check_interface(request.interface.trim())),
+                "inventory" => CheckResult::Inventory(check_inventory(&request.can)),
+                _ => CheckResult::Can(check_can(&request.can)),
+            };
+            let _ = sender.send(result);
+        });
+        if spawned.is_err() {
+            self.checking.retain(|s| *s != source);
+        }
+    }
+
+    /// Results that arrived since the last call.
+    pub fn poll(&mut self) -> Vec<CheckResult> {
+        let results: Vec<CheckResult> = self.receiver.try_iter().collect();
+        for result in &results {
+            self.checking.retain(|s| *s != result.source());
+        }
+        results
+    }
+
+    pub fn is_checking(&self, source: &str) -> bool {
+        self.checking.contains(&source)
+    }
+
+    /// Nothing is being checked and the last check started at least `interval` ago.
+    pub fn is_due(&self, interval: Duration) -> bool {
+        self.checking.is_empty() && self.last_started.is_none_or(|started| started.elapsed() >= interval)
+    }
+}
+
+/// The `--can-map` report for `settings`, read in process on the backend the session captures
+/// with, as the capture itself does (`CanEngine`).
+pub fn check_can(settings: &CanSettings) -> Result<MapReport, String> {
+    let options = settings.cli_options("--can-map", None);
+    let networks = options.load_network_config()?;
+    with_backend(&options, |backend| map_report(backend, &options, &networks)).map_err(|(_, e)| e)?
+}
+
+/// The `--can-inventory` result for `settings`: every channel the driver reports.
+pub fn check_inventory(settings: &CanSettings) -> Result<CanInventory, String> {
+    let options = settings.cli_options("--can-inventory", None);
+    let networks = options.load_network_config()?;
+    with_backend(&options, |backend| {
+        can_inventory::collect_inventory(backend, &options.app_name, options.max_channels, &networks)
+    })
+    .map_err(|(_, e)| e)?
+    .map_err(|e| format!("CAN inventory error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_arrive_without_blocking() {
+        let mut checker = ConnectivityChecker::new();
+        assert!(checker.is_due(RECHECK_INTERVAL));
+        let request = CheckRequest {
+            interface: "cdl-no-such-if0".into(),
+            can: CanSettings { backend: "sim".into(), ..CanSettings::default() },
+            ..CheckRequest::default()
+        };
+        checker.start("qnx", &request);
+        checker.start("ethernet", &request);
+        checker.start("ethernet", &request);
+        checker.start("inventory", &request);
+        assert!(!checker.is_due(Duration::ZERO));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut results = Vec::new();
+        while results.len() < 3 && Instant::now() < deadline {
+            results.extend(checker.poll());
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut sources: Vec<&str> = results.iter().map(CheckResult::source).collect();
+        sources.sort();
+        assert_eq!(sources, ["ethernet", "inventory", "qnx"], "one worker per source");
+        for result in results {
+            match result {
+                CheckResult::Qnx(result) => assert!(result.is_ok(), "no IP, nothing to check"),
+                CheckResult::Ethernet(result) => assert!(!result.unwrap_err().is_empty()),
+                CheckResult::Inventory(result) => assert!(!result.unwrap().channels.is_empty()),
+                _ => unreachable!(),
+            }
+        }
+        assert!(!checker.is_checking("ethernet"));
+        assert!(!checker.is_due(RECHECK_INTERVAL));
+        assert!(checker.is_due(Duration::ZERO));
+    }
+}
@@ -0,0 +1,390 @@
+// Local control API.
+// Lets test automation drive the logger over TCP on localhost: one JSON command per line in,
+// one JSON response per line out. The connection threads only parse and forward; every command
+// runs on the thread that owns the session (the GUI's update loop or the headless `--serve`
+// loop) through `SessionHost`, so the API starts and stops sessions exactly like the buttons.
+//
+//   {"cmd":"configure","config":{"sources":["can"],"can":{"log_format":"blf"}}}
+//   {"cmd":"start"}
+//   {"cmd":"mark","label":"door opened"}
+//   {"cmd":"status"}
+//   {"cmd":"stop"}
+
+use crate::capture_session::{CaptureSession, SessionConfig};
+use crate::can_cli::{EXIT_FAILED, EXIT_OK, EXIT_USAGE};
+use crate::markers::Marker;
+use crate::process_control;
+use crate::session::SourceRecord;
+use crate::supervisor::SourceHealth;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+pub const DEFAULT_CONTROL_PORT: u16 = 47800;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ControlCommand {
+    /// Replaces the session options; only while no session runs.
+    Configure { config: Box<SessionConfig> },
+    Start,
+    Mark {
+        #[serde(default)]
+        label: String,
+    },
+    Status,
+    Stop,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Status line of the logger, as shown in the GUI.
+    pub status: String,
+    pub running: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    /// Sources of the running session, as recorded in its manifest.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<SourceRecord>,
+    /// State of each source of the running session since its last transition.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub health: Vec<SourceHealth>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marker: Option<Marker>,
+    /// Manifest of the session a `stop` finished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<String>,
+}
+
+/// What the control commands act on.
+pub trait SessionHost {
+    fn configure(&mut self, config: SessionConfig) -> Result<(), String>;
+    fn start(&mut self) -> Result<(), String>;
+    fn mark(&mut self, label: &str) -> Result<Marker, String>;
+    /// Stops the running session; returns the path of its manifest.
+    fn stop(&mut self) -> Result<PathBuf, String>;
+    fn session(&self) -> Option<&CaptureSession>;
+    fn status(&self) -> String;
+}
+
+pub fn handle(host: &mut impl SessionHost, command: ControlCommand) -> ControlResponse {
+    let result = match command {
+        ControlCommand::Configure { config } => host.configure(*config).map(|_| ControlResponse::default()),
+        ControlCommand::Start => host.start().map(|_| ControlResponse::default()),
+        ControlCommand::Mark { label } => host.mark(&label).map(|marker| ControlResponse {
+            marker: Some(marker),
+            ..ControlResponse::default()
+        }),
+        ControlCommand::Status => Ok(ControlResponse::default()),
+        ControlCommand::Stop => host.stop().map(|path| ControlResponse {
+            manifest: Some(path.display().to_string()),
+            ..ControlResponse::default()
+        }),
+    };
+    let mut response = result.unwrap_or_else(|e| ControlResponse {
+        error: Some(e),
+        ..ControlResponse::default()
+    });
+    response.ok = response.error.is_none();
+    response.status = host.status();
+    if let Some(session) = host.session() {
+        response.running = true;
+        response.folder = Some(session.folder().display().to_string());
+        response.sources = session.manifest().sources.clone();
+        response.health = session.health().to_vec();
+    }
+    response
+}
+
+/// A command waiting for the session thread; the connection blocks until it is answered.
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    reply: Sender<ControlResponse>,
+}
+
+pub struct ControlServer {
+    addr: SocketAddr,
+    requests: Receiver<ControlRequest>,
+}
+
+impl ControlServer {
+    /// Listens on 127.0.0.1:`port` (0 picks a free port); only local clients can connect.
+    pub fn start(port: u16) -> Result<Self, String> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .map_err(|e| format!("Failed to open control port {}: {}", port, e))?;
+        let addr = listener.local_addr().map_err(|e| e.to_string())?;
+        let (sender, requests) = mpsc::channel();
+        thread::Builder::new()
+            .name("control-api".into())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let sender = sender.clone();
+                    thread::spawn(move || serve_connection(stream, sender));
+                }
+            })
+            .map_err(|e| format!("Failed to start control API: {}", e))?;
+        Ok(ControlServer { addr, requests })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Runs the commands that arrived since the last call on `host`.
+    pub fn serve_pending(&self, host: &mut impl SessionHost) {
+        for request in self.requests.try_iter() {
+            let _ = request.reply.send(handle(host, request.command));
+        }
+    }
+}
+
+fn serve_connection(stream: TcpStream, requests: Sender<ControlRequest>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<ControlCommand>(&line) {
+            Ok(command) => {
+                let (reply, response) = mpsc::channel();
+                if requests.send(ControlRequest { command, reply }).is_err() {
+                    return;
+                }
+                match response.recv() {
+                    Ok(response) => response,
+                    Err(_) => return,
+                }
+            }
+            Err(e) => ControlResponse {
+                error: Some(format!("Invalid command: {}", e)),
+                ..ControlResponse::default()
+            },
+        };
+        let Ok(text) = serde_json::to_string(&response) else {
+            return;
+        };
+        if writeln!(writer, "{}", text).is_err() {
+            return;
+        }
+    }
+}
+
+/// Session host of the headless `--serve` command.
+pub struct Controller {
+    config: SessionConfig,
+    capture: Option<CaptureSession>,
+    status: String,
+}
+
+impl Controller {
+    pub fn new(config: SessionConfig) -> Self {
+        Controller {
+            config,
+            capture: None,
+            status: "Idle".into(),
+        }
+    }
+
+    /// Keeps the running session going between commands.
+    pub fn poll(&mut self) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        for event in capture.poll() {
+            if let crate::can_capture::CaptureEvent::Error(e) = event {
+                self.status = format!("CAN error: {}", e);
+                println!("{}", self.status);
+            }
+        }
+        for warning in capture.take_warnings() {
+            println!("{}", warning);
+        }
+    }
+}
+
+impl SessionHost for Controller {
+    fn configure(&mut self, config: SessionConfig) -> Result<(), String> {
+        if self.capture.is_some() {
+            return Err("Stop the running session before configuring".into());
+        }
+        config.validate()?;
+        self.config = config;
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<(), String> {
+        if self.capture.is_some() {
+            return Err("A session is already running".into());
+        }
+        let capture = CaptureSession::start(&self.config, &[], &[])?;
+        let failures = capture.failures();
+        self.status = if failures.is_empty() {
+            "Running".into()
+        } else {
+            format!("Running ({})", failures.join("; "))
+        };
+        println!("Started {}", capture.folder().display());
+        self.capture = Some(capture);
+        Ok(())
+    }
+
+    fn mark(&mut self, label: &str) -> Result<Marker, String> {
+        self.capture
+            .as_mut()
+            .ok_or("Start a session before adding markers")?
+            .add_marker(label)
+    }
+
+    fn stop(&mut self) -> Result<PathBuf, String> {
+        let capture = self.capture.take().ok_or("No session is running")?;
+        let report = capture.stop();
+        self.status = if report.killed.is_empty() {
+            "Stopped".into()
+        } else {
+            format!("Stopped ({} killed)", report.killed.join(", "))
+        };
+        for line in report.warnings {
+            println!("{}", line);
+        }
+        let path = report.manifest_path?;
+        println!("Session manifest: {}", path.display());
+        Ok(path)
+    }
+
+    fn session(&self) -> Option<&CaptureSession> {
+        self.capture.as_ref()
+    }
+
+    fn status(&self) -> String {
+        self.status.clone()
+    }
+}
+
+/// Port of `--control-port <port>`, or `DEFAULT_CONTROL_PORT`.
+pub fn control_port(args: &[String]) -> Result<u16, String> {
+    match args.iter().position(|a| a == "--control-port") {
+        Some(i) => args
+            .get(i + 1)
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| "--control-port expects a port number".to_string()),
+        None => Ok(DEFAULT_CONTROL_PORT),
+    }
+}
+
+/// `--serve [--control-port <port>] [capture options]`: runs the control API without the GUI
+/// until Ctrl+C. The capture options are the initial configuration; a running session is
+/// stopped on exit.
+pub fn run_serve(args: &[String]) -> i32 {
+    let (port, config) = match control_port(args).and_then(|port| Ok((port, SessionConfig::from_args(args)?))) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            println!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let server = match ControlServer::start(port) {
+        Ok(server) => server,
+        Err(e) => {
+            println!("{}", e);
+            return EXIT_FAILED;
+        }
+    };
+    process_control::install_interrupt_handler();
+    println!("Control API listening on {}", server.addr());
+
+    let mut controller = Controller::new(config);
+    while !process_control::interrupted() {
+        server.serve_pending(&mut controller);
+        controller.poll();
+        thread::sleep(Duration::from_millis(20));
+    }
+    if controller.capture.is_some()
+        && let Err(e) = controller.stop()
+    {
+        println!("{}", e);
+        return EXIT_FAILED;
+    }
+    EXIT_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{SessionManifest, SourceStatus};
+    use crate::supervisor::SourceState;
+    use std::fs;
+
+    #[test]
+    fn drives_a_session_over_tcp() {
+        let dir = std::env::temp_dir().join(format!("cdl_control_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let server = ControlServer::start(0).unwrap();
+        let addr = server.addr();
+        let configure = format!(
+            r#"{{"cmd":"configure","config":{{"session_name":"api","logs_dir":{:?},"sources":["can"],"can":{{"backend":"sim","max_channels":2}}}}}}"#,
+            dir.display().to_string()
+        );
+
+        let client = thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let commands = [
+                r#"{"cmd":"mark","label":"too early"}"#,
+                configure.as_str(),
+                r#"{"cmd":"start"}"#,
+                r#"{"cmd":"start"}"#,
+                r#"{"cmd":"mark","label":"door opened"}"#,
+                r#"{"cmd":"pause"}"#,
+                r#"{"cmd":"status"}"#,
+                r#"{"cmd":"stop"}"#,
+            ];
+            commands
+                .iter()
+                .map(|command| {
+                    writeln!(writer, "{}", command).unwrap();
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    serde_json::from_str::<ControlResponse>(&line).unwrap()
+                })
+                .collect::<Vec<_>>()
+        });
+        let mut controller = Controller::new(SessionConfig::default());
+        while !client.is_finished() {
+            server.serve_pending(&mut controller);
+            controller.poll();
+            thread::sleep(Duration::from_millis(10));
+        }
+        let responses = client.join().unwrap();
+
+        let errors: Vec<_> = responses.iter().map(|r| r.error.as_deref().unwrap_or("")).collect();
+        assert!(errors[0].contains("Start a session"));
+        assert!(responses[1].ok && responses[2].ok && responses[2].running);
+        assert!(errors[3].contains("already running"));
+        assert_eq!(responses[4].marker.as_ref().unwrap().text(), "CDL_MARKER #1 door opened");
+        assert!(errors[5].contains("Invalid command"));
+        assert_eq!(responses[6].sources[0].name, "can");
+        assert_eq!(responses[6].health[0].state, SourceState::Running);
+        assert!(responses[7].ok && !responses[7].running);
+
+        let folder = dir.join("api");
+        assert_eq!(responses[7].manifest, Some(folder.join("session.json").display().to_string()));
+        let manifest = SessionManifest::read(&folder).unwrap();
+        assert_eq!(manifest.sources[0].status, SourceStatus::Stopped);
+        assert_eq!(crate::markers::read_markers(&folder).unwrap().len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,17 +1,45 @@
+mod asc_writer;
+mod blf_writer;
+mod can_backend;
+mod can_capture;
+mod can_cli;
+mod can_config;
+mod can_engine;
+mod can_inventory;
+mod can_layout;
+mod can_sim;
+mod capture_session;
+mod clock_sync;
+mod connectivity;
+mod control_api;
+mod markers;
+mod mdf4_writer;
+mod pcapng;
+mod preflight;
+mod process_control;
+mod session;
+mod supervisor;
+mod timeline;
+#[cfg(all(feature = "socketcan", target_os = "linux"))]
+mod socketcan_capture;
 #[cfg(feature = "vxl-can")]
 mod vxl_capture;
-            // Optional: CAN/ETH capture via vxlapi.dll
-            // Example usage (uncomment to test):
-            // match vxl_capture::try_open_driver() {
-            //     Ok(_) => println!("vxlapi driver opened successfully"),
-            //     Err(e) => println!("vxlapi error: {}", e),
-            // }
-            // vxl_capture::try_close_driver();
+use can_backend::CanBusMode;
+use can_capture::{CanLogFormat, CaptureEvent, CapturedNetwork, ChannelMapping, SkippedChannel};
+use can_cli::MapReport;
+use can_config::{CanNetworkConfig, DEFAULT_CONFIG_FILE};
+use capture_session::{CanSettings, CaptureSession, PendingStart, SessionConfig};
+use connectivity::{CheckRequest, CheckResult, ConnectivityChecker};
+use control_api::{ControlServer, SessionHost};
+use markers::Marker;
+use session::SessionManifest;
+use supervisor::{SourceHealth, SourceState};
+use can_inventory::{CanInventory, InventoryChannel};
+use can_layout::{LayoutCheck, LayoutState};
 use eframe::egui;
-use std::process::{Command, Child, Stdio};
+use std::process::Command;
 use std::fs;
-use chrono::Local;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use std::io::{Read, Seek, SeekFrom};
 
@@ -26,150 +54,211 @@ struct LoggerApp {
     android_connected: bool,
     eth_connected: bool,
     can_connected: bool,
+    /// Why a source's indicator is red, from the last connectivity check.
+    unreachable: Vec<(&'static str, String)>,
+    connectivity: ConnectivityChecker,
+    recheck_connectivity: bool,
 
     capture_qnx: bool,
     capture_android: bool,
     capture_can: bool,
     capture_eth: bool,
 
-    qnx_process: Option<Child>,
-    android_process: Option<Child>,
-    eth_process: Option<Child>,
-    can_process: Option<Child>,
+    // the running session: its child processes, CAN capture, clock samples and markers
+    capture: Option<CaptureSession>,
+    // a session start on its worker thread, with the CAN problem it was started without
+    starting: Option<(PendingStart, Option<String>)>,
+    // START waits for the CAN check to finish, then runs again
+    start_after_check: bool,
+
+    // options without a GUI field (logs dir, CAN backend, ...), replaced by the control API's configure
+    session_defaults: SessionConfig,
+    // local control API, when started with --control-port
+    control: Option<ControlServer>,
+
+    // state of each source of the running (or last) session
+    source_health: Vec<SourceHealth>,
+
+    // label of the next marker, and how QNX writes it to slog2
+    marker_label: String,
+    qnx_marker_command: String,
 
     log_folder: Option<PathBuf>,
 
     // UI log viewer
-    log_view: usize, // 0 = QNX, 1 = Android, 2 = Ethernet
+    log_view: usize, // 0 = QNX, 1 = Android, 2 = Ethernet, 3 = Markers
     log_buffer: String,
     last_log_update: Instant,
     can_channels: Vec<(String, String)>,
+    can_map: Vec<ChannelMapping>,
     can_channels_status: String,
+    can_log_format: CanLogFormat,
+    can_bus_mode: CanBusMode,
+    can_config_path: String,
+    can_config_status: String,
+    can_layout: Vec<LayoutCheck>,
+    can_refuse_drift: bool,
+    can_inventory: Vec<InventoryChannel>,
+    can_inventory_status: String,
+    // live state of the in-process CAN capture
+    can_live: Vec<CapturedNetwork>,
+    can_skipped: Vec<SkippedChannel>,
+    can_capture_status: String,
+    can_error: Option<String>,
+
+    // manifest shown under "Session Manifest"
+    session_view_path: String,
+    session_view: Vec<String>,
 }
 
 impl LoggerApp {
-    fn sanitize_folder_name(name: &str) -> String {
-        let sanitized: String = name
-            .chars()
-            .map(|c| {
-                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
-                    c
-                } else {
-                    '_'
-                }
-            })
-            .collect();
-
-        sanitized.trim_matches('_').to_string()
-    }
-
     fn detect_interfaces() -> Vec<String> {
-        if let Ok(output) = Command::new("ifconfig").arg("-l").output() {
-            if output.status.success() {
-                let s = String::from_utf8_lossy(&output.stdout).to_string();
-                return s.split_whitespace().map(|s| s.to_string()).collect();
-            }
+        if let Ok(output) = Command::new("ifconfig").arg("-l").output()
+            && output.status.success()
+        {
+            let s = String::from_utf8_lossy(&output.stdout).to_string();
+            return s.split_whitespace().map(|s| s.to_string()).collect();
         }
         vec!["en0".into(), "en1".into(), "eth0".into()]
     }
 
     fn read_tail(path: &PathBuf, max_bytes: usize) -> String {
-        if let Ok(mut f) = fs::File::open(path) {
-            if let Ok(size) = f.seek(SeekFrom::End(0)) {
-                let start = if size > max_bytes as u64 {
-                    size - max_bytes as u64
-                } else {
-                    0
-                };
-                let _ = f.seek(SeekFrom::Start(start));
-                let mut buf = String::new();
-                let _ = f.read_to_string(&mut buf);
-                return buf;
-            }
+        if let Ok(mut f) = fs::File::open(path)
+            && let Ok(size) = f.seek(SeekFrom::End(0))
+        {
+            let start = size.saturating_sub(max_bytes as u64);
+            let _ = f.seek(SeekFrom::Start(start));
+            let mut buf = String::new();
+            let _ = f.read_to_string(&mut buf);
+            return buf;
         }
         String::new()
     }
 
-    fn check_qnx(&self) -> bool {
-        if self.qnx_ip.trim().is_empty() {
-            return true;
-        }
-        let status = Command::new("ssh")
-            .arg("-o")
-            .arg("BatchMode=yes")
-            .arg("-o")
-            .arg("ConnectTimeout=5")
-            .arg(format!("root@{}", self.qnx_ip))
-            .arg("echo ok")
-            .status();
-        matches!(status, Ok(s) if s.success())
-    }
-
-    fn check_android(&self) -> bool {
-        if let Ok(out) = Command::new("adb").arg("devices").output() {
-            let s = String::from_utf8_lossy(&out.stdout);
-            // skip header line
-            let mut lines = s.lines().skip(1).map(|l| l.trim()).filter(|l| !l.is_empty());
-            if self.android_ip.trim().is_empty() {
-                return lines.next().is_some();
-            }
-            return lines.any(|l| l.contains(&self.android_ip));
+    /// Checks every source on background workers; the indicators update as results arrive.
+    fn refresh_connectivity(&mut self) {
+        let request = self.check_request();
+        for source in ["qnx", "android", "ethernet", "can"] {
+            self.connectivity.start(source, &request);
         }
-        false
     }
 
-    fn check_eth(&self) -> bool {
-        self.interfaces.get(self.selected_interface).is_some()
+    fn check_request(&self) -> CheckRequest {
+        CheckRequest {
+            qnx_ip: self.qnx_ip.clone(),
+            android_serial: self.android_ip.clone(),
+            interface: self.interfaces.get(self.selected_interface).cloned().unwrap_or_default(),
+            can: self.session_config().can,
+        }
     }
 
-    fn refresh_can_channels(&mut self) {
-        let exe = match std::env::current_exe() {
-            Ok(path) => path,
-            Err(e) => {
-                self.can_connected = false;
-                self.can_channels.clear();
-                self.can_channels_status = format!("Unable to resolve app path: {}", e);
-                return;
+    fn poll_connectivity(&mut self) {
+        if self.recheck_connectivity
+            && self.capture.is_none()
+            && self.starting.is_none()
+            && self.connectivity.is_due(connectivity::RECHECK_INTERVAL)
+        {
+            self.refresh_connectivity();
+        }
+        for result in self.connectivity.poll() {
+            let source = result.source();
+            let outcome = match result {
+                CheckResult::Qnx(result) => {
+                    self.qnx_connected = result.is_ok();
+                    result
+                }
+                CheckResult::Android(result) => {
+                    self.android_connected = result.is_ok();
+                    result
+                }
+                CheckResult::Ethernet(result) => {
+                    self.eth_connected = result.is_ok();
+                    result
+                }
+                CheckResult::Can(result) => {
+                    self.apply_can_map(result);
+                    if self.can_connected { Ok(()) } else { Err(self.can_channels_status.clone()) }
+                }
+                CheckResult::Inventory(result) => {
+                    self.apply_can_inventory(result);
+                    continue;
+                }
+            };
+            self.unreachable.retain(|(s, _)| *s != source);
+            if let Err(reason) = outcome {
+                self.unreachable.push((source, reason));
             }
+        }
+        if self.start_after_check && !self.connectivity.is_checking("can") {
+            self.start_after_check = false;
+            self.start_checked();
+        }
+    }
+
+    /// Blue while `source` is captured, orange if it exited or failed during the running
+    /// session, gray while it is being checked, otherwise green or red for its connectivity.
+    fn source_color(&self, source: &str, connected: bool) -> egui::Color32 {
+        let Some(capture) = &self.capture else {
+            return if self.connectivity.is_checking(source) {
+                egui::Color32::GRAY
+            } else if connected {
+                egui::Color32::from_rgb(0, 200, 0)
+            } else {
+                egui::Color32::from_rgb(200, 0, 0)
+            };
         };
+        if capture.is_running(source) {
+            egui::Color32::from_rgb(0, 122, 255)
+        } else if capture.health().iter().any(|h| h.source == source) {
+            egui::Color32::from_rgb(230, 140, 0)
+        } else if connected {
+            egui::Color32::from_rgb(0, 200, 0)
+        } else {
+            egui::Color32::from_rgb(200, 0, 0)
+        }
+    }
 
-        let output = Command::new(exe)
-            .arg("--test-can")
-            .arg("--can-map")
-            .arg("--can-app")
-            .arg("CANoe")
-            .arg("--can-max-channels")
-            .arg("64")
-            .output();
-
-        match output {
-            Ok(out) => {
-                let stdout = String::from_utf8_lossy(&out.stdout);
-                let mut mapped_channels: Vec<(String, String)> = Vec::new();
-                for line in stdout.lines() {
-                    if line.contains("ch=") && line.contains("->") && !line.contains("hwType=0") {
-                        let channel = line
-                            .split_whitespace()
-                            .find(|part| part.starts_with("ch="))
-                            .map(|part| part.trim_start_matches("ch=").to_string())
-                            .unwrap_or_else(|| "?".to_string());
-
-                        let network = line
-                            .rsplit("->")
-                            .next()
-                            .map(|part| part.trim().to_string())
-                            .unwrap_or_else(|| "UNMAPPED".to_string());
-
-                        if network != "UNMAPPED" {
-                            mapped_channels.push((channel, network));
-                        }
-                    }
+    fn load_can_config(&mut self) {
+        let path = self.can_config_path.trim();
+        let loaded = if path.is_empty() {
+            CanNetworkConfig::load_default()
+        } else {
+            CanNetworkConfig::load(Path::new(path))
+        };
+        self.can_config_status = match loaded {
+            Ok(config) => {
+                let mapped = config.networks().iter().filter(|n| n.app_channel.is_some()).count();
+                match &config.source {
+                    Some(source) => format!(
+                        "{} networks ({} mapped) from {}",
+                        config.networks().len(),
+                        mapped,
+                        source.display()
+                    ),
+                    None => format!("No {} found; channels are unmapped", DEFAULT_CONFIG_FILE),
                 }
+            }
+            Err(e) => e,
+        };
+        self.connectivity.start("can", &self.check_request());
+    }
+
+    /// Shows the channel mapping found by `connectivity::check_can`.
+    fn apply_can_map(&mut self, result: Result<MapReport, String>) {
+        match result {
+            Ok(report) => {
+                self.can_map = report.channels.into_iter().filter(|c| c.hw.hw_type != 0).collect();
+                let mapped_channels: Vec<(String, String)> = self
+                    .can_map
+                    .iter()
+                    .filter_map(|c| Some((c.channel.to_string(), c.network.clone()?)))
+                    .collect();
+                self.can_layout = report.layout;
 
                 if mapped_channels.is_empty() {
                     self.can_connected = false;
                     self.can_channels.clear();
-                    self.can_channels_status = "No mapped CAN channels found for app 'CANoe'.".into();
+                    self.can_channels_status = format!("No mapped CAN channels found for app '{}'.", report.app_name);
                 } else {
                     self.can_connected = true;
                     self.can_channels = mapped_channels;
@@ -179,208 +268,318 @@ impl LoggerApp {
             Err(e) => {
                 self.can_connected = false;
                 self.can_channels.clear();
-                self.can_channels_status = format!("Failed to query CAN mapping: {}", e);
+                self.can_layout.clear();
+                self.can_channels_status = e;
             }
         }
     }
 
-    fn start_logging(&mut self) {
-        if !self.capture_qnx && !self.capture_android && !self.capture_can && !self.capture_eth {
-            self.status = "Select at least one log source (QNX/Android/CAN/Ethernet).".into();
-            return;
-        }
-
-        // Connectivity checks
-        self.qnx_connected = self.check_qnx();
-        self.android_connected = self.check_android();
-        self.eth_connected = self.check_eth();
-        self.refresh_can_channels();
+    /// Scans the CAN hardware on a background worker; `poll_connectivity` shows the result.
+    fn refresh_can_inventory(&mut self) {
+        self.can_inventory.clear();
+        self.can_inventory_status = "Scanning...".into();
+        self.connectivity.start("inventory", &self.check_request());
+    }
 
-        if self.capture_qnx && !self.qnx_connected {
-            self.status = "QNX not reachable".into();
-            return;
+    fn apply_can_inventory(&mut self, result: Result<CanInventory, String>) {
+        match result {
+            Ok(inventory) => {
+                self.can_inventory = inventory.channels;
+                self.can_inventory_status = if self.can_inventory.is_empty() {
+                    "No CAN channels reported by the driver.".into()
+                } else {
+                    String::new()
+                };
+            }
+            Err(e) => {
+                self.can_inventory.clear();
+                self.can_inventory_status = e;
+            }
         }
-        if self.capture_android && !self.android_connected {
-            self.status = "Android not reachable".into();
+    }
+
+    /// START: re-checks the CAN mapping in the background first if CAN is captured, then goes on
+    /// in `start_checked`.
+    fn start_logging(&mut self) {
+        if self.capture.is_some() || self.starting.is_some() {
+            self.status = "A session is already running".into();
             return;
         }
-        if self.capture_can && !self.can_connected {
-            self.status = "CAN mapping unavailable (Refresh Connectivity and verify CANoe mapping).".into();
+        if !self.capture_qnx && !self.capture_android && !self.capture_can && !self.capture_eth {
+            self.status = "Select at least one log source (QNX/Android/CAN/Ethernet).".into();
             return;
         }
-        if self.capture_eth && !self.eth_connected {
-            self.status = "Ethernet interface not available".into();
+        if self.capture_can {
+            // the session records the mapping as found at start, so it must not be stale
+            self.connectivity.start("can", &self.check_request());
+            self.start_after_check = true;
+            self.status = "Waiting for the CAN check...".into();
             return;
         }
+        self.start_checked();
+    }
 
-        let default_name = Local::now().format("session_%Y_%m_%d_%H_%M_%S").to_string();
-        let requested_name = self.testing_session_name.trim();
-        let base_name = if requested_name.is_empty() {
-            default_name
-        } else {
-            let cleaned = LoggerApp::sanitize_folder_name(requested_name);
-            if cleaned.is_empty() {
-                Local::now().format("session_%Y_%m_%d_%H_%M_%S").to_string()
+    /// Applies the fresh CAN check, then starts the session on a worker thread; `poll_start`
+    /// takes it over once it runs.
+    fn start_checked(&mut self) {
+        // QNX, Android and Ethernet are checked by the session start itself; the CAN mapping and
+        // harness layout are GUI checks, so an optional CAN source with a problem is left out here
+        let mut config = self.session_config();
+        let mut skipped = None;
+        if self.capture_can {
+            let drifted: Vec<String> = self
+                .can_layout
+                .iter()
+                .filter(|c| self.can_refuse_drift && c.state != LayoutState::Ok)
+                .map(|c| format!("{} {}", c.network, c.state.name()))
+                .collect();
+            let problem = if !self.can_connected {
+                Some(format!(
+                    "CAN mapping unavailable (verify the {} mapping).",
+                    config.can.app_name
+                ))
+            } else if !drifted.is_empty() {
+                Some(format!("CAN harness layout drift: {}", drifted.join(", ")))
             } else {
-                cleaned
+                None
+            };
+            if let Some(problem) = problem {
+                if config.is_required("can") {
+                    self.status = problem;
+                    return;
+                }
+                config.sources.retain(|s| s != "can");
+                skipped = Some(problem);
             }
-        };
-
-        let mut folder = PathBuf::from("logs").join(&base_name);
-        if folder.exists() {
-            let suffix = Local::now().format("%Y_%m_%d_%H_%M_%S").to_string();
-            folder = PathBuf::from("logs").join(format!("{}_{}", base_name, suffix));
         }
-
-        if let Err(e) = fs::create_dir_all(&folder) {
-            self.status = format!("Failed to create log folder: {}", e);
+        if config.sources.is_empty() {
+            self.status = skipped.unwrap_or_else(|| "No source to capture".into());
             return;
         }
 
-        let can_folder = folder.join("CAN_LOGS");
-        if self.capture_can {
-            if let Err(e) = fs::create_dir_all(&can_folder) {
-                self.status = format!("Failed to create CAN_LOGS folder: {}", e);
-                return;
-            }
+        if config.has("can") {
+            self.can_live.clear();
+            self.can_skipped.clear();
+            self.can_error = None;
+            self.can_capture_status = "Starting...".into();
         }
+        self.status = "Starting...".into();
+        self.starting = Some((PendingStart::spawn(config, self.can_map.clone(), self.can_layout.clone()), skipped));
+    }
 
-        self.status = "Running".into();
-        self.log_folder = Some(folder.clone());
+    /// Takes over the session once its start finished.
+    fn poll_start(&mut self) {
+        let Some(result) = self.starting.as_ref().and_then(|(pending, _)| pending.poll()) else {
+            return;
+        };
+        let skipped = self.starting.take().and_then(|(_, skipped)| skipped);
+        match result {
+            Ok(capture) => {
+                let failures: Vec<String> = capture.failures().into_iter().chain(skipped).collect();
+                self.status = if failures.is_empty() {
+                    "Running".into()
+                } else {
+                    format!("Running ({})", failures.join("; "))
+                };
+                self.log_folder = Some(capture.folder().to_path_buf());
+                self.capture = Some(capture);
+            }
+            Err(e) => self.status = e,
+        }
+    }
 
-        // QNX
-        if self.capture_qnx && !self.qnx_ip.trim().is_empty() {
-            let qnx_log_path = folder.join("qnx.log");
-            let qnx_file = match fs::File::create(&qnx_log_path) {
-                Ok(f) => f,
-                Err(e) => { self.status = format!("Failed to create qnx log: {}", e); return; }
-            };
+    /// The session the GUI fields describe; QNX is only captured with an IP.
+    fn session_config(&self) -> SessionConfig {
+        let sources = [
+            ("qnx", self.capture_qnx && !self.qnx_ip.trim().is_empty()),
+            ("android", self.capture_android),
+            ("can", self.capture_can),
+            ("ethernet", self.capture_eth),
+        ];
+        SessionConfig {
+            session_name: self.testing_session_name.clone(),
+            qnx_ip: self.qnx_ip.clone(),
+            android_serial: self.android_ip.clone(),
+            interface: self.interfaces.get(self.selected_interface).cloned().unwrap_or_else(|| "en0".into()),
+            sources: sources.iter().filter(|(_, on)| *on).map(|(name, _)| name.to_string()).collect(),
+            qnx_marker_command: self.qnx_marker_command.clone(),
+            can: CanSettings {
+                log_format: self.can_log_format.name().into(),
+                mode: self.can_bus_mode.name().into(),
+                config: Some(self.can_config_path.trim().to_string()).filter(|p| !p.is_empty()),
+                refuse_drift: self.can_refuse_drift,
+                ..self.session_defaults.can.clone()
+            },
+            ..self.session_defaults.clone()
+        }
+    }
 
-            let qnx_child = Command::new("ssh")
-                .arg(format!("root@{}", self.qnx_ip))
-                .arg("sh -l -c 'exec slog2info -w'")
-                .stdout(qnx_file)
-                .spawn();
+    fn load_session_view(&mut self) {
+        self.session_view = match SessionManifest::read(Path::new(self.session_view_path.trim())) {
+            Ok(manifest) => manifest.summary_lines(),
+            Err(e) => vec![e],
+        };
+    }
 
-            match qnx_child {
-                Ok(child) => self.qnx_process = Some(child),
-                Err(e) => { self.status = format!("Failed to start SSH: {}", e); }
+    fn handle_can_event(&mut self, event: CaptureEvent) {
+        match event {
+            CaptureEvent::Started { networks, skipped } => {
+                self.can_capture_status = format!("Capturing {} networks", networks.len());
+                self.can_live = networks;
+                self.can_skipped = skipped;
+            }
+            CaptureEvent::FrameStats(networks) => self.can_live = networks,
+            CaptureEvent::Error(e) => {
+                self.can_capture_status = format!("CAN error: {}", e);
+                self.can_error = Some(e);
+            }
+            CaptureEvent::Stopped(summary) => {
+                if let Some(summary) = summary {
+                    self.can_live = summary.networks;
+                    if summary.error.is_none() {
+                        self.can_capture_status = "Stopped".into();
+                    }
+                }
             }
         }
+    }
 
-        // Android
-        if self.capture_android {
-            let android_log_path = folder.join("android.log");
-            let android_file = match fs::File::create(&android_log_path) {
-                Ok(f) => f,
-                Err(e) => { self.status = format!("Failed to create android log: {}", e); return; }
-            };
-
-            let mut adb_cmd = Command::new("adb");
-            if !self.android_ip.trim().is_empty() {
-                let _ = adb_cmd.arg("-s").arg(&self.android_ip);
+    /// Sets a marker in the running session (see `CaptureSession::add_marker`).
+    fn add_marker(&mut self) -> Option<Marker> {
+        let Some(capture) = &mut self.capture else {
+            self.status = "Start a session before adding markers".into();
+            return None;
+        };
+        match capture.add_marker(&self.marker_label) {
+            Ok(marker) => {
+                self.status = format!(
+                    "Running - marker #{} '{}' at {}",
+                    marker.index,
+                    marker.label,
+                    marker.host_time().map(|t| t.format("%H:%M:%S%.3f").to_string()).unwrap_or_default()
+                );
+                self.marker_label.clear();
+                Some(marker)
             }
-            let android_child = adb_cmd.arg("logcat").arg("-v").arg("threadtime")
-                .stdout(android_file)
-                .spawn();
-
-            match android_child {
-                Ok(child) => self.android_process = Some(child),
-                Err(e) => { self.status = format!("Failed to start adb: {}", e); }
+            Err(e) => {
+                self.status = e;
+                None
             }
         }
+    }
 
-        // Ethernet
-        if self.capture_eth {
-            let iface = self.interfaces.get(self.selected_interface).cloned().unwrap_or_else(|| "en0".into());
-            let eth_path = folder.join("ethernet.pcapng");
-            let eth_child = Command::new("dumpcap")
-                .arg("-i")
-                .arg(&iface)
-                .arg("-w")
-                .arg(eth_path.to_str().unwrap())
-                .spawn();
-
-            match eth_child {
-                Ok(child) => self.eth_process = Some(child),
-                Err(e) => { self.status = format!("Failed to start dumpcap: {}", e); }
-            }
+    /// Applies the CAN events of the running session and shows its warnings, such as targets a
+    /// marker could not be written to.
+    fn poll_capture(&mut self) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        let events = capture.poll();
+        let warnings = capture.take_warnings();
+        self.source_health = capture.health().to_vec();
+        for event in events {
+            self.handle_can_event(event);
         }
+        if !warnings.is_empty() {
+            self.status = format!("{} ({})", self.status, warnings.join("; "));
+        }
+    }
 
-        if self.capture_can {
-            let can_console_path = folder.join("can_capture_console.log");
-            let can_stdout = match fs::File::create(&can_console_path) {
-                Ok(f) => f,
-                Err(e) => {
-                    self.status = format!("Running (CAN not started: failed to create CAN console log: {})", e);
-                    return;
-                }
-            };
-            let can_stderr = match can_stdout.try_clone() {
-                Ok(f) => f,
-                Err(e) => {
-                    self.status = format!("Running (CAN not started: failed to clone CAN console log handle: {})", e);
-                    return;
-                }
-            };
+    /// Stops the running session (see `CaptureSession::stop`) and shows its manifest; returns
+    /// the manifest path.
+    fn stop_logging(&mut self) -> Result<PathBuf, String> {
+        if self.starting.is_some() {
+            return Err("The session is still starting".into());
+        }
+        let Some(capture) = self.capture.take() else {
+            return Err("No session is running".into());
+        };
+        let report = capture.stop();
+        self.source_health = report.health;
+        for event in report.events {
+            self.handle_can_event(event);
+        }
+        self.status = if report.killed.is_empty() {
+            "Stopped".into()
+        } else {
+            format!("Stopped ({} killed after {} s)", report.killed.join(", "), process_control::STOP_TIMEOUT.as_secs())
+        };
+        if !report.warnings.is_empty() {
+            self.status = format!("{} ({})", self.status, report.warnings.join("; "));
+        }
+        if let Err(e) = &report.manifest_path {
+            self.status = format!("{} (session manifest not written: {})", self.status, e);
+        }
+        self.session_view_path = report.folder.display().to_string();
+        self.session_view = report.manifest.summary_lines();
+        report.manifest_path
+    }
+}
 
-            let exe = match std::env::current_exe() {
-                Ok(path) => path,
-                Err(e) => {
-                    self.status = format!("Running (CAN not started: unable to resolve app path: {})", e);
-                    return;
+/// The control API drives the same fields and START/Mark/STOP paths as the buttons.
+impl SessionHost for LoggerApp {
+    fn configure(&mut self, config: SessionConfig) -> Result<(), String> {
+        if self.capture.is_some() || self.starting.is_some() || self.start_after_check {
+            return Err("Stop the running session before configuring".into());
+        }
+        config.validate()?;
+        self.testing_session_name = config.session_name.clone();
+        self.qnx_ip = config.qnx_ip.clone();
+        self.android_ip = config.android_serial.clone();
+        if !config.interface.is_empty() {
+            self.selected_interface = match self.interfaces.iter().position(|i| *i == config.interface) {
+                Some(index) => index,
+                None => {
+                    self.interfaces.push(config.interface.clone());
+                    self.interfaces.len() - 1
                 }
             };
-
-            let can_child = Command::new(exe)
-                .arg("--test-can")
-                .arg("--can-listen-all")
-                .arg("--can-max-channels")
-                .arg("64")
-                .arg("--can-app")
-                .arg("CANoe")
-                .arg("--can-iface-version")
-                .arg("4")
-                .arg("--can-log-format")
-                .arg("asc")
-                .arg("--can-output-dir")
-                .arg(can_folder.to_string_lossy().to_string())
-                .stdout(Stdio::from(can_stdout))
-                .stderr(Stdio::from(can_stderr))
-                .spawn();
-
-            match can_child {
-                Ok(child) => self.can_process = Some(child),
-                Err(e) => {
-                    self.status = format!("Running (CAN not started: {})", e);
-                }
-            }
         }
+        self.capture_qnx = config.has("qnx");
+        self.capture_android = config.has("android");
+        self.capture_can = config.has("can");
+        self.capture_eth = config.has("ethernet");
+        self.can_log_format = CanLogFormat::from_name(&config.can.log_format).unwrap_or(self.can_log_format);
+        self.can_bus_mode = CanBusMode::from_name(&config.can.mode).unwrap_or(self.can_bus_mode);
+        self.can_config_path = config.can.config.clone().unwrap_or_default();
+        self.can_refuse_drift = config.can.refuse_drift;
+        self.qnx_marker_command = config.qnx_marker_command.clone();
+        self.session_defaults = config;
+        self.status = "Configured by control API".into();
+        if self.capture_can {
+            self.connectivity.start("can", &self.check_request());
+        }
+        Ok(())
     }
 
-    fn stop_logging(&mut self) {
-        if let Some(child) = &mut self.qnx_process {
-            let _ = child.kill();
+    /// Returns once the start is under way; `status` shows it running or why it failed.
+    fn start(&mut self) -> Result<(), String> {
+        if self.capture.is_some() || self.starting.is_some() || self.start_after_check {
+            return Err("A session is already running".into());
         }
-
-        if let Some(child) = &mut self.android_process {
-            let _ = child.kill();
+        self.start_logging();
+        if self.starting.is_some() || self.start_after_check {
+            Ok(())
+        } else {
+            Err(self.status.clone())
         }
+    }
 
-        if let Some(child) = &mut self.eth_process {
-            let _ = child.kill();
-        }
+    fn mark(&mut self, label: &str) -> Result<Marker, String> {
+        self.marker_label = label.to_string();
+        self.add_marker().ok_or_else(|| self.status.clone())
+    }
 
-        if let Some(child) = &mut self.can_process {
-            let _ = child.kill();
-        }
+    fn stop(&mut self) -> Result<PathBuf, String> {
+        self.stop_logging()
+    }
 
-        self.status = "Stopped".into();
+    fn session(&self) -> Option<&CaptureSession> {
+        self.capture.as_ref()
+    }
 
-        self.qnx_process = None;
-        self.android_process = None;
-        self.eth_process = None;
-        self.can_process = None;
+    fn status(&self) -> String {
+        self.status.clone()
     }
 }
 
@@ -398,26 +597,58 @@ impl Default for LoggerApp {
             android_connected: false,
             eth_connected: false,
             can_connected: false,
+            unreachable: Vec::new(),
+            connectivity: ConnectivityChecker::new(),
+            recheck_connectivity: false,
             capture_qnx: true,
             capture_android: true,
             capture_can: true,
             capture_eth: true,
-            qnx_process: None,
-            android_process: None,
-            eth_process: None,
-            can_process: None,
+            capture: None,
+            starting: None,
+            start_after_check: false,
+            source_health: Vec::new(),
+            session_defaults: SessionConfig::default(),
+            control: None,
+            marker_label: String::new(),
+            qnx_marker_command: markers::DEFAULT_QNX_MARKER_COMMAND.into(),
             log_folder: None,
             log_view: 0,
             log_buffer: String::new(),
             last_log_update: Instant::now(),
             can_channels: Vec::new(),
+            can_map: Vec::new(),
             can_channels_status: "Click Refresh Connectivity to load CAN channel mapping.".into(),
+            can_log_format: CanLogFormat::Asc,
+            can_bus_mode: CanBusMode::Active,
+            can_config_path: String::new(),
+            can_config_status: String::new(),
+            can_layout: Vec::new(),
+            can_refuse_drift: false,
+            can_inventory: Vec::new(),
+            can_inventory_status: "Click Scan to list the connected CAN hardware.".into(),
+            can_live: Vec::new(),
+            can_skipped: Vec::new(),
+            can_capture_status: String::new(),
+            can_error: None,
+            session_view_path: String::new(),
+            session_view: Vec::new(),
         }
     }
 }
 
 impl eframe::App for LoggerApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_start();
+        self.poll_capture();
+        self.poll_connectivity();
+        if let Some(control) = self.control.take() {
+            control.serve_pending(self);
+            self.control = Some(control);
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::F8)) {
+            self.add_marker();
+        }
         egui::CentralPanel::default().show(ctx, |ui| {
 
             ui.heading("Cross Domain Log Collector");
@@ -451,63 +682,83 @@ impl eframe::App for LoggerApp {
                 ui.checkbox(&mut self.capture_android, "Android");
                 ui.checkbox(&mut self.capture_can, "CAN");
                 ui.checkbox(&mut self.capture_eth, "Ethernet");
+                ui.label("Reconnect attempts:");
+                ui.add(egui::DragValue::new(&mut self.session_defaults.reconnect.max_attempts).clamp_range(0..=100))
+                    .on_hover_text("QNX and Android streams that drop are reconnected this many times per outage, with backoff. 0: off.");
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Required:").on_hover_text(
+                    "A required source that fails its pre-flight check or its start aborts the session and stops the others; an optional one is skipped.",
+                );
+                for (source, label) in [("qnx", "QNX"), ("android", "Android"), ("can", "CAN"), ("ethernet", "Ethernet")] {
+                    let mut required = self.session_defaults.is_required(source);
+                    if ui.checkbox(&mut required, label).changed() {
+                        self.session_defaults.optional.retain(|s| s != source);
+                        if !required {
+                            self.session_defaults.optional.push(source.to_string());
+                        }
+                    }
+                }
             });
 
+            ui.horizontal(|ui| {
+                ui.label("CAN log format:");
+                egui::ComboBox::from_id_source("can_log_format")
+                    .selected_text(self.can_log_format.name().to_uppercase())
+                    .show_ui(ui, |ui| {
+                        for format in CanLogFormat::ALL {
+                            ui.selectable_value(&mut self.can_log_format, format, format.name().to_uppercase());
+                        }
+                    });
+                ui.label("CAN bus mode:");
+                egui::ComboBox::from_id_source("can_bus_mode")
+                    .selected_text(self.can_bus_mode.name())
+                    .show_ui(ui, |ui| {
+                        for mode in CanBusMode::ALL {
+                            ui.selectable_value(&mut self.can_bus_mode, mode, mode.name());
+                        }
+                    })
+                    .response
+                    .on_hover_text("Passive uses silent output mode (no ACKs) and never changes bus settings it does not own.");
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("CAN network config:");
+                ui.text_edit_singleline(&mut self.can_config_path)
+                    .on_hover_text("Channel-to-network mapping and bus parameters (TOML or JSON). Empty: can_networks.toml next to the exe.");
+                if ui.button("Load").clicked() {
+                    self.load_can_config();
+                }
+                ui.label(&self.can_config_status);
+            });
+            ui.checkbox(&mut self.can_refuse_drift, "Refuse to start CAN capture on harness layout drift");
+
             ui.add_space(8.0);
 
             ui.horizontal(|ui| {
                 // Connection / collecting indicators
-                let qnx_collecting = self.qnx_process.is_some();
-                let qnx_color = if qnx_collecting {
-                    egui::Color32::from_rgb(0, 122, 255)
-                } else if self.qnx_connected {
-                    egui::Color32::from_rgb(0, 200, 0)
-                } else {
-                    egui::Color32::from_rgb(200, 0, 0)
-                };
-                ui.label(egui::RichText::new("●").color(qnx_color));
-                ui.label("QNX");
-
-                let android_collecting = self.android_process.is_some();
-                let android_color = if android_collecting {
-                    egui::Color32::from_rgb(0, 122, 255)
-                } else if self.android_connected {
-                    egui::Color32::from_rgb(0, 200, 0)
-                } else {
-                    egui::Color32::from_rgb(200, 0, 0)
-                };
-                ui.label(egui::RichText::new("●").color(android_color));
-                ui.label("Android");
-
-                let eth_collecting = self.eth_process.is_some();
-                let eth_color = if eth_collecting {
-                    egui::Color32::from_rgb(0, 122, 255)
-                } else if self.eth_connected {
-                    egui::Color32::from_rgb(0, 200, 0)
-                } else {
-                    egui::Color32::from_rgb(200, 0, 0)
-                };
-                ui.label(egui::RichText::new("●").color(eth_color));
-                ui.label("Ethernet");
-
-                let can_collecting = self.can_process.is_some();
-                let can_color = if can_collecting {
-                    egui::Color32::from_rgb(0, 122, 255)
-                } else if self.can_connected {
-                    egui::Color32::from_rgb(0, 200, 0)
-                } else {
-                    egui::Color32::from_rgb(200, 0, 0)
-                };
-                ui.label(egui::RichText::new("●").color(can_color));
-                ui.label("CAN");
+                for (source, label, connected) in [
+                    ("qnx", "QNX", self.qnx_connected),
+                    ("android", "Android", self.android_connected),
+                    ("ethernet", "Ethernet", self.eth_connected),
+                    ("can", "CAN", self.can_connected),
+                ] {
+                    ui.label(egui::RichText::new("●").color(self.source_color(source, connected)));
+                    ui.label(label);
+                }
 
                 if ui.button("Refresh Connectivity").clicked() {
-                    self.qnx_connected = self.check_qnx();
-                    self.android_connected = self.check_android();
-                    self.eth_connected = self.check_eth();
-                    self.refresh_can_channels();
+                    self.refresh_connectivity();
                 }
+                ui.checkbox(&mut self.recheck_connectivity, "Re-check every 30 s")
+                    .on_hover_text("Check the sources again in the background while no session runs.");
             });
+            if self.capture.is_none() {
+                for (source, reason) in &self.unreachable {
+                    ui.colored_label(egui::Color32::from_rgb(200, 0, 0), format!("{}: {}", source, reason));
+                }
+            }
 
             ui.horizontal(|ui| {
                 if ui.button("START").clicked() {
@@ -515,12 +766,33 @@ impl eframe::App for LoggerApp {
                 }
 
                 if ui.button("STOP").clicked() {
-                    self.stop_logging();
+                    let _ = self.stop_logging();
                 }
             });
 
+            ui.horizontal(|ui| {
+                ui.label("Marker:");
+                ui.text_edit_singleline(&mut self.marker_label)
+                    .on_hover_text("Label of the next marker (empty: \"Marker <n>\").");
+                if ui.button("Mark (F8)").clicked() {
+                    self.add_marker();
+                }
+                ui.label("QNX marker command:");
+                ui.text_edit_singleline(&mut self.qnx_marker_command)
+                    .on_hover_text("Run on QNX over ssh to write the marker to slog2; {label} is replaced by the quoted marker text.");
+            });
+
             ui.add_space(6.0);
             ui.label(format!("Status: {}", self.status));
+            for health in &self.source_health {
+                let color = match health.state {
+                    SourceState::Running => egui::Color32::from_rgb(0, 122, 255),
+                    SourceState::Stopped | SourceState::Exited => egui::Color32::GRAY,
+                    SourceState::Restarting => egui::Color32::from_rgb(220, 190, 0),
+                    SourceState::Failed => egui::Color32::from_rgb(230, 140, 0),
+                };
+                ui.colored_label(color, health.describe());
+            }
 
             ui.add_space(6.0);
             egui::CollapsingHeader::new("Available CAN Channels (CANoe mapping)")
@@ -540,6 +812,102 @@ impl eframe::App for LoggerApp {
                     });
                 });
 
+            if !self.can_capture_status.is_empty() {
+                egui::CollapsingHeader::new("CAN Capture")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        ui.label(&self.can_capture_status);
+                        egui::ScrollArea::vertical().id_source("can_live").max_height(120.0).show(ui, |ui| {
+                            for network in &self.can_live {
+                                let color = if network.error_frames > 0 {
+                                    egui::Color32::from_rgb(230, 140, 0)
+                                } else if network.frames > 0 {
+                                    egui::Color32::from_rgb(0, 200, 0)
+                                } else {
+                                    egui::Color32::GRAY
+                                };
+                                ui.colored_label(
+                                    color,
+                                    format!(
+                                        "{} (ch={}): {} frames, {} error frames, {}",
+                                        network.network,
+                                        network.channel,
+                                        network.frames,
+                                        network.error_frames,
+                                        network.participation
+                                    ),
+                                );
+                            }
+                            for channel in &self.can_skipped {
+                                ui.colored_label(
+                                    egui::Color32::from_rgb(200, 0, 0),
+                                    format!("ch={} not captured: {}", channel.channel, channel.reason),
+                                );
+                            }
+                        });
+                    });
+            }
+
+            egui::CollapsingHeader::new("Session Manifest")
+                .default_open(false)
+                .show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Session folder:");
+                        ui.text_edit_singleline(&mut self.session_view_path);
+                        if ui.button("Load").clicked() {
+                            self.load_session_view();
+                        }
+                    });
+                    egui::ScrollArea::vertical().id_source("session_view").max_height(120.0).show(ui, |ui| {
+                        for line in &self.session_view {
+                            ui.label(egui::RichText::new(line).monospace());
+                        }
+                    });
+                });
+
+            egui::CollapsingHeader::new("CAN Hardware Inventory")
+                .default_open(false)
+                .show(ui, |ui| {
+                    if ui.button("Scan").clicked() {
+                        self.refresh_can_inventory();
+                    }
+                    egui::ScrollArea::vertical().id_source("can_inventory").max_height(120.0).show(ui, |ui| {
+                        if self.can_inventory.is_empty() {
+                            ui.label(&self.can_inventory_status);
+                        } else {
+                            for channel in &self.can_inventory {
+                                ui.label(egui::RichText::new(can_inventory::format_channel(channel)).monospace());
+                            }
+                        }
+                    });
+                });
+
+            if !self.can_layout.is_empty() {
+                egui::CollapsingHeader::new("Harness Layout Check")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        egui::ScrollArea::vertical().id_source("can_layout").max_height(100.0).show(ui, |ui| {
+                            for check in &self.can_layout {
+                                let color = match check.state {
+                                    LayoutState::Ok => egui::Color32::from_rgb(0, 200, 0),
+                                    LayoutState::Moved => egui::Color32::from_rgb(230, 140, 0),
+                                    LayoutState::Missing => egui::Color32::from_rgb(200, 0, 0),
+                                };
+                                ui.colored_label(
+                                    color,
+                                    format!(
+                                        "{:<7} {} (ch={}) {}",
+                                        check.state.name(),
+                                        check.network,
+                                        check.app_channel + 1,
+                                        check.detail
+                                    ),
+                                );
+                            }
+                        });
+                    });
+            }
+
             ui.separator();
 
             ui.horizontal(|ui| {
@@ -548,12 +916,14 @@ impl eframe::App for LoggerApp {
                     .selected_text(match self.log_view {
                         0 => "QNX",
                         1 => "Android",
-                        _ => "Ethernet",
+                        2 => "Ethernet",
+                        _ => "Markers",
                     })
                     .show_ui(ui, |ui| {
                         ui.selectable_value(&mut self.log_view, 0, "QNX");
                         ui.selectable_value(&mut self.log_view, 1, "Android");
                         ui.selectable_value(&mut self.log_view, 2, "Ethernet");
+                        ui.selectable_value(&mut self.log_view, 3, "Markers");
                     });
             });
 
@@ -563,11 +933,20 @@ impl eframe::App for LoggerApp {
                     let path = match self.log_view {
                         0 => folder.join("qnx.log"),
                         1 => folder.join("android.log"),
-                        _ => folder.join("ethernet.pcapng"),
+                        2 => folder.join("ethernet.pcapng"),
+                        _ => folder.join(markers::MARKERS_FILE),
                     };
                     // For pcap, show a small message
                     if self.log_view == 2 {
                         self.log_buffer = format!("Capturing to: {}", path.display());
+                    } else if self.log_view == 3 {
+                        self.log_buffer = match markers::read_markers(folder) {
+                            Ok(markers) => markers
+                                .iter()
+                                .map(|m| format!("{}  #{}  {}\n", m.time, m.index, m.label))
+                                .collect(),
+                            Err(e) => e,
+                        };
                     } else {
                         self.log_buffer = LoggerApp::read_tail(&path, 32 * 1024);
                     }
@@ -583,179 +962,96 @@ impl eframe::App for LoggerApp {
 
         ctx.request_repaint();
     }
+
+    /// Closing the window stops the capture the same way as STOP, so no child outlives the GUI.
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let Some((pending, _)) = self.starting.take() {
+            self.capture = pending.wait().ok();
+        }
+        let _ = self.stop_logging();
+    }
 }
 
 fn main() -> Result<(), eframe::Error> {
     let args: Vec<String> = std::env::args().collect();
 
-    // Check for --test-can flag (for Vector CAN test)
-    if args.iter().any(|arg| arg == "--test-can") {
-        #[cfg(feature = "vxl-can")]
-        {
-            let mut selected_channel: Option<u32> = None;
-            let mut timeout_ms: u64 = 300;
-            let mut can_app_name = String::from("CANoe");
-            let mut can_iface_version: u32 = 4;
-            let mut can_max_channels: u32 = 64;
-            let mut can_diagnose = false;
-            let mut can_map = false;
-            let mut can_listen = false;
-            let mut can_listen_all = false;
-            let mut can_duration_ms: Option<u64> = None;
-            let mut can_log_file: Option<String> = None;
-            let mut can_log_format = String::from("text");
-            let mut can_output_dir: Option<String> = None;
-            let mut index = 0usize;
-            while index < args.len() {
-                if args[index] == "--can-channel" && index + 1 < args.len() {
-                    selected_channel = args[index + 1].parse::<u32>().ok();
-                }
-                if args[index] == "--can-timeout-ms" && index + 1 < args.len() {
-                    if let Ok(parsed) = args[index + 1].parse::<u64>() {
-                        timeout_ms = parsed;
-                    }
-                }
-                if args[index] == "--can-app" && index + 1 < args.len() {
-                    can_app_name = args[index + 1].clone();
-                }
-                if args[index] == "--can-iface-version" && index + 1 < args.len() {
-                    if let Ok(parsed) = args[index + 1].parse::<u32>() {
-                        can_iface_version = parsed;
-                    }
-                }
-                if args[index] == "--can-max-channels" && index + 1 < args.len() {
-                    if let Ok(parsed) = args[index + 1].parse::<u32>() {
-                        can_max_channels = parsed.max(1).min(64);
-                    }
-                }
-                if args[index] == "--can-diagnose" {
-                    can_diagnose = true;
-                }
-                if args[index] == "--can-map" {
-                    can_map = true;
-                }
-                if args[index] == "--can-listen" {
-                    can_listen = true;
-                }
-                if args[index] == "--can-listen-all" {
-                    can_listen_all = true;
-                }
-                if args[index] == "--can-duration-ms" && index + 1 < args.len() {
-                    if let Ok(parsed) = args[index + 1].parse::<u64>() {
-                        can_duration_ms = Some(parsed);
-                    }
-                }
-                if args[index] == "--can-log-file" && index + 1 < args.len() {
-                    can_log_file = Some(args[index + 1].clone());
-                }
-                if args[index] == "--can-output-dir" && index + 1 < args.len() {
-                    can_output_dir = Some(args[index + 1].clone());
-                }
-                if args[index] == "--can-log-format" && index + 1 < args.len() {
-                    can_log_format = args[index + 1].to_lowercase();
-                }
-                index += 1;
-            }
+    if args.iter().any(|arg| arg == "--show-session") {
+        std::process::exit(session::run_show(&args));
+    }
 
-            match vxl_capture::try_open_driver() {
-                Ok(_) => println!("vxlapi driver opened successfully"),
-                Err(e) => {
-                    println!("vxlapi error: {}", e);
-                    return Ok(());
-                }
-            }
+    if args.iter().any(|arg| arg == "--merge") {
+        std::process::exit(timeline::run_merge(&args));
+    }
 
-            if can_map {
-                vxl_capture::print_can_channel_mapping(can_max_channels, &can_app_name);
-            } else if can_diagnose {
-                vxl_capture::diagnose_can_setup(can_max_channels, &can_app_name, can_iface_version);
-            } else if can_listen_all {
-                let format = if can_log_format == "asc" {
-                    vxl_capture::CanLogFormat::Asc
-                } else {
-                    vxl_capture::CanLogFormat::Text
-                };
-                match vxl_capture::listen_can_all_connected(
-                    can_max_channels,
-                    &can_app_name,
-                    can_iface_version,
-                    can_duration_ms,
-                    can_output_dir.as_deref(),
-                    format,
-                ) {
-                    Ok(()) => {}
-                    Err(e) => println!("CAN listen-all error: {}", e),
-                }
-            } else if can_listen {
-                if let Some(channel) = selected_channel {
-                    let format = if can_log_format == "asc" {
-                        vxl_capture::CanLogFormat::Asc
-                    } else {
-                        vxl_capture::CanLogFormat::Text
-                    };
-                    match vxl_capture::listen_can_on_channel(
-                        channel,
-                        &can_app_name,
-                        can_iface_version,
-                        can_duration_ms,
-                        can_log_file.as_deref(),
-                        format,
-                    ) {
-                        Ok(()) => {}
-                        Err(e) => println!("CAN listen error: {}", e),
-                    }
-                } else {
-                    println!("--can-listen requires --can-channel <n>");
-                }
-            } else if let Some(channel) = selected_channel {
-                println!(
-                    "Listening on CAN channel {} for {} ms (app: {}, iface: {})...",
-                    channel, timeout_ms, can_app_name, can_iface_version
-                );
-                match vxl_capture::try_capture_can_on_channel(
-                    channel,
-                    timeout_ms,
-                    &can_app_name,
-                    can_iface_version,
-                ) {
-                    Ok(true) => println!("CAN capture complete on channel {}", channel),
-                    Ok(false) => println!("No CAN frame received on channel {}", channel),
-                    Err(e) => println!("CAN capture error on channel {}: {}", channel, e),
-                }
-            } else {
-                println!(
-                    "Auto-scanning CAN channels 0..{} for traffic (app: {}, iface: {})...",
-                    can_max_channels.saturating_sub(1),
-                    can_app_name,
-                    can_iface_version
-                );
-                match vxl_capture::try_capture_any_can(
-                    can_max_channels,
-                    timeout_ms,
-                    &can_app_name,
-                    can_iface_version,
-                ) {
-                    Ok(channel) => println!("Detected traffic on CAN channel {}", channel),
-                    Err(e) => println!("CAN auto-scan result: {}", e),
-                }
-            }
+    if args.iter().any(|arg| arg == "--capture") {
+        std::process::exit(capture_session::run_capture(&args));
+    }
 
-            vxl_capture::try_close_driver();
-            return Ok(());
-        }
+    // Check for --test-can flag (CAN test/capture on Vector hardware or the simulator)
+    if args.iter().any(|arg| arg == "--test-can") {
+        let options = can_cli::CanCliOptions::parse(&args);
+        std::process::exit(can_cli::run(&options));
+    }
+    if args.iter().any(|arg| arg == "--serve") {
+        std::process::exit(control_api::run_serve(&args));
+    }
 
-        #[cfg(not(feature = "vxl-can"))]
-        {
-            println!(
-                "--test-can requested, but vxl CAN support is disabled. Rebuild with --features vxl-can"
-            );
-            return Ok(());
+    let mut app = LoggerApp::default();
+    if args.iter().any(|arg| arg == "--control-port") {
+        match control_api::control_port(&args).and_then(ControlServer::start) {
+            Ok(server) => {
+                app.status = format!("Idle - control API on {}", server.addr());
+                app.control = Some(server);
+            }
+            Err(e) => app.status = e,
         }
     }
     let options = eframe::NativeOptions::default();
     eframe::run_native(
         "Cross Domain Logger",
         options,
-        Box::new(|_cc| Box::new(LoggerApp::default())),
+        Box::new(|_cc| Box::new(app)),
     )
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use control_api::ControlCommand;
+    use std::time::Duration;
+
+    #[test]
+    fn control_api_starts_the_gui_session_in_the_background() {
+        let dir = std::env::temp_dir().join(format!("cdl_gui_control_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let networks = dir.join("networks.toml");
+        fs::write(&networks, "[[network]]\nname = \"HS_CAN1\"\napp_channel = 0\n").unwrap();
+        let config = format!(
+            r#"{{"session_name":"gui","logs_dir":{:?},"sources":["can"],"can":{{"backend":"sim","app_name":"CANalyzer","max_channels":2,"config":{:?}}}}}"#,
+            dir.display().to_string(),
+            networks.display().to_string()
+        );
+        let config: SessionConfig = serde_json::from_str(&config).unwrap();
+
+        let mut app = LoggerApp::default();
+        assert!(control_api::handle(&mut app, ControlCommand::Configure { config: Box::new(config) }).ok);
+        let can = app.check_request().can;
+        assert_eq!((can.backend.as_str(), can.app_name.as_str(), can.max_channels), ("sim", "CANalyzer", 2));
+
+        let started = control_api::handle(&mut app, ControlCommand::Start);
+        assert!(started.ok && !started.running, "{:?}", started.error);
+        assert_eq!(started.status, "Waiting for the CAN check...");
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while app.capture.is_none() && Instant::now() < deadline {
+            app.poll_start();
+            app.poll_connectivity();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(app.capture.is_some(), "{}", app.status);
+        assert_eq!(app.can_channels, [("1".to_string(), "HS_CAN1".to_string())]);
+        let stopped = control_api::handle(&mut app, ControlCommand::Stop);
+        assert!(stopped.ok, "{:?}", stopped.error);
+        assert!(Path::new(&stopped.manifest.unwrap()).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
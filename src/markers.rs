@@ -0,0 +1,150 @@
+// Session markers.
+// A marker flags "it happened now" during a running session. It is appended to markers.jsonl
+// in the session folder with its host time and label, and injected into every live stream so
+// it shows up next to the records it refers to: `log -t CDL_MARKER` into logcat, a configurable
+// command on QNX (slog2), a comment line in the CAN logs and - once dumpcap has closed the file
+// at stop - a comment packet in ethernet.pcapng.
+
+use crate::clock_sync::DeviceTarget;
+use crate::timeline::Domain;
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+use std::thread::{self, JoinHandle};
+
+pub const MARKERS_FILE: &str = "markers.jsonl";
+/// Tag of the injected log lines, so they can be searched for in every log.
+pub const MARKER_TAG: &str = "CDL_MARKER";
+/// QNX has no standard command that writes to slog2; this default works where syslog is
+/// forwarded to slog2 and can be replaced with whatever the target image provides.
+pub const DEFAULT_QNX_MARKER_COMMAND: &str = "logger -t CDL_MARKER {label}";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    /// 1-based within the session.
+    pub index: u32,
+    /// Host time the marker was set.
+    pub time: String,
+    pub label: String,
+}
+
+impl Marker {
+    pub fn new(index: u32, label: &str) -> Self {
+        let label = label.trim();
+        Marker {
+            index,
+            time: Local::now().to_rfc3339_opts(SecondsFormat::Micros, false),
+            label: if label.is_empty() { format!("Marker {}", index) } else { label.to_string() },
+        }
+    }
+
+    pub fn host_time(&self) -> Option<DateTime<Local>> {
+        DateTime::parse_from_rfc3339(&self.time).ok().map(|t| t.with_timezone(&Local))
+    }
+
+    /// `CDL_MARKER #<index> <label>`, the text injected into every stream.
+    pub fn text(&self) -> String {
+        format!("{} #{} {}", MARKER_TAG, self.index, self.label)
+    }
+
+    /// Shell script that writes the marker on `target`; QNX uses `qnx_command` with `{label}`
+    /// replaced by the quoted marker text.
+    pub fn script(&self, target: &DeviceTarget, qnx_command: &str) -> String {
+        let label = format!("#{} {}", self.index, self.label);
+        match target.domain {
+            Domain::Qnx => qnx_command.replace("{label}", &shell_quote(&label)),
+            _ => format!("log -t {} {}", MARKER_TAG, shell_quote(&label)),
+        }
+    }
+}
+
+/// Single-quoted for a POSIX shell.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+pub fn append_marker(folder: &Path, marker: &Marker) -> Result<(), String> {
+    let path = folder.join(MARKERS_FILE);
+    let line = serde_json::to_string(marker).map_err(|e| format!("Failed to encode marker: {}", e))?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{}", line))
+        .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+}
+
+/// Markers of a session folder; a missing file gives no markers.
+pub fn read_markers(folder: &Path) -> Result<Vec<Marker>, String> {
+    let path = folder.join(MARKERS_FILE);
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| format!("Invalid marker in '{}': {}", path.display(), e)))
+        .collect()
+}
+
+/// Writes `marker` on every target from a background thread; the thread returns one message
+/// per target that failed.
+pub fn inject_remote(marker: &Marker, targets: Vec<DeviceTarget>, qnx_command: &str) -> JoinHandle<Vec<String>> {
+    let scripts: Vec<(DeviceTarget, String)> = targets
+        .into_iter()
+        .map(|target| {
+            let script = marker.script(&target, qnx_command);
+            (target, script)
+        })
+        .collect();
+    thread::spawn(move || {
+        let mut errors = Vec::new();
+        for (target, script) in scripts {
+            let output = target
+                .command(&script)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .output();
+            let error = match output {
+                Ok(output) if output.status.success() => continue,
+                Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                Err(e) => e.to_string(),
+            };
+            errors.push(format!("{} marker not written: {}", target.domain.name(), error));
+        }
+        errors
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_markers_and_quotes_injected_text() {
+        let dir = std::env::temp_dir().join(format!("cdl_markers_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let first = Marker::new(1, "  ");
+        let second = Marker::new(2, "driver's door opened");
+        append_marker(&dir, &first).unwrap();
+        append_marker(&dir, &second).unwrap();
+        assert_eq!(read_markers(&dir).unwrap(), [first.clone(), second.clone()]);
+        assert_eq!(first.text(), "CDL_MARKER #1 Marker 1");
+        assert!(second.host_time().unwrap() >= first.host_time().unwrap());
+
+        assert_eq!(
+            second.script(&DeviceTarget::android("7d186538"), DEFAULT_QNX_MARKER_COMMAND),
+            r"log -t CDL_MARKER '#2 driver'\''s door opened'"
+        );
+        assert_eq!(
+            second.script(&DeviceTarget::qnx("192.168.164.115"), DEFAULT_QNX_MARKER_COMMAND),
+            r"logger -t CDL_MARKER '#2 driver'\''s door opened'"
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,418 @@
+// ASAM MDF 4.10 bus-logging writer for CAN / CAN FD frames.
+// One file holds one sorted data group with a single CAN_DataFrame channel group
+// (ASAM MDF bus logging layout): master channel `Timestamp` plus the composed
+// `CAN_DataFrame` structure (BusChannel, ID, IDE, DLC, DataLength, Dir, EDL, BRS, ESI, DataBytes).
+// The file is written as "UnFinMF" with a growing DT block; finish() patches the DT length and
+// cycle count and marks the file finalized, so an interrupted capture is still recoverable.
+
+use crate::can_backend::CanFrame;
+use chrono::{DateTime, Local, Offset};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+const RECORD_SIZE: usize = 80;
+const FRAME_OFFSET: u32 = 8;
+const DATA_BYTES_OFFSET: usize = 16;
+
+const CN_TYPE_FIXED: u8 = 0;
+const CN_TYPE_MASTER: u8 = 2;
+const CN_SYNC_NONE: u8 = 0;
+const CN_SYNC_TIME: u8 = 1;
+const DT_UINT_LE: u8 = 0;
+const DT_FLOAT_LE: u8 = 4;
+const DT_BYTE_ARRAY: u8 = 10;
+const CN_FLAG_BUS_EVENT: u32 = 0x0400;
+const CG_FLAG_BUS_EVENT: u16 = 0x0002;
+const CG_FLAG_PLAIN_BUS_EVENT: u16 = 0x0004;
+const SI_TYPE_BUS: u8 = 2;
+const SI_BUS_CAN: u8 = 2;
+const UNFIN_CYCLE_COUNTERS: u16 = 0x0001;
+const UNFIN_DT_LENGTH: u16 = 0x0004;
+
+struct ChannelSpec {
+    name: &'static str,
+    data_type: u8,
+    byte_offset: u32,
+    bit_offset: u8,
+    bit_count: u32,
+}
+
+/// Members of the CAN_DataFrame structure, with offsets inside the 80 byte record.
+const FRAME_MEMBERS: [ChannelSpec; 10] = [
+    ChannelSpec { name: "CAN_DataFrame.BusChannel", data_type: DT_UINT_LE, byte_offset: 8, bit_offset: 0, bit_count: 8 },
+    ChannelSpec { name: "CAN_DataFrame.ID", data_type: DT_UINT_LE, byte_offset: 9, bit_offset: 0, bit_count: 29 },
+    ChannelSpec { name: "CAN_DataFrame.IDE", data_type: DT_UINT_LE, byte_offset: 12, bit_offset: 7, bit_count: 1 },
+    ChannelSpec { name: "CAN_DataFrame.DLC", data_type: DT_UINT_LE, byte_offset: 13, bit_offset: 0, bit_count: 4 },
+    ChannelSpec { name: "CAN_DataFrame.DataLength", data_type: DT_UINT_LE, byte_offset: 14, bit_offset: 0, bit_count: 8 },
+    ChannelSpec { name: "CAN_DataFrame.Dir", data_type: DT_UINT_LE, byte_offset: 15, bit_offset: 0, bit_count: 1 },
+    ChannelSpec { name: "CAN_DataFrame.EDL", data_type: DT_UINT_LE, byte_offset: 15, bit_offset: 1, bit_count: 1 },
+    ChannelSpec { name: "CAN_DataFrame.BRS", data_type: DT_UINT_LE, byte_offset: 15, bit_offset: 2, bit_count: 1 },
+    ChannelSpec { name: "CAN_DataFrame.ESI", data_type: DT_UINT_LE, byte_offset: 15, bit_offset: 3, bit_count: 1 },
+    ChannelSpec { name: "CAN_DataFrame.DataBytes", data_type: DT_BYTE_ARRAY, byte_offset: 16, bit_offset: 0, bit_count: 64 * 8 },
+];
+
+pub struct Mdf4Writer {
+    out: BufWriter<File>,
+    cg_cycle_count_pos: u64,
+    dt_pos: u64,
+    record_count: u64,
+}
+
+/// Appends 8-byte aligned blocks to an in-memory image and hands out their file addresses.
+struct BlockImage {
+    bytes: Vec<u8>,
+}
+
+impl BlockImage {
+    fn add(&mut self, id: &[u8; 4], links: &[u64], data: &[u8]) -> u64 {
+        let address = self.bytes.len() as u64;
+        let unpadded = 24 + links.len() * 8 + data.len();
+        let length = unpadded.div_ceil(8) * 8;
+        self.bytes.extend_from_slice(id);
+        self.bytes.extend_from_slice(&[0u8; 4]);
+        self.bytes.extend_from_slice(&(length as u64).to_le_bytes());
+        self.bytes.extend_from_slice(&(links.len() as u64).to_le_bytes());
+        for link in links {
+            self.bytes.extend_from_slice(&link.to_le_bytes());
+        }
+        self.bytes.extend_from_slice(data);
+        self.bytes.resize(address as usize + length, 0);
+        address
+    }
+
+    fn add_text(&mut self, id: &[u8; 4], text: &str) -> u64 {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        self.add(id, &[], &data)
+    }
+
+    fn patch_u64(&mut self, at: u64, value: u64) {
+        let at = at as usize;
+        self.bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// `links` are the cn_next, cn_composition and cn_md_unit addresses.
+fn channel_block(image: &mut BlockImage, spec: &ChannelSpec, cn_type: u8, sync_type: u8, flags: u32, links: [u64; 3]) -> u64 {
+    let [next, composition, unit] = links;
+    let name = image.add_text(b"##TX", spec.name);
+    let mut data = Vec::with_capacity(72);
+    data.push(cn_type);
+    data.push(sync_type);
+    data.push(spec.data_type);
+    data.push(spec.bit_offset);
+    data.extend_from_slice(&spec.byte_offset.to_le_bytes());
+    data.extend_from_slice(&spec.bit_count.to_le_bytes());
+    data.extend_from_slice(&flags.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes()); // invalidation bit position
+    data.push(0); // precision
+    data.push(0);
+    data.extend_from_slice(&0u16.to_le_bytes()); // attachment count
+    data.extend_from_slice(&[0u8; 48]); // value range and limits (not valid)
+    // links: cn_next, composition, name, source, conversion, data, unit, comment
+    image.add(b"##CN", &[next, composition, name, 0, 0, 0, unit, 0], &data)
+}
+
+impl Mdf4Writer {
+    pub fn create(file: File, start: DateTime<Local>) -> Result<Self, String> {
+        let mut image = BlockImage { bytes: Vec::with_capacity(4096) };
+
+        // ID block
+        let mut id = Vec::with_capacity(64);
+        id.extend_from_slice(b"UnFinMF ");
+        id.extend_from_slice(b"4.10    ");
+        id.extend_from_slice(b"CDLogger");
+        id.extend_from_slice(&[0u8; 4]);
+        id.extend_from_slice(&410u16.to_le_bytes());
+        id.extend_from_slice(&[0u8; 30]);
+        id.extend_from_slice(&(UNFIN_CYCLE_COUNTERS | UNFIN_DT_LENGTH).to_le_bytes());
+        id.extend_from_slice(&0u16.to_le_bytes());
+        image.bytes.extend_from_slice(&id);
+
+        let start_ns = start.timestamp_nanos_opt().unwrap_or(0) as u64;
+        let tz_offset_min = (start.offset().fix().local_minus_utc() / 60) as i16;
+
+        let mut hd_data = Vec::with_capacity(32);
+        hd_data.extend_from_slice(&start_ns.to_le_bytes());
+        hd_data.extend_from_slice(&tz_offset_min.to_le_bytes());
+        hd_data.extend_from_slice(&0i16.to_le_bytes()); // DST already folded into the offset
+        hd_data.push(0x02); // time offsets valid
+        hd_data.push(0); // local PC reference time
+        hd_data.push(0);
+        hd_data.push(0);
+        hd_data.extend_from_slice(&0f64.to_le_bytes());
+        hd_data.extend_from_slice(&0f64.to_le_bytes());
+        // links: dg_first, fh_first, ch_first, at_first, ev_first, comment
+        let hd = image.add(b"##HD", &[0, 0, 0, 0, 0, 0], &hd_data);
+
+        let fh_comment = image.add_text(
+            b"##MD",
+            &format!(
+                "<FHcomment><TX>CAN capture</TX><tool_id>{}</tool_id><tool_vendor>cross_domain_logger</tool_vendor><tool_version>{}</tool_version></FHcomment>",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            ),
+        );
+        let mut fh_data = Vec::with_capacity(16);
+        fh_data.extend_from_slice(&start_ns.to_le_bytes());
+        fh_data.extend_from_slice(&tz_offset_min.to_le_bytes());
+        fh_data.extend_from_slice(&0i16.to_le_bytes());
+        fh_data.push(0x02);
+        fh_data.extend_from_slice(&[0u8; 3]);
+        let fh = image.add(b"##FH", &[0, fh_comment], &fh_data);
+
+        let source_name = image.add_text(b"##TX", "CAN");
+        let si = image.add(b"##SI", &[source_name, source_name, 0], &[SI_TYPE_BUS, SI_BUS_CAN, 0, 0, 0, 0, 0, 0]);
+
+        // Structure members are chained back to front so every block knows its successor.
+        let mut next_member = 0u64;
+        for spec in FRAME_MEMBERS.iter().rev() {
+            next_member = channel_block(&mut image, spec, CN_TYPE_FIXED, CN_SYNC_NONE, 0, [next_member, 0, 0]);
+        }
+        let frame_spec = ChannelSpec {
+            name: "CAN_DataFrame",
+            data_type: DT_BYTE_ARRAY,
+            byte_offset: FRAME_OFFSET,
+            bit_offset: 0,
+            bit_count: (RECORD_SIZE as u32 - FRAME_OFFSET) * 8,
+        };
+        let frame_cn = channel_block(&mut image, &frame_spec, CN_TYPE_FIXED, CN_SYNC_NONE, CN_FLAG_BUS_EVENT, [0, next_member, 0]);
+        let unit = image.add_text(b"##TX", "s");
+        let time_spec = ChannelSpec {
+            name: "Timestamp",
+            data_type: DT_FLOAT_LE,
+            byte_offset: 0,
+            bit_offset: 0,
+            bit_count: 64,
+        };
+        let time_cn = channel_block(&mut image, &time_spec, CN_TYPE_MASTER, CN_SYNC_TIME, 0, [frame_cn, 0, unit]);
+
+        let acq_name = image.add_text(b"##TX", "CAN_DataFrame");
+        let mut cg_data = Vec::with_capacity(32);
+        cg_data.extend_from_slice(&0u64.to_le_bytes()); // record id
+        cg_data.extend_from_slice(&0u64.to_le_bytes()); // cycle count, patched by finish()
+        cg_data.extend_from_slice(&(CG_FLAG_BUS_EVENT | CG_FLAG_PLAIN_BUS_EVENT).to_le_bytes());
+        cg_data.extend_from_slice(&(b'.' as u16).to_le_bytes());
+        cg_data.extend_from_slice(&[0u8; 4]);
+        cg_data.extend_from_slice(&(RECORD_SIZE as u32).to_le_bytes());
+        cg_data.extend_from_slice(&0u32.to_le_bytes()); // invalidation bytes
+        // links: cg_next, cn_first, acq_name, acq_source, sr_first, comment
+        let cg = image.add(b"##CG", &[0, time_cn, acq_name, si, 0, 0], &cg_data);
+        let cg_cycle_count_pos = cg + 24 + 6 * 8 + 8;
+
+        // DG comes last before DT, so the DT block sits at the end of the file and can grow.
+        let dg = image.bytes.len() as u64;
+        let dt = dg + 24 + 4 * 8 + 8;
+        image.add(b"##DG", &[0, cg, dt, 0], &[0u8; 8]);
+
+        image.patch_u64(hd + 24, dg);
+        image.patch_u64(hd + 32, fh);
+
+        let mut out = BufWriter::new(file);
+        out.write_all(&image.bytes)
+            .and_then(|_| out.write_all(b"##DT"))
+            .and_then(|_| out.write_all(&[0u8; 4]))
+            .and_then(|_| out.write_all(&24u64.to_le_bytes()))
+            .and_then(|_| out.write_all(&0u64.to_le_bytes()))
+            .map_err(|e| format!("Failed to write MDF header blocks: {}", e))?;
+
+        Ok(Self {
+            out,
+            cg_cycle_count_pos,
+            dt_pos: dt,
+            record_count: 0,
+        })
+    }
+
+    /// `timestamp_ns` is relative to the start time given to `create`.
+    pub fn write_frame(&mut self, channel: u16, timestamp_ns: u64, frame: &CanFrame) -> Result<(), String> {
+        let mut record = [0u8; RECORD_SIZE];
+        record[0..8].copy_from_slice(&(timestamp_ns as f64 / 1e9).to_le_bytes());
+        record[8] = channel.min(255) as u8;
+        let mut id = frame.id & 0x1FFF_FFFF;
+        if frame.extended {
+            id |= 0x8000_0000;
+        }
+        record[9..13].copy_from_slice(&id.to_le_bytes());
+        record[13] = frame.dlc & 0x0F;
+        let data_len = frame.data.len().min(64);
+        record[14] = data_len as u8;
+        record[15] = (frame.tx as u8) | ((frame.fd as u8) << 1) | ((frame.brs as u8) << 2) | ((frame.esi as u8) << 3);
+        record[DATA_BYTES_OFFSET..DATA_BYTES_OFFSET + data_len].copy_from_slice(&frame.data[..data_len]);
+
+        self.out
+            .write_all(&record)
+            .map_err(|e| format!("Failed to write MDF record: {}", e))?;
+        self.record_count += 1;
+        Ok(())
+    }
+
+    /// Patches the DT length and cycle count and marks the file as finalized.
+    pub fn finish(mut self) -> Result<(), String> {
+        let dt_length = 24 + self.record_count * RECORD_SIZE as u64;
+        let result = (|| -> std::io::Result<()> {
+            self.out.seek(SeekFrom::Start(self.dt_pos + 8))?;
+            self.out.write_all(&dt_length.to_le_bytes())?;
+            self.out.seek(SeekFrom::Start(self.cg_cycle_count_pos))?;
+            self.out.write_all(&self.record_count.to_le_bytes())?;
+            self.out.seek(SeekFrom::Start(0))?;
+            self.out.write_all(b"MDF     ")?;
+            self.out.seek(SeekFrom::Start(60))?;
+            self.out.write_all(&0u16.to_le_bytes())?;
+            self.out.flush()
+        })();
+        result.map_err(|e| format!("Failed to finalize MDF file: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(b: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([b[at], b[at + 1]])
+    }
+
+    fn u32_at(b: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(b: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+    }
+
+    fn link(b: &[u8], block: u64, index: usize) -> u64 {
+        u64_at(b, block as usize + 24 + index * 8)
+    }
+
+    fn text(b: &[u8], block: u64) -> String {
+        let start = block as usize + 24;
+        let end = start + b[start..].iter().position(|c| *c == 0).unwrap();
+        String::from_utf8(b[start..end].to_vec()).unwrap()
+    }
+
+    fn read_bits(record: &[u8], byte_offset: u32, bit_offset: u8, bit_count: u32) -> u64 {
+        let mut raw = [0u8; 8];
+        let len = ((bit_offset as u32 + bit_count).div_ceil(8)) as usize;
+        raw[..len].copy_from_slice(&record[byte_offset as usize..byte_offset as usize + len]);
+        let value = u64::from_le_bytes(raw) >> bit_offset;
+        if bit_count >= 64 { value } else { value & ((1u64 << bit_count) - 1) }
+    }
+
+    struct MdfChannel {
+        name: String,
+        cn_type: u8,
+        data_type: u8,
+        bit_offset: u8,
+        byte_offset: u32,
+        bit_count: u32,
+    }
+
+    fn read_channel(b: &[u8], cn: u64) -> MdfChannel {
+        let data = cn as usize + 24 + 8 * 8;
+        MdfChannel {
+            name: text(b, link(b, cn, 2)),
+            cn_type: b[data],
+            data_type: b[data + 2],
+            bit_offset: b[data + 3],
+            byte_offset: u32_at(b, data + 4),
+            bit_count: u32_at(b, data + 8),
+        }
+    }
+
+    fn frame(id: u32, extended: bool, fd: bool, data: &[u8], dlc: u8) -> CanFrame {
+        CanFrame {
+            channel_index: 0,
+            timestamp_ns: 0,
+            id,
+            extended,
+            tx: false,
+            fd,
+            brs: false,
+            esi: false,
+            remote: false,
+            dlc,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn writes_finalized_can_dataframe_group() {
+        let path = std::env::temp_dir().join(format!("cdl_mdf_{}.mf4", std::process::id()));
+        let start = Local::now();
+        let mut writer = Mdf4Writer::create(File::create(&path).unwrap(), start).unwrap();
+
+        let classic = frame(0x123, false, false, &[1, 2, 3], 3);
+        let mut fd = frame(0x18DA_F110, true, true, &(0..64).collect::<Vec<u8>>(), 15);
+        fd.brs = true;
+        fd.tx = true;
+        writer.write_frame(1, 1_500_000, &classic).unwrap();
+        writer.write_frame(1, 2_000_000_000, &fd).unwrap();
+        writer.finish().unwrap();
+
+        let b = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(&b[0..8], b"MDF     ");
+        assert_eq!(&b[8..16], b"4.10    ");
+        assert_eq!(u16_at(&b, 28), 410);
+        assert_eq!(u16_at(&b, 60), 0);
+
+        let hd = 64u64;
+        assert_eq!(&b[64..68], b"##HD");
+        assert_eq!(u64_at(&b, hd as usize + 72), start.timestamp_nanos_opt().unwrap() as u64);
+        assert_ne!(link(&b, hd, 1), 0);
+
+        let dg = link(&b, hd, 0);
+        assert_eq!(&b[dg as usize..dg as usize + 4], b"##DG");
+        assert_eq!(link(&b, dg, 0), 0);
+        let cg = link(&b, dg, 1);
+        assert_eq!(text(&b, link(&b, cg, 2)), "CAN_DataFrame");
+        let cg_data = cg as usize + 24 + 6 * 8;
+        assert_eq!(u64_at(&b, cg_data + 8), 2);
+        assert_eq!(u16_at(&b, cg_data + 16), CG_FLAG_BUS_EVENT | CG_FLAG_PLAIN_BUS_EVENT);
+        assert_eq!(u32_at(&b, cg_data + 24), RECORD_SIZE as u32);
+
+        let time_cn = link(&b, cg, 1);
+        let time = read_channel(&b, time_cn);
+        assert_eq!((time.name.as_str(), time.cn_type, time.data_type), ("Timestamp", CN_TYPE_MASTER, DT_FLOAT_LE));
+        let frame_cn = link(&b, time_cn, 0);
+        assert_eq!(read_channel(&b, frame_cn).name, "CAN_DataFrame");
+        let mut members = Vec::new();
+        let mut cn = link(&b, frame_cn, 1);
+        while cn != 0 {
+            members.push(read_channel(&b, cn));
+            cn = link(&b, cn, 0);
+        }
+        let names: Vec<&str> = members.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, FRAME_MEMBERS.iter().map(|m| m.name).collect::<Vec<_>>());
+
+        let dt = link(&b, dg, 2) as usize;
+        assert_eq!(&b[dt..dt + 4], b"##DT");
+        assert_eq!(u64_at(&b, dt + 8), 24 + 2 * RECORD_SIZE as u64);
+        assert_eq!(b.len(), dt + 24 + 2 * RECORD_SIZE);
+
+        let value = |record: &[u8], name: &str| {
+            let m = members.iter().find(|m| m.name == name).unwrap();
+            read_bits(record, m.byte_offset, m.bit_offset, m.bit_count)
+        };
+        let first = &b[dt + 24..dt + 24 + RECORD_SIZE];
+        let second = &b[dt + 24 + RECORD_SIZE..dt + 24 + 2 * RECORD_SIZE];
+        assert_eq!(f64::from_le_bytes(first[0..8].try_into().unwrap()), 0.0015);
+        assert_eq!(value(first, "CAN_DataFrame.BusChannel"), 1);
+        assert_eq!(value(first, "CAN_DataFrame.ID"), 0x123);
+        assert_eq!(value(first, "CAN_DataFrame.IDE"), 0);
+        assert_eq!(value(first, "CAN_DataFrame.DataLength"), 3);
+        assert_eq!(&first[DATA_BYTES_OFFSET..DATA_BYTES_OFFSET + 4], &[1, 2, 3, 0]);
+        assert_eq!(f64::from_le_bytes(second[0..8].try_into().unwrap()), 2.0);
+        assert_eq!(value(second, "CAN_DataFrame.ID"), 0x18DA_F110);
+        assert_eq!(value(second, "CAN_DataFrame.IDE"), 1);
+        assert_eq!(value(second, "CAN_DataFrame.DLC"), 15);
+        assert_eq!(value(second, "CAN_DataFrame.DataLength"), 64);
+        assert_eq!(value(second, "CAN_DataFrame.Dir"), 1);
+        assert_eq!(value(second, "CAN_DataFrame.EDL"), 1);
+        assert_eq!(value(second, "CAN_DataFrame.BRS"), 1);
+        assert_eq!(value(second, "CAN_DataFrame.ESI"), 0);
+        assert_eq!(second[DATA_BYTES_OFFSET + 63], 63);
+    }
+}
@@ -0,0 +1,327 @@
+// pcapng reading and comment blocks.
+// Reads the enhanced packet blocks of a dumpcap capture (host UTC timestamps in the resolution
+// of their interface) for the merged timeline, and appends comment-only packets - zero captured
+// bytes plus an opt_comment - to a finished capture, which Wireshark shows as packet comments.
+
+use chrono::{DateTime, Local, TimeZone};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+const PCAPNG_SHB: u32 = 0x0A0D_0D0A;
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_OPT_END: u16 = 0;
+const PCAPNG_OPT_COMMENT: u16 = 1;
+const PCAPNG_IF_TSRESOL: u16 = 9;
+const LINKTYPE_ETHERNET: u16 = 1;
+/// Interface timestamps without `if_tsresol` are in microseconds.
+const DEFAULT_UNITS_PER_SECOND: u64 = 1_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packet {
+    pub time: DateTime<Local>,
+    /// Length, addresses and ports, then the packet comment if there is one.
+    pub text: String,
+}
+
+struct PcapngReader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl PcapngReader<'_> {
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    /// Options as (code, value) from `start` to `end`.
+    fn options(&self, mut start: usize, end: usize) -> Vec<(u16, &[u8])> {
+        let mut options = Vec::new();
+        while start + 4 <= end {
+            let (Some(code), Some(length)) = (self.u16_at(start), self.u16_at(start + 2)) else {
+                break;
+            };
+            if code == PCAPNG_OPT_END {
+                break;
+            }
+            let length = usize::from(length);
+            let Some(value) = self.data.get(start + 4..(start + 4 + length).min(end)) else {
+                break;
+            };
+            options.push((code, value));
+            start += 4 + length.div_ceil(4) * 4;
+        }
+        options
+    }
+}
+
+/// Interfaces of the current section as (link type, timestamp units per second).
+type Interfaces = Vec<(u16, u64)>;
+
+/// Calls `block` with every complete block after the section header it belongs to has been
+/// read; a trailing partial block (capture killed mid-write) is ignored.
+fn walk(data: &[u8], mut block: impl FnMut(&PcapngReader, &Interfaces, u32, usize, usize)) -> Result<(), String> {
+    let mut reader = PcapngReader { data, big_endian: false };
+    let mut interfaces = Interfaces::new();
+    let mut offset = 0usize;
+
+    while offset + 12 <= data.len() {
+        let block_type = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap_or_default());
+        if block_type == PCAPNG_SHB {
+            let magic = u32::from_le_bytes(data[offset + 8..offset + 12].try_into().unwrap_or_default());
+            reader.big_endian = magic != PCAPNG_BYTE_ORDER_MAGIC;
+            interfaces.clear();
+        } else if offset == 0 {
+            return Err("not a pcapng file".to_string());
+        }
+        let block_type = reader.u32_at(offset).unwrap_or_default();
+        let length = reader.u32_at(offset + 4).unwrap_or_default() as usize;
+        if length < 12 || offset + length > data.len() {
+            break;
+        }
+        let body = offset + 8;
+        let end = offset + length - 4;
+        if block_type == PCAPNG_IDB {
+            let link_type = reader.u16_at(body).unwrap_or_default();
+            let units = reader
+                .options(body + 8, end)
+                .into_iter()
+                .find(|(code, value)| *code == PCAPNG_IF_TSRESOL && !value.is_empty())
+                .map_or(DEFAULT_UNITS_PER_SECOND, |(_, value)| tsresol_units(value[0]));
+            interfaces.push((link_type, units));
+        }
+        block(&reader, &interfaces, block_type, body, end);
+        offset += length;
+    }
+    Ok(())
+}
+
+/// Units per second of an interface timestamp (`if_tsresol`).
+fn tsresol_units(value: u8) -> u64 {
+    let exponent = u32::from(value & 0x7F);
+    if value & 0x80 == 0 {
+        10u64.checked_pow(exponent).unwrap_or(u64::MAX)
+    } else {
+        1u64.checked_shl(exponent).unwrap_or(u64::MAX)
+    }
+}
+
+fn describe_ethernet(packet: &[u8], original_len: u32) -> String {
+    let mut text = format!("len={}", original_len);
+    if packet.len() < 14 {
+        return text;
+    }
+    let ethertype = u16::from_be_bytes([packet[12], packet[13]]);
+    let mac = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":");
+    text.push_str(&format!(" {} -> {} ethertype=0x{:04X}", mac(&packet[6..12]), mac(&packet[0..6]), ethertype));
+    let ip = &packet[14..];
+    if ethertype == 0x0800 && ip.len() >= 20 {
+        let header_len = usize::from(ip[0] & 0x0F) * 4;
+        let protocol = ip[9];
+        let addr = |b: &[u8]| format!("{}.{}.{}.{}", b[0], b[1], b[2], b[3]);
+        text.push_str(&format!(" IPv4 {} -> {}", addr(&ip[12..16]), addr(&ip[16..20])));
+        let ports = ip
+            .get(header_len..header_len + 4)
+            .map(|p| (u16::from_be_bytes([p[0], p[1]]), u16::from_be_bytes([p[2], p[3]])));
+        match (protocol, ports) {
+            (6, Some((src, dst))) => text.push_str(&format!(" TCP {} -> {}", src, dst)),
+            (17, Some((src, dst))) => text.push_str(&format!(" UDP {} -> {}", src, dst)),
+            _ => text.push_str(&format!(" proto={}", protocol)),
+        }
+    }
+    text
+}
+
+/// Enhanced packet blocks of a pcapng file, with their comments, in file order.
+pub fn read_packets(data: &[u8]) -> Result<Vec<Packet>, String> {
+    let mut packets = Vec::new();
+    walk(data, |reader, interfaces, block_type, body, end| {
+        if block_type != PCAPNG_EPB {
+            return;
+        }
+        let interface = reader.u32_at(body).unwrap_or_default() as usize;
+        let (link_type, units) = interfaces
+            .get(interface)
+            .copied()
+            .unwrap_or((LINKTYPE_ETHERNET, DEFAULT_UNITS_PER_SECOND));
+        let high = u64::from(reader.u32_at(body + 4).unwrap_or_default());
+        let low = u64::from(reader.u32_at(body + 8).unwrap_or_default());
+        let captured = reader.u32_at(body + 12).unwrap_or_default() as usize;
+        let original = reader.u32_at(body + 16).unwrap_or_default();
+        let packet_start = body + 20;
+        let packet = data.get(packet_start..(packet_start + captured).min(end)).unwrap_or_default();
+
+        let ticks = (high << 32) | low;
+        let nanos = (u128::from(ticks) * 1_000_000_000 / u128::from(units.max(1))) as i64;
+        let mut text = if link_type == LINKTYPE_ETHERNET {
+            describe_ethernet(packet, original)
+        } else {
+            format!("len={} linktype={}", original, link_type)
+        };
+        for (code, value) in reader.options(packet_start + captured.div_ceil(4) * 4, end) {
+            if code == PCAPNG_OPT_COMMENT {
+                text.push_str(&format!(" comment: {}", String::from_utf8_lossy(value)));
+            }
+        }
+        packets.push(Packet {
+            time: Local.timestamp_nanos(nanos),
+            text,
+        });
+    })?;
+    Ok(packets)
+}
+
+struct PcapngWriter {
+    big_endian: bool,
+}
+
+impl PcapngWriter {
+    fn u16(&self, value: u16) -> [u8; 2] {
+        if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+    }
+
+    fn u32(&self, value: u32) -> [u8; 4] {
+        if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+    }
+
+    fn option(&self, code: u16, value: &[u8]) -> Vec<u8> {
+        let mut bytes = [&self.u16(code)[..], &self.u16(value.len() as u16), value].concat();
+        bytes.resize(bytes.len().div_ceil(4) * 4, 0);
+        bytes
+    }
+
+    fn block(&self, block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = self.u32((12 + body.len()) as u32);
+        [&self.u32(block_type)[..], &length, body, &length].concat()
+    }
+
+    /// An enhanced packet block on interface 0 without packet bytes, carrying only `comment`.
+    fn comment_block(&self, ticks: u64, comment: &str) -> Vec<u8> {
+        // Option values are limited to 65535 bytes.
+        let comment = &comment.as_bytes()[..comment.len().min(usize::from(u16::MAX))];
+        let body = [
+            &self.u32(0)[..],
+            &self.u32((ticks >> 32) as u32),
+            &self.u32(ticks as u32),
+            &self.u32(0),
+            &self.u32(0),
+            &self.option(PCAPNG_OPT_COMMENT, comment),
+            &self.option(PCAPNG_OPT_END, &[]),
+        ]
+        .concat();
+        self.block(PCAPNG_EPB, &body)
+    }
+}
+
+/// Appends one comment packet per `(host time, text)` to the last section of a finished
+/// capture, in that section's byte order and interface 0 timestamp resolution.
+pub fn append_comments(path: &Path, comments: &[(DateTime<Local>, String)]) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    let mut last_section = None;
+    let mut complete_len = 0;
+    walk(&data, |reader, interfaces, _, _, end| {
+        last_section = interfaces.first().map(|(_, units)| (reader.big_endian, *units));
+        complete_len = end + 4;
+    })
+    .map_err(|e| format!("'{}': {}", path.display(), e))?;
+    let Some((big_endian, units)) = last_section else {
+        return Err(format!("'{}' has no capture interface", path.display()));
+    };
+
+    let writer = PcapngWriter { big_endian };
+    let mut blocks = Vec::new();
+    for (time, text) in comments {
+        let nanos = time.timestamp_nanos_opt().unwrap_or_default().max(0) as u128;
+        let ticks = (nanos * u128::from(units) / 1_000_000_000) as u64;
+        blocks.extend(writer.comment_block(ticks, text));
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    // A partial block left by a killed dumpcap would hide everything after it; write over it.
+    file.set_len(complete_len as u64)
+        .and_then(|_| {
+            use std::io::{Seek, SeekFrom};
+            file.seek(SeekFrom::End(0))?;
+            file.write_all(&blocks)
+        })
+        .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+}
+
+/// Little-endian capture with one Ethernet interface (nanosecond resolution) and one UDP packet.
+#[cfg(test)]
+pub fn test_capture(timestamp: DateTime<Local>, comment: &str) -> Vec<u8> {
+    let writer = PcapngWriter { big_endian: false };
+    let shb = [&writer.u32(PCAPNG_BYTE_ORDER_MAGIC)[..], &writer.u16(1), &writer.u16(0), &u64::MAX.to_le_bytes()].concat();
+    let idb = [
+        &writer.u16(LINKTYPE_ETHERNET)[..],
+        &writer.u16(0),
+        &writer.u32(0),
+        &writer.option(PCAPNG_IF_TSRESOL, &[9]),
+        &writer.option(PCAPNG_OPT_END, &[]),
+    ]
+    .concat();
+    let mut packet = vec![0u8; 42];
+    packet[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+    packet[14] = 0x45;
+    packet[23] = 17;
+    packet[26..30].copy_from_slice(&[10, 0, 0, 1]);
+    packet[30..34].copy_from_slice(&[10, 0, 0, 2]);
+    packet[34..36].copy_from_slice(&5000u16.to_be_bytes());
+    packet[36..38].copy_from_slice(&6000u16.to_be_bytes());
+    let ticks = timestamp.timestamp_nanos_opt().unwrap() as u64;
+    let mut epb = [
+        &writer.u32(0)[..],
+        &writer.u32((ticks >> 32) as u32),
+        &writer.u32(ticks as u32),
+        &writer.u32(packet.len() as u32),
+        &writer.u32(packet.len() as u32),
+        &packet,
+    ]
+    .concat();
+    epb.resize(epb.len().div_ceil(4) * 4, 0);
+    epb.extend(writer.option(PCAPNG_OPT_COMMENT, comment.as_bytes()));
+    epb.extend(writer.option(PCAPNG_OPT_END, &[]));
+    [writer.block(PCAPNG_SHB, &shb), writer.block(PCAPNG_IDB, &idb), writer.block(PCAPNG_EPB, &epb)].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn appends_comment_packets_after_a_partial_block() {
+        let path = std::env::temp_dir().join(format!("cdl_pcapng_{}.pcapng", std::process::id()));
+        let start = Local.with_ymd_and_hms(2024, 5, 2, 10, 0, 0).unwrap();
+        let mut capture = test_capture(start, "first");
+        capture.extend([6, 0, 0, 0, 64, 0]);
+        std::fs::write(&path, capture).unwrap();
+
+        let marker = start + TimeDelta::microseconds(1_500_250);
+        append_comments(&path, &[(marker, "CDL_MARKER #1 brake noise".to_string())]).unwrap();
+
+        let packets = read_packets(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].time, start);
+        assert!(packets[0].text.ends_with("UDP 5000 -> 6000 comment: first"), "{}", packets[0].text);
+        assert_eq!(
+            packets[1],
+            Packet {
+                time: marker,
+                text: "len=0 comment: CDL_MARKER #1 brake noise".to_string()
+            }
+        );
+        assert!(read_packets(b"not a capture").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
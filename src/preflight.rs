@@ -0,0 +1,187 @@
+// Source pre-flight checks.
+// Quick checks that each source of a session can start, run before anything is started: the QNX
+// target answers ssh, the Android device is online, dumpcap knows the interface and the CAN
+// settings load. Every failure says why, so a refused start can be fixed without guessing.
+
+use crate::can_config::CanNetworkConfig;
+use crate::can_sim::SimulatedBackend;
+use crate::capture_session::{CanSettings, SessionConfig};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+const SSH_CONNECT_TIMEOUT_S: u32 = 5;
+
+/// `ssh` with key authentication only, so a missing key fails instead of prompting.
+pub fn check_qnx(ip: &str) -> Result<(), String> {
+    let timeout = format!("ConnectTimeout={}", SSH_CONNECT_TIMEOUT_S);
+    let output = Command::new("ssh")
+        .args(["-o", "BatchMode=yes", "-o", &timeout, &format!("root@{}", ip), "echo ok"])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("ssh not available: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(ssh_reason(ip, failure("ssh", &output)))
+    }
+}
+
+/// Names the cause of an ssh failure, so a dead link reads differently from a missing key.
+fn ssh_reason(ip: &str, detail: String) -> String {
+    let lower = detail.to_lowercase();
+    if lower.contains("timed out") {
+        format!("timeout: no answer from {} within {} s", ip, SSH_CONNECT_TIMEOUT_S)
+    } else if lower.contains("permission denied") || lower.contains("host key verification failed") {
+        format!("auth failure: {}", detail)
+    } else {
+        detail
+    }
+}
+
+/// The device with `serial` (any device if empty) is listed by `adb devices` as `device`.
+pub fn check_android(serial: &str) -> Result<(), String> {
+    let output = Command::new("adb")
+        .arg("devices")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("adb not available: {}", e))?;
+    if !output.status.success() {
+        return Err(failure("adb", &output));
+    }
+    device_state(&String::from_utf8_lossy(&output.stdout), serial)
+}
+
+/// Looks up `serial` in `adb devices` output; the serial must match exactly, as `adb -s` needs.
+fn device_state(devices: &str, serial: &str) -> Result<(), String> {
+    let devices: Vec<(&str, &str)> = devices
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.trim().split_once(char::is_whitespace))
+        .map(|(id, state)| (id, state.trim()))
+        .collect();
+    match devices.iter().find(|(id, _)| serial.is_empty() || *id == serial) {
+        Some((_, "device")) => Ok(()),
+        Some((id, "unauthorized")) => Err(format!("auth failure: {} is unauthorized (allow USB debugging on the device)", id)),
+        Some((id, state)) => Err(format!("{} is {}", id, state)),
+        None if serial.is_empty() => Err("no device connected".to_string()),
+        None => Err(format!("{} not connected", serial)),
+    }
+}
+
+/// `dumpcap -D` lists `iface`.
+pub fn check_interface(iface: &str) -> Result<(), String> {
+    let output = Command::new("dumpcap")
+        .arg("-D")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("dumpcap not available: {}", e))?;
+    if !output.status.success() {
+        return Err(failure("dumpcap", &output));
+    }
+    if lists_interface(&String::from_utf8_lossy(&output.stdout), iface) {
+        Ok(())
+    } else {
+        Err(format!("interface '{}' not listed by dumpcap -D", iface))
+    }
+}
+
+/// Looks up `iface` in `dumpcap -D` lines (`N. <name> (<description>)`); the name or the
+/// description must match exactly, as `dumpcap -i` needs.
+fn lists_interface(interfaces: &str, iface: &str) -> bool {
+    interfaces
+        .lines()
+        .filter_map(|line| line.trim().split_once(". "))
+        .filter(|(index, _)| index.parse::<u32>().is_ok())
+        .any(|(_, entry)| match entry.split_once(" (") {
+            Some((name, description)) => name == iface || description.strip_suffix(')') == Some(iface),
+            None => entry == iface,
+        })
+}
+
+/// The network config and the simulation script load.
+pub fn check_can(settings: &CanSettings) -> Result<(), String> {
+    if let Some(path) = &settings.config {
+        CanNetworkConfig::load(Path::new(path))?;
+    }
+    if let Some(path) = settings.sim_script.as_deref().filter(|_| settings.backend == "sim") {
+        SimulatedBackend::from_script_file(path)?;
+    }
+    Ok(())
+}
+
+/// Checks every source of `config` in parallel; results in the order of `config.sources`.
+pub fn run(config: &SessionConfig) -> Vec<(String, Result<(), String>)> {
+    thread::scope(|scope| {
+        let checks: Vec<_> = config
+            .sources
+            .iter()
+            .map(|source| {
+                let handle = scope.spawn(move || match source.as_str() {
+                    "qnx" => check_qnx(config.qnx_ip.trim()),
+                    "android" => check_android(config.android_serial.trim()),
+                    "ethernet" => check_interface(config.interface.trim()),
+                    _ => check_can(&config.can),
+                });
+                (source, handle)
+            })
+            .collect();
+        checks
+            .into_iter()
+            .map(|(source, handle)| {
+                let result = handle.join().unwrap_or_else(|_| Err("check panicked".to_string()));
+                (source.clone(), result)
+            })
+            .collect()
+    })
+}
+
+/// Last line of stderr, or the exit code.
+fn failure(program: &str, output: &Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match stderr.lines().map(str::trim).rfind(|line| !line.is_empty()) {
+        Some(line) => line.to_string(),
+        None => format!("{} exited with {}", program, output.status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_failures_name_their_cause() {
+        let reason = |stderr: &str| ssh_reason("10.0.0.2", stderr.to_string());
+        assert_eq!(
+            reason("ssh: connect to host 10.0.0.2 port 22: Connection timed out"),
+            "timeout: no answer from 10.0.0.2 within 5 s"
+        );
+        assert!(reason("root@10.0.0.2: Permission denied (publickey,password).").starts_with("auth failure: "));
+        assert_eq!(
+            reason("ssh: connect to host 10.0.0.2 port 22: Connection refused"),
+            "ssh: connect to host 10.0.0.2 port 22: Connection refused"
+        );
+    }
+
+    #[test]
+    fn android_serial_must_match_exactly() {
+        let devices = "List of devices attached\nemulator-5554\tdevice\n7d186538\tunauthorized\n\n";
+        assert!(device_state(devices, "").is_ok());
+        assert!(device_state(devices, "emulator-5554").is_ok());
+        assert_eq!(device_state(devices, "emulator-55").unwrap_err(), "emulator-55 not connected");
+        assert!(device_state(devices, "7d186538").unwrap_err().starts_with("auth failure: "));
+        assert_eq!(device_state("List of devices attached\n\n", "").unwrap_err(), "no device connected");
+    }
+
+    #[test]
+    fn interface_must_match_exactly() {
+        let interfaces = "1. eth10\n2. \\Device\\NPF_{3E5B0C2A-6A1B-4F3B-9C5D-2B1D7E4A9F10} (Ethernet 2)\n3. any (Pseudo-device that captures on all interfaces)\n";
+        assert!(lists_interface(interfaces, "eth10"));
+        assert!(!lists_interface(interfaces, "eth1"));
+        assert!(lists_interface(interfaces, "Ethernet 2"));
+        assert!(lists_interface(interfaces, "\\Device\\NPF_{3E5B0C2A-6A1B-4F3B-9C5D-2B1D7E4A9F10}"));
+        assert!(!lists_interface(interfaces, "Ethernet"));
+        assert!(!lists_interface(interfaces, "Pseudo-device"));
+        assert!(!lists_interface(interfaces, "1"));
+    }
+}
@@ -0,0 +1,192 @@
+// Cooperative stop of capture processes.
+// Child processes (ssh, adb, dumpcap) first get an interrupt - CTRL_BREAK on Windows, SIGINT
+// elsewhere - so they can flush and close their output (dumpcap finishes the last pcapng block),
+// and are killed only if they are still running after the timeout. The `--test-can` capture
+// commands install an interrupt handler so Ctrl+C ends the capture like the duration limit does.
+
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long stopped children get to exit on their own before they are killed.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopOutcome {
+    /// Exited after the interrupt (or before it); the exit code if it had one.
+    Exited(Option<i32>),
+    /// Still running at the timeout, or the interrupt could not be sent.
+    Killed,
+}
+
+#[cfg(windows)]
+mod os {
+    use std::os::windows::process::CommandExt;
+    use std::process::{Child, Command};
+
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+
+    unsafe extern "system" {
+        fn GenerateConsoleCtrlEvent(ctrl_event: u32, process_group_id: u32) -> i32;
+        fn SetConsoleCtrlHandler(handler: Option<unsafe extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+
+    /// A child in its own process group can get CTRL_BREAK without it reaching our console.
+    pub fn make_interruptible(command: &mut Command) {
+        command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+
+    pub fn interrupt(child: &Child) -> Result<(), String> {
+        if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, child.id()) } == 0 {
+            return Err(format!("GenerateConsoleCtrlEvent failed: {}", std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    unsafe extern "system" fn on_ctrl(ctrl_type: u32) -> i32 {
+        if ctrl_type != CTRL_C_EVENT && ctrl_type != CTRL_BREAK_EVENT {
+            return 0;
+        }
+        // A second Ctrl+C falls through to the default handler and ends the process.
+        i32::from(!super::INTERRUPTED.swap(true, std::sync::atomic::Ordering::Relaxed))
+    }
+
+    pub fn install_interrupt_handler() {
+        unsafe {
+            SetConsoleCtrlHandler(Some(on_ctrl), 1);
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod os {
+    use std::process::{Child, Command};
+
+    const SIGINT: i32 = 2;
+    const SIG_DFL: usize = 0;
+
+    unsafe extern "C" {
+        fn kill(pid: i32, sig: i32) -> i32;
+        fn signal(signum: i32, handler: usize) -> usize;
+    }
+
+    pub fn make_interruptible(_command: &mut Command) {}
+
+    pub fn interrupt(child: &Child) -> Result<(), String> {
+        if unsafe { kill(child.id() as i32, SIGINT) } != 0 {
+            return Err(format!("kill failed: {}", std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    extern "C" fn on_sigint(_signum: i32) {
+        super::INTERRUPTED.store(true, std::sync::atomic::Ordering::Relaxed);
+        // A second Ctrl+C ends the process.
+        unsafe {
+            signal(SIGINT, SIG_DFL);
+        }
+    }
+
+    pub fn install_interrupt_handler() {
+        unsafe {
+            signal(SIGINT, on_sigint as extern "C" fn(i32) as usize);
+        }
+    }
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Prepares `command` so the child can be stopped with `stop_children`.
+pub fn interruptible(command: &mut Command) -> &mut Command {
+    os::make_interruptible(command);
+    command
+}
+
+/// Makes Ctrl+C set `interrupted()` instead of ending the process; a second Ctrl+C still does.
+pub fn install_interrupt_handler() {
+    os::install_interrupt_handler();
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Interrupts every child, waits up to `timeout` for all of them together and kills the ones
+/// still running. Returns how each named child ended.
+pub fn stop_children(children: Vec<(String, Child)>, timeout: Duration) -> Vec<(String, StopOutcome)> {
+    let mut pending = Vec::new();
+    let mut outcomes = Vec::new();
+    for (name, mut child) in children {
+        match child.try_wait() {
+            Ok(Some(status)) => outcomes.push((name, StopOutcome::Exited(status.code()))),
+            _ if os::interrupt(&child).is_ok() => pending.push((name, child)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                outcomes.push((name, StopOutcome::Killed));
+            }
+        }
+    }
+
+    let deadline = Instant::now() + timeout;
+    while !pending.is_empty() {
+        let timed_out = Instant::now() >= deadline;
+        let mut index = 0;
+        while index < pending.len() {
+            let exited = match pending[index].1.try_wait() {
+                Ok(Some(status)) => Some(StopOutcome::Exited(status.code())),
+                Ok(None) if !timed_out => None,
+                _ => {
+                    let _ = pending[index].1.kill();
+                    let _ = pending[index].1.wait();
+                    Some(StopOutcome::Killed)
+                }
+            };
+            match exited {
+                Some(outcome) => {
+                    let (name, _) = pending.swap_remove(index);
+                    outcomes.push((name, outcome));
+                }
+                None => index += 1,
+            }
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    outcomes
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn spawn(script: &str) -> Child {
+        interruptible(Command::new("sh").arg("-c").arg(script)).spawn().unwrap()
+    }
+
+    #[test]
+    fn interrupts_before_killing() {
+        let children = vec![
+            ("graceful".to_string(), spawn("trap 'exit 3' INT; while true; do sleep 0.05; done")),
+            ("stubborn".to_string(), spawn("trap '' INT; while true; do sleep 0.05; done")),
+            ("done".to_string(), spawn("exit 0")),
+        ];
+        thread::sleep(Duration::from_millis(200));
+
+        let started = Instant::now();
+        let mut outcomes = stop_children(children, Duration::from_millis(500));
+        outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(
+            outcomes,
+            vec![
+                ("done".to_string(), StopOutcome::Exited(Some(0))),
+                ("graceful".to_string(), StopOutcome::Exited(Some(3))),
+                ("stubborn".to_string(), StopOutcome::Killed),
+            ]
+        );
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+}
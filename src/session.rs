@@ -0,0 +1,336 @@
+// Session manifest.
+// Every session folder gets a session.json describing the recording: who ran it where, which
+// sources were enabled with which targets, the CAN settings and channel mapping, start/stop
+// times and how each source ended. It is written when the session starts and finalised at
+// stop; `--show-session <folder>` and the GUI read it back.
+
+use crate::can_capture::{CaptureSummary, CapturedNetwork, ChannelMapping, SkippedChannel};
+use crate::can_cli::{EXIT_FAILED, EXIT_OK, EXIT_USAGE};
+use crate::can_layout::LayoutCheck;
+use chrono::{Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILE: &str = "session.json";
+/// Bumped whenever a field changes meaning; readers refuse manifests from a newer version.
+pub const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStatus {
+    /// Started; the session has not been stopped (or the logger did not survive until stop).
+    Running,
+    /// Ended at stop, by itself or after the stop interrupt.
+    Stopped,
+    /// Still running at the stop timeout and killed; its file may be truncated.
+    Killed,
+    /// Did not start, or failed during the session.
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SourceRecord {
+    /// `qnx`, `android`, `ethernet` or `can`.
+    pub name: String,
+    /// QNX IP, Android serial, ethernet interface or CAN app name.
+    pub target: Option<String>,
+    /// Output files relative to the session folder.
+    pub files: Vec<String>,
+    pub status: SourceStatus,
+    pub exit_code: Option<i32>,
+    pub detail: Option<String>,
+    /// Times the stream was reconnected after it dropped.
+    #[serde(default)]
+    pub reconnects: u32,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CanSessionInfo {
+    pub app_name: String,
+    pub iface_version: u32,
+    pub log_format: String,
+    pub bus_mode: String,
+    pub refuse_on_drift: bool,
+    /// Network config file in use, if any.
+    pub config: Option<String>,
+    /// App channels assigned to hardware when the session started.
+    pub mapping: Vec<ChannelMapping>,
+    pub layout: Vec<LayoutCheck>,
+    /// Filled at stop.
+    pub captured: Vec<CapturedNetwork>,
+    pub skipped: Vec<SkippedChannel>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionManifest {
+    pub version: u32,
+    pub tool_version: String,
+    pub session_name: String,
+    pub operator: String,
+    pub host: String,
+    pub started_at: String,
+    pub stopped_at: Option<String>,
+    pub sources: Vec<SourceRecord>,
+    pub can: Option<CanSessionInfo>,
+    /// Why the start was rolled back, for a session whose required source failed to start.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aborted: Option<String>,
+}
+
+fn now() -> String {
+    Local::now().to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+fn env_or_unknown(names: &[&str]) -> String {
+    names
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+impl SessionManifest {
+    pub fn new(session_name: &str) -> Self {
+        SessionManifest {
+            version: MANIFEST_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            session_name: session_name.to_string(),
+            operator: env_or_unknown(&["USERNAME", "USER"]),
+            host: env_or_unknown(&["COMPUTERNAME", "HOSTNAME"]),
+            started_at: now(),
+            stopped_at: None,
+            sources: Vec::new(),
+            can: None,
+            aborted: None,
+        }
+    }
+
+    pub fn add_source(&mut self, name: &str, target: Option<&str>, files: &[&str], started: Result<(), String>) {
+        let (status, detail) = match started {
+            Ok(()) => (SourceStatus::Running, None),
+            Err(e) => (SourceStatus::Failed, Some(e)),
+        };
+        self.sources.push(SourceRecord {
+            name: name.to_string(),
+            target: target.map(str::to_string),
+            files: files.iter().map(|f| f.to_string()).collect(),
+            status,
+            exit_code: None,
+            detail,
+            reconnects: 0,
+        });
+    }
+
+    pub fn source_mut(&mut self, name: &str) -> Option<&mut SourceRecord> {
+        self.sources.iter_mut().find(|s| s.name == name)
+    }
+
+    /// Records how the CAN capture ended: the per-network counts, or the error.
+    pub fn finish_can(&mut self, summary: Option<&CaptureSummary>, error: Option<&str>) {
+        if let (Some(can), Some(summary)) = (&mut self.can, summary) {
+            can.captured = summary.networks.clone();
+            can.skipped = summary.skipped.clone();
+        }
+        if let Some(source) = self.source_mut("can")
+            && source.status == SourceStatus::Running
+        {
+            if let Some(summary) = summary {
+                source.files = summary.networks.iter().map(|n| relative_can_file(&n.file)).collect();
+            }
+            match error {
+                Some(e) => {
+                    source.status = SourceStatus::Failed;
+                    source.detail = Some(e.to_string());
+                }
+                None => source.status = SourceStatus::Stopped,
+            }
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.stopped_at = Some(now());
+    }
+
+    /// Writes `session.json` into `folder` (through a temporary file, so a reader never sees half a manifest).
+    pub fn write(&self, folder: &Path) -> Result<PathBuf, String> {
+        let path = folder.join(MANIFEST_FILE);
+        let temp = folder.join(format!("{}.tmp", MANIFEST_FILE));
+        let json = serde_json::to_string_pretty(self).map_err(|e| format!("Failed to encode session manifest: {}", e))?;
+        fs::write(&temp, json).map_err(|e| format!("Failed to write '{}': {}", temp.display(), e))?;
+        fs::rename(&temp, &path).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    /// Reads the manifest of a session folder, or a manifest file given directly.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let file = if path.is_dir() { path.join(MANIFEST_FILE) } else { path.to_path_buf() };
+        let text = fs::read_to_string(&file).map_err(|e| format!("Failed to read '{}': {}", file.display(), e))?;
+        let value: serde_json::Value =
+            serde_json::from_str(&text).map_err(|e| format!("Invalid session manifest '{}': {}", file.display(), e))?;
+        let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+        if version > u64::from(MANIFEST_VERSION) {
+            return Err(format!(
+                "Session manifest '{}' has version {}; this build reads up to version {}",
+                file.display(),
+                version,
+                MANIFEST_VERSION
+            ));
+        }
+        serde_json::from_value(value).map_err(|e| format!("Invalid session manifest '{}': {}", file.display(), e))
+    }
+
+    /// Human-readable summary, one line per entry.
+    pub fn summary_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Session {} (manifest v{}, logger {})", self.session_name, self.version, self.tool_version),
+            format!("Recorded by {} on {}", self.operator, self.host),
+            format!(
+                "Started {}, stopped {}",
+                self.started_at,
+                self.stopped_at.as_deref().unwrap_or("- (not finalised)")
+            ),
+        ];
+        if let Some(reason) = &self.aborted {
+            lines.push(format!("Aborted at start: {}", reason));
+        }
+        for source in &self.sources {
+            let mut line = format!(
+                "{}: {:?}{}",
+                source.name,
+                source.status,
+                source.target.as_ref().map(|t| format!(" [{}]", t)).unwrap_or_default()
+            );
+            if let Some(code) = source.exit_code {
+                line.push_str(&format!(", exit code {}", code));
+            }
+            if source.reconnects > 0 {
+                line.push_str(&format!(", reconnected {} times", source.reconnects));
+            }
+            if let Some(detail) = &source.detail {
+                line.push_str(&format!(", {}", detail));
+            }
+            if !source.files.is_empty() {
+                line.push_str(&format!(" -> {}", source.files.join(", ")));
+            }
+            lines.push(line);
+        }
+        if let Some(can) = &self.can {
+            lines.push(format!(
+                "CAN app '{}', iface {}, {} {}, config {}",
+                can.app_name,
+                can.iface_version,
+                can.log_format,
+                can.bus_mode,
+                can.config.as_deref().unwrap_or("-")
+            ));
+            for mapping in &can.mapping {
+                lines.push(format!(
+                    "  ch={} hwType={} hwIndex={} hwChannel={} -> {}",
+                    mapping.channel,
+                    mapping.hw.hw_type,
+                    mapping.hw.hw_index,
+                    mapping.hw.hw_channel,
+                    mapping.network.as_deref().unwrap_or("UNMAPPED")
+                ));
+            }
+            for network in &can.captured {
+                lines.push(format!(
+                    "  {}: {} frames, {} error frames, {}",
+                    network.network, network.frames, network.error_frames, network.participation
+                ));
+            }
+        }
+        lines
+    }
+}
+
+/// CAN file paths are relative to the session folder (`CAN_LOGS/<network>.<ext>`).
+fn relative_can_file(file: &str) -> String {
+    let name = Path::new(file).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    format!("CAN_LOGS/{}", name)
+}
+
+/// `--show-session <folder|session.json> [--json]`: prints a session manifest.
+pub fn run_show(args: &[String]) -> i32 {
+    let Some(path) = args.iter().position(|a| a == "--show-session").and_then(|i| args.get(i + 1)) else {
+        println!("--show-session requires a session folder or {}", MANIFEST_FILE);
+        return EXIT_USAGE;
+    };
+    match SessionManifest::read(Path::new(path)) {
+        Ok(manifest) => {
+            if args.iter().any(|a| a == "--json") {
+                println!("{}", serde_json::to_string_pretty(&manifest).unwrap_or_default());
+            } else {
+                for line in manifest.summary_lines() {
+                    println!("{}", line);
+                }
+            }
+            EXIT_OK
+        }
+        Err(e) => {
+            println!("{}", e);
+            EXIT_FAILED
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_refuses_newer_versions() {
+        let dir = std::env::temp_dir().join(format!("cdl_session_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut manifest = SessionManifest::new("bench_run");
+        manifest.add_source("qnx", Some("192.168.164.115"), &["qnx.log"], Ok(()));
+        manifest.add_source("ethernet", Some("eth0"), &["ethernet.pcapng"], Err("dumpcap not found".to_string()));
+        manifest.add_source("can", Some("CANoe"), &[], Ok(()));
+        manifest.can = Some(CanSessionInfo::default());
+        manifest.write(&dir).unwrap();
+        assert_eq!(SessionManifest::read(&dir).unwrap().stopped_at, None);
+
+        let summary = CaptureSummary {
+            output_dir: dir.join("CAN_LOGS").display().to_string(),
+            format: "asc".to_string(),
+            networks: vec![CapturedNetwork {
+                channel: 1,
+                app_channel: 0,
+                network: "FD_CANW".to_string(),
+                file: dir.join("CAN_LOGS").join("FD_CANW.asc").display().to_string(),
+                frames: 12,
+                error_frames: 0,
+                participation: "active (normal output mode)".to_string(),
+            }],
+            skipped: Vec::new(),
+            error: None,
+        };
+        manifest.finish_can(Some(&summary), None);
+        manifest.source_mut("qnx").unwrap().status = SourceStatus::Killed;
+        manifest.finish();
+        manifest.write(&dir).unwrap();
+
+        let read = SessionManifest::read(&dir.join(MANIFEST_FILE)).unwrap();
+        assert!(read.stopped_at.is_some());
+        let states: Vec<(&str, SourceStatus)> = read.sources.iter().map(|s| (s.name.as_str(), s.status)).collect();
+        assert_eq!(
+            states,
+            [
+                ("qnx", SourceStatus::Killed),
+                ("ethernet", SourceStatus::Failed),
+                ("can", SourceStatus::Stopped)
+            ]
+        );
+        assert_eq!(read.sources[2].files, ["CAN_LOGS/FD_CANW.asc"]);
+        assert_eq!(read.can.unwrap().captured[0].frames, 12);
+        assert!(read.sources.iter().all(|s| s.name != "android"));
+
+        let mut json: serde_json::Value = serde_json::from_str(&fs::read_to_string(dir.join(MANIFEST_FILE)).unwrap()).unwrap();
+        json["version"] = serde_json::json!(MANIFEST_VERSION + 1);
+        fs::write(dir.join(MANIFEST_FILE), json.to_string()).unwrap();
+        assert!(SessionManifest::read(&dir).unwrap_err().contains("this build reads up to version 1"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
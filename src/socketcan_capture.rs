@@ -0,0 +1,528 @@
+// CAN capture backend for Linux SocketCAN (can0, vcan0, PEAK/Kvaser via kernel drivers).
+// Only compiled with the `socketcan` feature on Linux. App channel N is the N-th CAN interface,
+// either from `--can-ifaces` or every interface of type ARPHRD_CAN ordered by ifindex.
+// Bit timing is owned by the kernel (`ip link set canX type can ...`), so this backend never
+// reconfigures the bus.
+
+use crate::can_backend::{
+    CanAccess, CanBackend, CanBusMode, CanChannelInfo, CanEvent, CanFrame, CanHwChannel, CanPort, CanStatusError,
+};
+use crate::can_config::CanBusParams;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::time::Duration;
+
+const ARPHRD_CAN: u32 = 280;
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+const CAN_RTR_FLAG: u32 = 0x4000_0000;
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+const CAN_ERR_MASK: u32 = 0x1FFF_FFFF;
+const CANFD_BRS: u8 = 0x01;
+const CANFD_ESI: u8 = 0x02;
+const CAN_MTU: usize = 16;
+const CANFD_MTU: usize = 72;
+const CAN_RAW: libc::c_int = 1;
+const SOL_CAN_RAW: libc::c_int = 101;
+const CAN_RAW_ERR_FILTER: libc::c_int = 2;
+const CAN_RAW_FD_FRAMES: libc::c_int = 5;
+
+#[repr(C)]
+struct SockaddrCan {
+    can_family: libc::sa_family_t,
+    can_ifindex: libc::c_int,
+    can_addr: [u64; 2],
+}
+
+#[repr(C)]
+struct CanFdFrameRaw {
+    can_id: u32,
+    len: u8,
+    flags: u8,
+    res0: u8,
+    res1: u8,
+    data: [u8; 64],
+}
+
+struct SocketCanInterface {
+    name: String,
+    ifindex: u32,
+}
+
+pub struct SocketCanBackend {
+    interfaces: Vec<SocketCanInterface>,
+}
+
+impl SocketCanBackend {
+    /// `interfaces` is a comma separated list (`can0,vcan0`); `None` picks up every CAN interface.
+    pub fn open(interfaces: Option<&str>) -> Result<Self, String> {
+        let interfaces = match interfaces {
+            Some(list) => {
+                let mut resolved = Vec::new();
+                for name in list.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+                    let c_name = CString::new(name).map_err(|_| format!("Invalid interface name '{}'", name))?;
+                    let ifindex = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+                    if ifindex == 0 {
+                        return Err(format!(
+                            "SocketCAN interface '{}' not found: {}",
+                            name,
+                            io::Error::last_os_error()
+                        ));
+                    }
+                    resolved.push(SocketCanInterface {
+                        name: name.to_string(),
+                        ifindex,
+                    });
+                }
+                resolved
+            }
+            None => discover_can_interfaces(),
+        };
+
+        if interfaces.is_empty() {
+            return Err("No SocketCAN interfaces found (bring up can0 or create vcan0)".to_string());
+        }
+        if interfaces.len() > 64 {
+            return Err(format!("Too many SocketCAN interfaces ({}, max 64)", interfaces.len()));
+        }
+
+        Ok(Self { interfaces })
+    }
+
+    pub fn interface_names(&self) -> Vec<&str> {
+        self.interfaces.iter().map(|i| i.name.as_str()).collect()
+    }
+
+    fn ordinal_of_ifindex(&self, ifindex: u32) -> Option<usize> {
+        self.interfaces.iter().position(|i| i.ifindex == ifindex)
+    }
+}
+
+fn discover_can_interfaces() -> Vec<SocketCanInterface> {
+    let mut found = Vec::new();
+    if let Ok(entries) = fs::read_dir("/sys/class/net") {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let if_type = fs::read_to_string(path.join("type"))
+                .ok()
+                .and_then(|t| t.trim().parse::<u32>().ok());
+            let ifindex = fs::read_to_string(path.join("ifindex"))
+                .ok()
+                .and_then(|t| t.trim().parse::<u32>().ok());
+            if let (Some(ARPHRD_CAN), Some(ifindex)) = (if_type, ifindex) {
+                found.push(SocketCanInterface {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    ifindex,
+                });
+            }
+        }
+    }
+    found.sort_by_key(|i| i.ifindex);
+    found
+}
+
+fn errno_error(call: &'static str) -> CanStatusError {
+    let err = io::Error::last_os_error();
+    CanStatusError {
+        call,
+        status: err.raw_os_error().unwrap_or(-1),
+        description: err.to_string(),
+    }
+}
+
+fn set_socket_option<T>(fd: libc::c_int, level: libc::c_int, name: libc::c_int, value: &T) -> bool {
+    let status = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    status == 0
+}
+
+fn describe_error_frame(can_id: u32, data: &[u8]) -> String {
+    const CLASSES: [(u32, &str); 9] = [
+        (0x001, "tx timeout"),
+        (0x002, "lost arbitration"),
+        (0x004, "controller problem"),
+        (0x008, "protocol violation"),
+        (0x010, "transceiver status"),
+        (0x020, "no ack"),
+        (0x040, "bus off"),
+        (0x080, "bus error"),
+        (0x100, "controller restarted"),
+    ];
+    let mut parts: Vec<&str> = CLASSES
+        .iter()
+        .filter(|(bit, _)| can_id & bit != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if parts.is_empty() {
+        parts.push("error frame");
+    }
+    let mut text = parts.join(", ");
+    if data.len() >= 8 && (data[6] != 0 || data[7] != 0) {
+        text.push_str(&format!(" (tec={} rec={})", data[6], data[7]));
+    }
+    text
+}
+
+impl CanBackend for SocketCanBackend {
+    fn name(&self) -> &'static str {
+        "socketcan"
+    }
+
+    fn app_channel_config(&self, _app_name: &str, app_channel: u32) -> Result<CanHwChannel, CanStatusError> {
+        self.interfaces
+            .get(app_channel as usize)
+            .map(|iface| CanHwChannel {
+                hw_type: ARPHRD_CAN,
+                hw_index: iface.ifindex,
+                hw_channel: 0,
+            })
+            .ok_or_else(|| CanStatusError {
+                call: "SocketCAN interface lookup",
+                status: libc::ENODEV,
+                description: format!("no interface for app channel {}", app_channel),
+            })
+    }
+
+    fn channel_mask(&self, hw: &CanHwChannel) -> CanAccess {
+        match self.ordinal_of_ifindex(hw.hw_index) {
+            Some(ordinal) if hw.hw_type == ARPHRD_CAN => 1u64 << ordinal,
+            _ => 0,
+        }
+    }
+
+    fn all_channels_mask(&self) -> CanAccess {
+        (0..self.interfaces.len()).fold(0, |mask, ordinal| mask | (1u64 << ordinal))
+    }
+
+    fn channel_inventory(&self) -> Result<Vec<CanChannelInfo>, CanStatusError> {
+        Ok(self
+            .interfaces
+            .iter()
+            .map(|iface| CanChannelInfo {
+                name: iface.name.clone(),
+                hw: CanHwChannel {
+                    hw_type: ARPHRD_CAN,
+                    hw_index: iface.ifindex,
+                    hw_channel: 0,
+                },
+                serial_number: 0,
+                article_number: 0,
+                transceiver: String::new(),
+                // CAN FD capable interfaces accept the CANFD_MTU once `fd on` is set.
+                fd_capable: fs::read_to_string(format!("/sys/class/net/{}/mtu", iface.name))
+                    .is_ok_and(|mtu| mtu.trim() == CANFD_MTU.to_string()),
+                max_bitrate: 0,
+                driver_version: String::new(),
+                on_bus: fs::read_to_string(format!("/sys/class/net/{}/operstate", iface.name))
+                    .is_ok_and(|state| state.trim() == "up"),
+            })
+            .collect())
+    }
+
+    fn open_port(
+        &self,
+        _app_name: &str,
+        access_mask: CanAccess,
+        rx_queue_size: u32,
+        _interface_version: u32,
+    ) -> Result<CanPort, CanStatusError> {
+        let selected: Vec<&SocketCanInterface> = self
+            .interfaces
+            .iter()
+            .enumerate()
+            .filter(|(ordinal, _)| access_mask & (1u64 << ordinal) != 0)
+            .map(|(_, iface)| iface)
+            .collect();
+        if selected.is_empty() {
+            return Err(CanStatusError {
+                call: "socket",
+                status: libc::ENODEV,
+                description: "access mask selects no SocketCAN interface".to_string(),
+            });
+        }
+
+        let fd = unsafe { libc::socket(libc::PF_CAN, libc::SOCK_RAW, CAN_RAW) };
+        if fd < 0 {
+            return Err(errno_error("socket"));
+        }
+
+        // FD frames are optional (classic-only controllers reject the option); error frames and
+        // timestamps are best effort as well.
+        let enable: libc::c_int = 1;
+        let _ = set_socket_option(fd, SOL_CAN_RAW, CAN_RAW_FD_FRAMES, &enable);
+        let err_mask: u32 = CAN_ERR_MASK;
+        let _ = set_socket_option(fd, SOL_CAN_RAW, CAN_RAW_ERR_FILTER, &err_mask);
+        let rcvbuf: libc::c_int = (rx_queue_size as libc::c_int).saturating_mul(CANFD_MTU as libc::c_int);
+        let _ = set_socket_option(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, &rcvbuf);
+        let ts_flags: libc::c_uint = libc::SOF_TIMESTAMPING_RX_HARDWARE
+            | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+            | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+            | libc::SOF_TIMESTAMPING_SOFTWARE;
+        if !set_socket_option(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPING, &ts_flags) {
+            let _ = set_socket_option(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, &enable);
+        }
+
+        // A single interface binds directly; several share one socket bound to all interfaces
+        // and receive() drops frames from interfaces outside the mask.
+        let bind_ifindex = if selected.len() == 1 { selected[0].ifindex } else { 0 };
+        let addr = SockaddrCan {
+            can_family: libc::AF_CAN as libc::sa_family_t,
+            can_ifindex: bind_ifindex as libc::c_int,
+            can_addr: [0; 2],
+        };
+        let status = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const SockaddrCan as *const libc::sockaddr,
+                mem::size_of::<SockaddrCan>() as libc::socklen_t,
+            )
+        };
+        if status != 0 {
+            let err = errno_error("bind");
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+
+        // Bit timing and listen-only mode belong to `ip link`; the socket never has init access.
+        Ok(CanPort {
+            handle: fd as u64,
+            access_mask,
+            permission_mask: 0,
+        })
+    }
+
+    fn configure_port(
+        &self,
+        _port: &CanPort,
+        _channel_mask: CanAccess,
+        _mode: CanBusMode,
+        _params: &CanBusParams,
+    ) -> Result<(), CanStatusError> {
+        Ok(())
+    }
+
+    fn activate_port(&self, port: &CanPort) -> Result<(), CanStatusError> {
+        for (ordinal, iface) in self.interfaces.iter().enumerate() {
+            if port.access_mask & (1u64 << ordinal) == 0 {
+                continue;
+            }
+            let flags = fs::read_to_string(format!("/sys/class/net/{}/flags", iface.name))
+                .ok()
+                .and_then(|f| u32::from_str_radix(f.trim().trim_start_matches("0x"), 16).ok())
+                .unwrap_or(0);
+            if flags & libc::IFF_UP as u32 == 0 {
+                return Err(CanStatusError {
+                    call: "SocketCAN activate",
+                    status: libc::ENETDOWN,
+                    description: format!("interface {} is down", iface.name),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Kernel receive stamps are CLOCK_REALTIME; NIC hardware stamps are only comparable when
+    /// the PHC is synchronised to it, otherwise the capture re-anchors on the first frame.
+    fn clock_ns(&self, _port: &CanPort) -> Result<u64, CanStatusError> {
+        let mut now: libc::timespec = unsafe { mem::zeroed() };
+        if unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut now) } != 0 {
+            return Err(errno_error("clock_gettime"));
+        }
+        Ok(timespec_ns(&now))
+    }
+
+    fn receive(&self, port: &CanPort) -> Result<Option<CanEvent>, CanStatusError> {
+        let fd = port.handle as libc::c_int;
+
+        loop {
+            let mut frame: CanFdFrameRaw = unsafe { mem::zeroed() };
+            let mut addr: SockaddrCan = unsafe { mem::zeroed() };
+            let mut control = [0u64; 16];
+            let mut iov = libc::iovec {
+                iov_base: &mut frame as *mut CanFdFrameRaw as *mut libc::c_void,
+                iov_len: mem::size_of::<CanFdFrameRaw>(),
+            };
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_name = &mut addr as *mut SockaddrCan as *mut libc::c_void;
+            msg.msg_namelen = mem::size_of::<SockaddrCan>() as libc::socklen_t;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = mem::size_of_val(&control) as _;
+
+            let read = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_DONTWAIT) };
+            if read < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::Interrupted {
+                    return Ok(None);
+                }
+                return Err(CanStatusError {
+                    call: "recvmsg",
+                    status: err.raw_os_error().unwrap_or(-1),
+                    description: err.to_string(),
+                });
+            }
+            let read = read as usize;
+            if read != CAN_MTU && read != CANFD_MTU {
+                continue;
+            }
+
+            let ordinal = match self.ordinal_of_ifindex(addr.can_ifindex as u32) {
+                Some(ordinal) if port.access_mask & (1u64 << ordinal) != 0 => ordinal,
+                _ => continue,
+            };
+            let timestamp_ns = unsafe { receive_timestamp_ns(&msg) };
+            let data_len = (frame.len as usize).min(if read == CANFD_MTU { 64 } else { 8 });
+
+            if frame.can_id & CAN_ERR_FLAG != 0 {
+                return Ok(Some(CanEvent::ErrorFrame {
+                    channel_index: ordinal as u16,
+                    timestamp_ns,
+                    description: describe_error_frame(frame.can_id & CAN_ERR_MASK, &frame.data[..data_len]),
+                }));
+            }
+
+            let fd_frame = read == CANFD_MTU;
+            let remote = !fd_frame && frame.can_id & CAN_RTR_FLAG != 0;
+            return Ok(Some(CanEvent::Frame(CanFrame {
+                channel_index: ordinal as u16,
+                timestamp_ns,
+                id: frame.can_id & CAN_ERR_MASK,
+                extended: frame.can_id & CAN_EFF_FLAG != 0,
+                tx: msg.msg_flags & libc::MSG_DONTROUTE != 0,
+                fd: fd_frame,
+                brs: fd_frame && frame.flags & CANFD_BRS != 0,
+                esi: fd_frame && frame.flags & CANFD_ESI != 0,
+                remote,
+                dlc: can_len_to_dlc(data_len),
+                data: if remote { Vec::new() } else { frame.data[..data_len].to_vec() },
+            })));
+        }
+    }
+
+    fn wait_for_event(&self, port: &CanPort, timeout: Duration) -> Result<bool, CanStatusError> {
+        let mut poll_fd = libc::pollfd {
+            fd: port.handle as libc::c_int,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as libc::c_int;
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+        if ready < 0 {
+            let err = errno_error("poll");
+            if err.status == libc::EINTR {
+                return Ok(false);
+            }
+            return Err(err);
+        }
+        Ok(ready > 0)
+    }
+
+    fn close_port(&self, port: &CanPort) {
+        unsafe { libc::close(port.handle as libc::c_int) };
+    }
+}
+
+/// Hardware timestamp when the driver provides one, otherwise the kernel receive time.
+unsafe fn receive_timestamp_ns(msg: &libc::msghdr) -> u64 {
+    let mut software = 0u64;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == libc::SOL_SOCKET {
+            let data = unsafe { libc::CMSG_DATA(cmsg) } as *const libc::timespec;
+            if header.cmsg_type == libc::SCM_TIMESTAMPING {
+                let stamps = unsafe { std::slice::from_raw_parts(data, 3) };
+                let hardware = timespec_ns(&stamps[2]);
+                if hardware != 0 {
+                    return hardware;
+                }
+                software = timespec_ns(&stamps[0]);
+            } else if header.cmsg_type == libc::SCM_TIMESTAMPNS {
+                software = timespec_ns(unsafe { &*data });
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(msg, cmsg) };
+    }
+    software
+}
+
+fn timespec_ns(ts: &libc::timespec) -> u64 {
+    (ts.tv_sec as u64) * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+fn can_len_to_dlc(len: usize) -> u8 {
+    match len {
+        0..=8 => len as u8,
+        9..=12 => 9,
+        13..=16 => 10,
+        17..=20 => 11,
+        21..=24 => 12,
+        25..=32 => 13,
+        33..=48 => 14,
+        _ => 15,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Needs a vcan interface: `ip link add dev vcan0 type vcan && ip link set up vcan0`, then
+    // `cargo test --features socketcan -- --ignored`.
+    #[test]
+    #[ignore]
+    fn receives_frames_sent_on_vcan0() {
+        let backend = SocketCanBackend::open(Some("vcan0")).unwrap();
+        let port = backend.open_port("test", 1, 64, 4).unwrap();
+        backend.activate_port(&port).unwrap();
+
+        let tx = unsafe { libc::socket(libc::PF_CAN, libc::SOCK_RAW, CAN_RAW) };
+        let addr = SockaddrCan {
+            can_family: libc::AF_CAN as libc::sa_family_t,
+            can_ifindex: backend.interfaces[0].ifindex as libc::c_int,
+            can_addr: [0; 2],
+        };
+        let mut frame: CanFdFrameRaw = unsafe { mem::zeroed() };
+        frame.can_id = 0x18DA_F110 | CAN_EFF_FLAG;
+        frame.len = 3;
+        frame.data[..3].copy_from_slice(&[1, 2, 3]);
+        unsafe {
+            libc::bind(
+                tx,
+                &addr as *const SockaddrCan as *const libc::sockaddr,
+                mem::size_of::<SockaddrCan>() as libc::socklen_t,
+            );
+            libc::write(tx, &frame as *const CanFdFrameRaw as *const libc::c_void, CAN_MTU);
+            libc::close(tx);
+        }
+
+        let mut received = None;
+        for _ in 0..100 {
+            if let Some(event) = backend.receive(&port).unwrap() {
+                received = Some(event);
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        backend.close_port(&port);
+
+        match received {
+            Some(CanEvent::Frame(frame)) => {
+                assert_eq!(frame.id, 0x18DA_F110);
+                assert!(frame.extended);
+                assert_eq!(frame.data, vec![1, 2, 3]);
+                assert!(frame.timestamp_ns > 0);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,530 @@
+// Source supervision.
+// Watches the child processes of a running session (ssh/slog2info, adb logcat, dumpcap): a child
+// that exits is noticed on the next poll, with its exit code and the tail of its stderr. QNX and
+// Android streams are reconnected with backoff - the new stream appends to the same log after a
+// "reconnected at" separator. Every source - including the in-process CAN capture - has a health
+// state, and each change of state is appended to source_events.jsonl in the session folder.
+
+use crate::process_control;
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+pub const SOURCE_EVENTS_FILE: &str = "source_events.jsonl";
+/// Lines of stderr kept in the detail of a source that exited.
+const STDERR_TAIL_LINES: usize = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceState {
+    Running,
+    /// The stream ended and is being reconnected; data flows again once it is `running`.
+    Restarting,
+    /// Ended by itself with exit code 0 while the session was running.
+    Exited,
+    /// Did not start, ended with an error, ran out of reconnect attempts, or was killed at stop.
+    Failed,
+    /// Ended at stop.
+    Stopped,
+}
+
+impl SourceState {
+    pub fn name(self) -> &'static str {
+        match self {
+            SourceState::Running => "running",
+            SourceState::Restarting => "restarting",
+            SourceState::Exited => "exited",
+            SourceState::Failed => "failed",
+            SourceState::Stopped => "stopped",
+        }
+    }
+}
+
+/// State of one source since its last transition; also one line of `source_events.jsonl`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SourceHealth {
+    pub source: String,
+    pub state: SourceState,
+    /// Host time of the transition.
+    pub since: String,
+    pub exit_code: Option<i32>,
+    pub detail: Option<String>,
+    /// Successful reconnects so far in the session.
+    #[serde(default)]
+    pub reconnects: u32,
+}
+
+impl SourceHealth {
+    pub fn since_time(&self) -> Option<DateTime<Local>> {
+        DateTime::parse_from_rfc3339(&self.since).ok().map(|t| t.with_timezone(&Local))
+    }
+
+    /// `android exited with code 1 at 10:02:03: error: device offline`
+    pub fn describe(&self) -> String {
+        let mut text = format!("{} {}", self.source, self.state.name());
+        if let Some(code) = self.exit_code {
+            text.push_str(&format!(" with code {}", code));
+        }
+        if let Some(time) = self.since_time() {
+            text.push_str(&format!(" at {}", time.format("%H:%M:%S")));
+        }
+        if self.reconnects > 0 {
+            text.push_str(&format!(" ({} reconnects)", self.reconnects));
+        }
+        if let Some(detail) = &self.detail {
+            text.push_str(&format!(": {}", detail));
+        }
+        text
+    }
+}
+
+/// How streams that end during a session are reconnected.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectPolicy {
+    /// Attempts per outage; 0 turns reconnecting off.
+    pub max_attempts: u32,
+    /// Wait before the first attempt; doubled for every further attempt.
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 10,
+            backoff_ms: 1000,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Wait before attempt `attempt` (1-based).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(16);
+        Duration::from_millis(self.backoff_ms.saturating_mul(factor).min(self.max_backoff_ms))
+    }
+}
+
+/// Program and arguments of a source process, and the file in the session folder its stdout
+/// goes to.
+#[derive(Clone, Debug)]
+pub struct SourceCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    pub stdout: Option<String>,
+}
+
+impl SourceCommand {
+    pub fn new(program: &str, args: &[String], stdout: Option<&str>) -> Self {
+        SourceCommand {
+            program: program.to_string(),
+            args: args.to_vec(),
+            stdout: stdout.map(str::to_string),
+        }
+    }
+
+    /// Starts the process with stdout and stderr going to files in `folder`; `append` keeps
+    /// what an earlier run of the source wrote.
+    fn spawn(&self, folder: &Path, name: &str, append: bool) -> Result<Child, String> {
+        let open = |file: &str| {
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(folder.join(file))
+                .map_err(|e| format!("Failed to create {}: {}", file, e))
+        };
+        let mut command = Command::new(&self.program);
+        process_control::interruptible(&mut command).args(&self.args);
+        if let Some(file) = &self.stdout {
+            command.stdout(open(file)?);
+        }
+        command
+            .stderr(open(&Supervisor::stderr_file(name))?)
+            .spawn()
+            .map_err(|e| format!("Failed to start {}: {}", self.program, e))
+    }
+}
+
+struct Watched {
+    name: String,
+    /// None while waiting for the next reconnect attempt.
+    child: Option<Child>,
+    /// Restarts the source; None for sources that are not reconnected.
+    restart: Option<SourceCommand>,
+    /// Attempts in the current outage.
+    attempts: u32,
+    next_attempt: Instant,
+    /// stdout length after the reconnect separator; the stream is back once it grows.
+    resumed_at: Option<u64>,
+}
+
+pub struct Supervisor {
+    folder: PathBuf,
+    policy: ReconnectPolicy,
+    watched: Vec<Watched>,
+    health: Vec<SourceHealth>,
+    /// Transitions that could not be appended to the events file.
+    errors: Vec<String>,
+}
+
+impl Supervisor {
+    pub fn new(folder: &Path, policy: ReconnectPolicy) -> Self {
+        Supervisor {
+            folder: folder.to_path_buf(),
+            policy,
+            watched: Vec::new(),
+            health: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Where the stderr of source `name` goes.
+    pub fn stderr_file(name: &str) -> String {
+        format!("{}_stderr.log", name)
+    }
+
+    /// Starts source `name` and watches it; with a `restart` command it is reconnected when it
+    /// ends before stop.
+    pub fn start(&mut self, name: &str, command: SourceCommand, restart: Option<SourceCommand>) -> Result<(), String> {
+        match command.spawn(&self.folder, name, false) {
+            Ok(child) => {
+                self.watched.push(Watched {
+                    name: name.to_string(),
+                    child: Some(child),
+                    restart,
+                    attempts: 0,
+                    next_attempt: Instant::now(),
+                    resumed_at: None,
+                });
+                self.record(name, SourceState::Running, None, None);
+                Ok(())
+            }
+            Err(e) => {
+                self.record(name, SourceState::Failed, None, Some(e.clone()));
+                Err(e)
+            }
+        }
+    }
+
+    /// Whether `name` is watched and its stream is up.
+    pub fn is_running(&self, name: &str) -> bool {
+        self.watched.iter().any(|w| w.name == name)
+            && self.health.iter().any(|h| h.source == name && h.state == SourceState::Running)
+    }
+
+    /// Whether any source is still running or being reconnected.
+    pub fn has_children(&self) -> bool {
+        !self.watched.is_empty()
+    }
+
+    pub fn health(&self) -> &[SourceHealth] {
+        &self.health
+    }
+
+    /// Sets the state of `name` and appends the transition to the events file.
+    pub fn record(&mut self, name: &str, state: SourceState, exit_code: Option<i32>, detail: Option<String>) -> SourceHealth {
+        let reconnects = self.health.iter().find(|h| h.source == name).map_or(0, |h| h.reconnects);
+        self.set(SourceHealth {
+            source: name.to_string(),
+            state,
+            since: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            exit_code,
+            detail,
+            reconnects,
+        })
+    }
+
+    fn set(&mut self, health: SourceHealth) -> SourceHealth {
+        match self.health.iter_mut().find(|h| h.source == health.source) {
+            Some(current) => *current = health.clone(),
+            None => self.health.push(health.clone()),
+        }
+        if let Err(e) = append_event(&self.folder, &health) {
+            self.errors.push(e);
+        }
+        health
+    }
+
+    /// Polls every source: notices the ones that ended, reconnects them when an attempt is due
+    /// and sees them come back. Returns the transitions since the last call.
+    pub fn check(&mut self) -> Vec<SourceHealth> {
+        let mut transitions = Vec::new();
+        let mut index = 0;
+        while index < self.watched.len() {
+            let (transition, keep) = self.check_one(index);
+            transitions.extend(transition);
+            if keep {
+                index += 1;
+            } else {
+                self.watched.remove(index);
+            }
+        }
+        transitions
+    }
+
+    /// Returns the transition of watched source `index`, and whether to keep watching it.
+    fn check_one(&mut self, index: usize) -> (Option<SourceHealth>, bool) {
+        let folder = self.folder.clone();
+        let watched = &mut self.watched[index];
+        let name = watched.name.clone();
+
+        let Some(child) = &mut watched.child else {
+            // waiting to reconnect
+            if Instant::now() < watched.next_attempt {
+                return (None, true);
+            }
+            let Some(command) = watched.restart.clone() else {
+                return (None, false);
+            };
+            let attempt = watched.attempts;
+            let resumed_at = command.stdout.as_ref().and_then(|file| write_separator(&folder.join(file), attempt).ok());
+            return match command.spawn(&folder, &name, true) {
+                Ok(child) => {
+                    watched.child = Some(child);
+                    watched.resumed_at = resumed_at;
+                    (None, true)
+                }
+                Err(e) => self.retry_or_fail(index, None, Some(e)),
+            };
+        };
+
+        match child.try_wait() {
+            Ok(None) => {
+                let Some(mark) = watched.resumed_at else {
+                    return (None, true);
+                };
+                let stdout = watched.restart.as_ref().and_then(|c| c.stdout.clone()).unwrap_or_default();
+                let grown = fs::metadata(folder.join(stdout)).map(|m| m.len() > mark).unwrap_or(true);
+                if !grown {
+                    return (None, true);
+                }
+                let attempts = std::mem::take(&mut watched.attempts);
+                watched.resumed_at = None;
+                let reconnects = self.health.iter().find(|h| h.source == name).map_or(0, |h| h.reconnects) + 1;
+                let health = self.set(SourceHealth {
+                    source: name,
+                    state: SourceState::Running,
+                    since: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+                    exit_code: None,
+                    detail: Some(format!("reconnected after {} attempts", attempts)),
+                    reconnects,
+                });
+                (Some(health), true)
+            }
+            Ok(Some(status)) => {
+                watched.child = None;
+                watched.resumed_at = None;
+                let tail = stderr_tail(&folder.join(Supervisor::stderr_file(&name)));
+                if watched.restart.is_some() && self.policy.max_attempts > 0 {
+                    return self.retry_or_fail(index, status.code(), tail);
+                }
+                let state = if status.success() { SourceState::Exited } else { SourceState::Failed };
+                (Some(self.record(&name, state, status.code(), tail)), false)
+            }
+            Err(_) => (None, true),
+        }
+    }
+
+    /// Schedules the next reconnect attempt of source `index`, or gives up after `max_attempts`.
+    fn retry_or_fail(&mut self, index: usize, exit_code: Option<i32>, detail: Option<String>) -> (Option<SourceHealth>, bool) {
+        let max_attempts = self.policy.max_attempts;
+        let watched = &mut self.watched[index];
+        let name = watched.name.clone();
+        if watched.attempts >= max_attempts {
+            let detail = match detail {
+                Some(detail) => Some(format!("gave up after {} reconnect attempts: {}", max_attempts, detail)),
+                None => Some(format!("gave up after {} reconnect attempts", max_attempts)),
+            };
+            return (Some(self.record(&name, SourceState::Failed, exit_code, detail)), false);
+        }
+        watched.attempts += 1;
+        let delay = self.policy.delay(watched.attempts);
+        watched.next_attempt = Instant::now() + delay;
+        let retry = format!("attempt {}/{} in {:.1} s", watched.attempts, max_attempts, delay.as_secs_f64());
+        let detail = match detail {
+            Some(detail) => format!("{}; {}", detail, retry),
+            None => retry,
+        };
+        (Some(self.record(&name, SourceState::Restarting, exit_code, Some(detail))), true)
+    }
+
+    /// Hands the children still running over for stopping; sources waiting to reconnect are
+    /// recorded as stopped.
+    pub fn take_children(&mut self) -> Vec<(String, Child)> {
+        let mut children = Vec::new();
+        for watched in std::mem::take(&mut self.watched) {
+            match watched.child {
+                Some(child) => children.push((watched.name, child)),
+                None => {
+                    self.record(&watched.name, SourceState::Stopped, None, Some("not reconnected before stop".into()));
+                }
+            }
+        }
+        children
+    }
+
+    pub fn take_errors(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.errors)
+    }
+}
+
+/// Appends `----- reconnected at <host time> (attempt n) -----` to the log; returns its new length.
+fn write_separator(path: &Path, attempt: u32) -> Result<u64, String> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    writeln!(
+        file,
+        "----- reconnected at {} (attempt {}) -----",
+        Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+        attempt
+    )
+    .and_then(|_| file.metadata().map(|m| m.len()))
+    .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+}
+
+fn append_event(folder: &Path, health: &SourceHealth) -> Result<(), String> {
+    let path = folder.join(SOURCE_EVENTS_FILE);
+    let line = serde_json::to_string(health).map_err(|e| format!("Failed to encode source event: {}", e))?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{}", line))
+        .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+}
+
+/// Last `STDERR_TAIL_LINES` non-empty lines of `path`, joined with " | ".
+pub fn stderr_tail(path: &Path) -> Option<String> {
+    let mut file = fs::File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(4096))).ok()?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).ok()?;
+    let text = String::from_utf8_lossy(&bytes);
+    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    let tail = lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join(" | ");
+    (!tail.is_empty()).then_some(tail)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> SourceCommand {
+        SourceCommand::new("sh", &["-c".to_string(), script.to_string()], Some("out.log"))
+    }
+
+    /// Polls until `count` transitions arrived or 5 s passed.
+    fn transitions(supervisor: &mut Supervisor, count: usize) -> Vec<SourceHealth> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut seen = Vec::new();
+        while seen.len() < count && Instant::now() < deadline {
+            seen.extend(supervisor.check());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        seen
+    }
+
+    #[test]
+    fn reports_a_child_that_dies_with_its_stderr() {
+        let dir = std::env::temp_dir().join(format!("cdl_supervisor_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let policy = ReconnectPolicy { max_attempts: 0, ..ReconnectPolicy::default() };
+        let mut supervisor = Supervisor::new(&dir, policy);
+        let script = "echo '- waiting for device -' >&2; sleep 0.2; echo 'error: device offline' >&2; exit 1";
+        supervisor.start("android", sh(script), None).unwrap();
+        assert!(supervisor.is_running("android"));
+
+        let exited = transitions(&mut supervisor, 1);
+        assert_eq!(exited.len(), 1);
+        assert_eq!((exited[0].state, exited[0].exit_code), (SourceState::Failed, Some(1)));
+        assert_eq!(exited[0].detail.as_deref(), Some("- waiting for device - | error: device offline"));
+        assert!(!supervisor.is_running("android") && !supervisor.has_children());
+        assert!(exited[0].describe().starts_with("android failed with code 1 at "));
+
+        let events: Vec<SourceHealth> = fs::read_to_string(dir.join(SOURCE_EVENTS_FILE))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let states: Vec<_> = events.iter().map(|e| e.state).collect();
+        assert_eq!(states, [SourceState::Running, SourceState::Failed]);
+        assert_eq!(supervisor.health(), &exited[..]);
+        assert!(supervisor.take_errors().is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reconnect_off_keeps_the_exit_status() {
+        let dir = std::env::temp_dir().join(format!("cdl_reconnect_off_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let policy = ReconnectPolicy { max_attempts: 0, ..ReconnectPolicy::default() };
+        let mut supervisor = Supervisor::new(&dir, policy);
+        supervisor.start("qnx", sh("exit 0"), Some(sh("exit 0"))).unwrap();
+        supervisor.start("android", sh("exit 3"), Some(sh("exit 3"))).unwrap();
+        let mut seen = transitions(&mut supervisor, 2);
+        seen.sort_by(|a, b| a.source.cmp(&b.source));
+        let outcomes: Vec<_> = seen.iter().map(|h| (h.source.as_str(), h.state, h.exit_code)).collect();
+        assert_eq!(outcomes, [("android", SourceState::Failed, Some(3)), ("qnx", SourceState::Exited, Some(0))]);
+        assert!(!supervisor.has_children());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reconnects_with_backoff_and_gives_up_after_max_attempts() {
+        let dir = std::env::temp_dir().join(format!("cdl_reconnect_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let policy = ReconnectPolicy { max_attempts: 2, backoff_ms: 20, max_backoff_ms: 40 };
+        assert_eq!(
+            [1, 2, 3].map(|attempt| policy.delay(attempt).as_millis()),
+            [20, 40, 40]
+        );
+
+        // first run drops after one line; the second one stays up
+        let counter = dir.join("runs");
+        let script = format!(
+            "n=$(( $(cat '{0}' 2>/dev/null || echo 0) + 1 )); echo $n > '{0}'; echo \"line $n\"; [ $n -ge 2 ] && exec sleep 30; exit 255",
+            counter.display()
+        );
+        let mut supervisor = Supervisor::new(&dir, policy);
+        supervisor.start("qnx", sh(&script), Some(sh(&script))).unwrap();
+
+        let seen = transitions(&mut supervisor, 2);
+        let states: Vec<_> = seen.iter().map(|h| h.state).collect();
+        assert_eq!(states, [SourceState::Restarting, SourceState::Running]);
+        assert_eq!(seen[0].exit_code, Some(255));
+        assert_eq!(seen[1].reconnects, 1);
+        assert!(supervisor.is_running("qnx"));
+        let log = fs::read_to_string(dir.join("out.log")).unwrap();
+        let lines: Vec<_> = log.lines().collect();
+        assert_eq!((lines[0], lines[2]), ("line 1", "line 2"));
+        assert!(lines[1].starts_with("----- reconnected at ") && lines[1].ends_with("(attempt 1) -----"));
+        process_control::stop_children(supervisor.take_children(), Duration::from_secs(2));
+
+        // never comes back: two attempts, then failed
+        let mut supervisor = Supervisor::new(&dir, ReconnectPolicy { max_attempts: 2, backoff_ms: 10, max_backoff_ms: 10 });
+        supervisor.start("android", sh("exit 1"), Some(sh("exit 1"))).unwrap();
+        let seen = transitions(&mut supervisor, 3);
+        let states: Vec<_> = seen.iter().map(|h| h.state).collect();
+        assert_eq!(states, [SourceState::Restarting, SourceState::Restarting, SourceState::Failed]);
+        assert!(seen[2].detail.as_deref().unwrap().starts_with("gave up after 2 reconnect attempts"));
+        assert!(!supervisor.has_children());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,491 @@
+// Cross-domain timeline.
+// Parses the files of a session folder - qnx.log (slog2info), android.log (logcat threadtime),
+// CAN_LOGS/*.asc and text CAN logs, ethernet.pcapng, markers.jsonl - converts every record onto
+// the host clock and merges them into one chronologically sorted timeline, written as
+// timeline.txt and timeline.jsonl with a domain column.
+//
+// Time bases: CAN logs are relative to the host capture start recorded in their header, pcapng
+// timestamps are host UTC, and slog2info/logcat print the target's local wall clock without a
+// year. Target times are read as host local time in the year of the session start and shifted
+// by the per-domain clock model (host minus target), fitted to the session's clock samples.
+
+use crate::can_cli::{EXIT_FAILED, EXIT_OK, EXIT_USAGE};
+use crate::clock_sync::{self, ClockModel};
+use crate::markers;
+use crate::pcapng;
+use crate::session::{SessionManifest, MANIFEST_FILE};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, TimeDelta, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub const TIMELINE_TEXT_FILE: &str = "timeline.txt";
+pub const TIMELINE_JSONL_FILE: &str = "timeline.jsonl";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Domain {
+    Qnx,
+    Android,
+    Can,
+    Ethernet,
+    /// Markers set during the session (markers.jsonl).
+    Marker,
+}
+
+impl Domain {
+    pub fn name(self) -> &'static str {
+        match self {
+            Domain::Qnx => "QNX",
+            Domain::Android => "Android",
+            Domain::Can => "CAN",
+            Domain::Ethernet => "Ethernet",
+            Domain::Marker => "Marker",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimelineEvent {
+    /// Host time of the record.
+    pub time: DateTime<Local>,
+    pub domain: Domain,
+    /// File the record came from (relative to the session folder).
+    pub source: String,
+    pub text: String,
+}
+
+/// One line of timeline.jsonl.
+#[derive(Serialize, Deserialize)]
+struct JsonRecord<'a> {
+    time: String,
+    domain: Domain,
+    source: &'a str,
+    text: &'a str,
+}
+
+impl TimelineEvent {
+    pub fn text_line(&self) -> String {
+        format!(
+            "{}  {:<8} {:<16} {}",
+            self.time.format("%Y-%m-%d %H:%M:%S%.6f"),
+            self.domain.name(),
+            self.source,
+            self.text
+        )
+    }
+
+    pub fn json_line(&self) -> String {
+        let record = JsonRecord {
+            time: self.time.to_rfc3339_opts(SecondsFormat::Micros, false),
+            domain: self.domain,
+            source: &self.source,
+            text: &self.text,
+        };
+        serde_json::to_string(&record).unwrap_or_default()
+    }
+}
+
+/// How target timestamps map onto host time.
+#[derive(Clone, Debug)]
+pub struct MergeOptions {
+    /// Date used to complete the year-less slog2info/logcat timestamps.
+    pub reference: DateTime<Local>,
+    /// Host minus target clock, per domain; domains without an entry are taken as in sync.
+    pub clocks: HashMap<Domain, ClockModel>,
+}
+
+impl MergeOptions {
+    pub fn new(reference: DateTime<Local>) -> Self {
+        MergeOptions {
+            reference,
+            clocks: HashMap::new(),
+        }
+    }
+
+    fn device_time(&self, domain: Domain, month: u32, day: u32, time: NaiveTime) -> Option<DateTime<Local>> {
+        // The year closest to the reference, so a session across New Year still sorts right.
+        let year = self.reference.year();
+        let local = [year, year - 1, year + 1]
+            .into_iter()
+            .filter_map(|y| NaiveDate::from_ymd_opt(y, month, day))
+            .map(|date| NaiveDateTime::new(date, time))
+            .min_by_key(|t| (*t - self.reference.naive_local()).abs())?;
+        let host = Local.from_local_datetime(&local).earliest()?;
+        Some(host + self.clocks.get(&domain).map(|clock| clock.offset_at(host)).unwrap_or_default())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    pub events: Vec<TimelineEvent>,
+    /// Files that were skipped or only partly read.
+    pub warnings: Vec<String>,
+}
+
+impl Timeline {
+    /// Adds `events` and keeps the timeline sorted; records with equal times keep file order.
+    pub fn extend(&mut self, events: Vec<TimelineEvent>) {
+        self.events.extend(events);
+        self.events.sort_by_key(|event| event.time);
+    }
+
+    pub fn write_text(&self, path: &Path) -> Result<(), String> {
+        self.write_lines(path, TimelineEvent::text_line)
+    }
+
+    pub fn write_jsonl(&self, path: &Path) -> Result<(), String> {
+        self.write_lines(path, TimelineEvent::json_line)
+    }
+
+    fn write_lines(&self, path: &Path, line: fn(&TimelineEvent) -> String) -> Result<(), String> {
+        let file = fs::File::create(path).map_err(|e| format!("Failed to create '{}': {}", path.display(), e))?;
+        let mut out = BufWriter::new(file);
+        for event in &self.events {
+            writeln!(out, "{}", line(event)).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+        }
+        out.flush().map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+    }
+}
+
+fn month_number(name: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    MONTHS.iter().position(|m| m.eq_ignore_ascii_case(name)).map(|i| i as u32 + 1)
+}
+
+/// Lines without a timestamp (wrapped messages, stream separators) take the time of the
+/// record before them; leading ones are dropped.
+fn push_line(events: &mut Vec<TimelineEvent>, time: Option<DateTime<Local>>, domain: Domain, source: &str, text: &str) {
+    let Some(time) = time.or_else(|| events.last().map(|e| e.time)) else {
+        return;
+    };
+    if text.trim().is_empty() {
+        return;
+    }
+    events.push(TimelineEvent {
+        time,
+        domain,
+        source: source.to_string(),
+        text: text.trim_end().to_string(),
+    });
+}
+
+/// `slog2info` lines: `Jan 01 00:00:18.517 <buffer> <slot> <level> <message>`.
+pub fn parse_slog2info(text: &str, source: &str, options: &MergeOptions) -> Vec<TimelineEvent> {
+    let mut events = Vec::new();
+    for line in text.lines() {
+        let mut parts = line.split_whitespace();
+        let parsed = (|| {
+            let month = month_number(parts.next()?)?;
+            let day = parts.next()?.parse().ok()?;
+            let time = NaiveTime::parse_from_str(parts.next()?, "%H:%M:%S%.f").ok()?;
+            let time = options.device_time(Domain::Qnx, month, day, time)?;
+            let rest = line.split_whitespace().skip(3).collect::<Vec<_>>().join(" ");
+            Some((time, rest))
+        })();
+        match parsed {
+            Some((time, rest)) => push_line(&mut events, Some(time), Domain::Qnx, source, &rest),
+            None => push_line(&mut events, None, Domain::Qnx, source, line),
+        }
+    }
+    events
+}
+
+/// `logcat -v threadtime` lines: `05-02 10:00:00.123  1234  1240 I Tag: message`.
+pub fn parse_logcat(text: &str, source: &str, options: &MergeOptions) -> Vec<TimelineEvent> {
+    let mut events = Vec::new();
+    for line in text.lines() {
+        let parsed = (|| {
+            let (date, rest) = line.split_once(' ')?;
+            let (month, day) = date.split_once('-')?;
+            let rest = rest.trim_start();
+            let (clock, message) = rest.split_once(char::is_whitespace)?;
+            let time = NaiveTime::parse_from_str(clock, "%H:%M:%S%.f").ok()?;
+            let time = options.device_time(Domain::Android, month.parse().ok()?, day.parse().ok()?, time)?;
+            Some((time, message.trim_start()))
+        })();
+        match parsed {
+            Some((time, message)) => push_line(&mut events, Some(time), Domain::Android, source, message),
+            None => push_line(&mut events, None, Domain::Android, source, line),
+        }
+    }
+    events
+}
+
+/// ASC traces and text CAN logs: records start with the seconds since the host capture start
+/// written in the `// capture start (host clock)` (ASC) or `# capture start (host clock)` header.
+pub fn parse_can_log(text: &str, source: &str) -> Result<Vec<TimelineEvent>, String> {
+    let start = text
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix("// capture start (host clock) ")
+                .or_else(|| line.strip_prefix("# capture start (host clock) "))
+        })
+        .and_then(|stamp| DateTime::parse_from_rfc3339(stamp.trim()).ok())
+        .map(|start| start.with_timezone(&Local))
+        .ok_or_else(|| format!("{}: no capture start (host clock) header", source))?;
+
+    let mut events = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let Some((seconds, record)) = trimmed.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let Ok(seconds) = seconds.parse::<f64>() else {
+            continue;
+        };
+        let record = record.split_whitespace().collect::<Vec<_>>().join(" ");
+        if record == "Start of measurement" {
+            continue;
+        }
+        let time = start + TimeDelta::nanoseconds((seconds * 1e9).round() as i64);
+        push_line(&mut events, Some(time), Domain::Can, source, &record);
+    }
+    Ok(events)
+}
+
+/// Enhanced packet blocks of a pcapng capture, with their comments; host UTC timestamps.
+pub fn parse_pcapng(data: &[u8], source: &str) -> Result<Vec<TimelineEvent>, String> {
+    let packets = pcapng::read_packets(data).map_err(|e| format!("{}: {}", source, e))?;
+    let mut events = Vec::new();
+    for packet in packets {
+        push_line(&mut events, Some(packet.time), Domain::Ethernet, source, &packet.text);
+    }
+    Ok(events)
+}
+
+/// Reads every known log of a session folder into one timeline. Files that cannot be parsed
+/// are listed in `warnings`; only a folder with nothing to merge is an error.
+pub fn merge_session(folder: &Path, options: &MergeOptions) -> Result<Timeline, String> {
+    let mut timeline = Timeline::default();
+    let mut read_text = |name: &str, parse: &dyn Fn(&str) -> Vec<TimelineEvent>| {
+        if let Ok(text) = fs::read(folder.join(name)) {
+            timeline.extend(parse(&String::from_utf8_lossy(&text)));
+        }
+    };
+    read_text("qnx.log", &|text| parse_slog2info(text, "qnx.log", options));
+    read_text("android.log", &|text| parse_logcat(text, "android.log", options));
+
+    match markers::read_markers(folder) {
+        Ok(markers) => timeline.extend(
+            markers
+                .iter()
+                .filter_map(|marker| {
+                    Some(TimelineEvent {
+                        time: marker.host_time()?,
+                        domain: Domain::Marker,
+                        source: markers::MARKERS_FILE.to_string(),
+                        text: marker.text(),
+                    })
+                })
+                .collect(),
+        ),
+        Err(e) => timeline.warnings.push(e),
+    }
+
+    if let Ok(data) = fs::read(folder.join("ethernet.pcapng")) {
+        match parse_pcapng(&data, "ethernet.pcapng") {
+            Ok(events) => timeline.extend(events),
+            Err(e) => timeline.warnings.push(e),
+        }
+    }
+
+    let can_folder = folder.join("CAN_LOGS");
+    let mut can_files: Vec<PathBuf> = fs::read_dir(&can_folder)
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+        .unwrap_or_default();
+    can_files.sort();
+    for path in can_files {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let source = format!("CAN_LOGS/{}", name);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("asc") | Some("log") if !name.ends_with("_console.log") => {
+                match fs::read(&path).map_err(|e| e.to_string()).and_then(|text| {
+                    parse_can_log(&String::from_utf8_lossy(&text), &source)
+                }) {
+                    Ok(events) => timeline.extend(events),
+                    Err(e) => timeline.warnings.push(e),
+                }
+            }
+            Some("blf") | Some("mf4") => {
+                timeline.warnings.push(format!("{}: BLF/MF4 logs are not merged; record CAN as ASC", source));
+            }
+            _ => {}
+        }
+    }
+
+    if timeline.events.is_empty() {
+        return Err(format!("No log records found in '{}'", folder.display()));
+    }
+    Ok(timeline)
+}
+
+/// Session start from `session.json` if present (otherwise now) and the clock models fitted
+/// to `clock_samples.jsonl`. Also returns a warning if the samples could not be read.
+pub fn default_options(folder: &Path) -> (MergeOptions, Option<String>) {
+    let reference = SessionManifest::read(&folder.join(MANIFEST_FILE))
+        .ok()
+        .and_then(|manifest| DateTime::parse_from_rfc3339(&manifest.started_at).ok())
+        .map_or_else(Local::now, |start| start.with_timezone(&Local));
+    let mut options = MergeOptions::new(reference);
+    let (samples, warning) = match clock_sync::read_samples(folder) {
+        Ok(samples) => (samples, None),
+        Err(e) => (Vec::new(), Some(e)),
+    };
+    for domain in [Domain::Qnx, Domain::Android] {
+        if let Some(model) = ClockModel::fit(&samples, domain) {
+            options.clocks.insert(domain, model);
+        }
+    }
+    (options, warning)
+}
+
+/// `--merge <session folder> [--merge-out <dir>] [--qnx-offset-ms <ms>] [--android-offset-ms <ms>]`:
+/// writes timeline.txt and timeline.jsonl (into the session folder unless `--merge-out` is given).
+/// A fixed offset given on the command line replaces the measured clock model of that domain.
+pub fn run_merge(args: &[String]) -> i32 {
+    let value = |flag: &str| args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1));
+    let Some(folder) = value("--merge").map(PathBuf::from) else {
+        println!("--merge requires a session folder");
+        return EXIT_USAGE;
+    };
+    let out_dir = value("--merge-out").map_or_else(|| folder.clone(), PathBuf::from);
+
+    let (mut options, warning) = default_options(&folder);
+    if let Some(warning) = warning {
+        println!("Warning: {}", warning);
+    }
+    for (flag, domain) in [("--qnx-offset-ms", Domain::Qnx), ("--android-offset-ms", Domain::Android)] {
+        if let Some(v) = value(flag) {
+            match v.parse::<i64>() {
+                Ok(ms) => {
+                    options.clocks.insert(domain, ClockModel::constant(TimeDelta::milliseconds(ms)));
+                }
+                Err(_) => {
+                    println!("{} expects milliseconds, got '{}'", flag, v);
+                    return EXIT_USAGE;
+                }
+            }
+        }
+    }
+
+    for domain in [Domain::Qnx, Domain::Android] {
+        if let Some(clock) = options.clocks.get(&domain) {
+            println!("{} clock: {}", domain.name(), clock.describe());
+        }
+    }
+
+    let timeline = match merge_session(&folder, &options) {
+        Ok(timeline) => timeline,
+        Err(e) => {
+            println!("{}", e);
+            return EXIT_FAILED;
+        }
+    };
+    for warning in &timeline.warnings {
+        println!("Warning: {}", warning);
+    }
+    let text_path = out_dir.join(TIMELINE_TEXT_FILE);
+    let jsonl_path = out_dir.join(TIMELINE_JSONL_FILE);
+    if let Err(e) = fs::create_dir_all(&out_dir)
+        .map_err(|e| format!("Failed to create '{}': {}", out_dir.display(), e))
+        .and_then(|_| timeline.write_text(&text_path))
+        .and_then(|_| timeline.write_jsonl(&jsonl_path))
+    {
+        println!("{}", e);
+        return EXIT_FAILED;
+    }
+
+    let mut counts: Vec<(Domain, usize)> = Vec::new();
+    for event in &timeline.events {
+        match counts.iter_mut().find(|(domain, _)| *domain == event.domain) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((event.domain, 1)),
+        }
+    }
+    println!(
+        "Merged {} records ({}) into {} and {}",
+        timeline.events.len(),
+        counts.iter().map(|(d, n)| format!("{} {}", d.name(), n)).collect::<Vec<_>>().join(", "),
+        text_path.display(),
+        jsonl_path.display()
+    );
+    EXIT_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asc_writer;
+
+    fn reference() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 2, 10, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn merges_all_domains_onto_host_time() {
+        let dir = std::env::temp_dir().join(format!("cdl_timeline_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("CAN_LOGS")).unwrap();
+
+        // CAN wake-up at 10:00:01.000, Ethernet at 10:00:01.250, Android 10:00:01.500, QNX
+        // prints 10:00:00.800 on a clock that is 1 s slow (host 10:00:01.800).
+        let asc = format!(
+            "{}   1.000000 1  123             Rx   d 1 01\nEnd TriggerBlock\n",
+            asc_writer::format_header(reference())
+        );
+        fs::write(dir.join("CAN_LOGS").join("FD_CAN5.asc"), asc).unwrap();
+        fs::write(dir.join("CAN_LOGS").join("can_capture_console.log"), "   0.5 ignored\n").unwrap();
+        fs::write(dir.join("android.log"), "--------- beginning of main\n05-02 10:00:01.500  1234  1240 I Power: wakeup\n").unwrap();
+        fs::write(dir.join("qnx.log"), "Jan 01 00:00:00.000 boot\nMay 02 10:00:00.800    power.1   main  2  resume\n").unwrap();
+        fs::write(
+            dir.join("ethernet.pcapng"),
+            pcapng::test_capture(reference() + TimeDelta::milliseconds(1250), "mark"),
+        )
+        .unwrap();
+
+        let marker = markers::Marker {
+            index: 1,
+            time: (reference() + TimeDelta::milliseconds(1900)).to_rfc3339(),
+            label: "brake noise".to_string(),
+        };
+        markers::append_marker(&dir, &marker).unwrap();
+
+        let mut options = MergeOptions::new(reference());
+        options.clocks.insert(Domain::Qnx, ClockModel::constant(TimeDelta::seconds(1)));
+        let timeline = merge_session(&dir, &options).unwrap();
+
+        let order: Vec<(Domain, &str)> = timeline
+            .events
+            .iter()
+            .filter(|e| e.time >= reference())
+            .map(|e| (e.domain, e.text.as_str()))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (Domain::Can, "1 123 Rx d 1 01"),
+                (
+                    Domain::Ethernet,
+                    "len=42 00:00:00:00:00:00 -> 00:00:00:00:00:00 ethertype=0x0800 IPv4 10.0.0.1 -> 10.0.0.2 UDP 5000 -> 6000 comment: mark"
+                ),
+                (Domain::Android, "1234  1240 I Power: wakeup"),
+                (Domain::Qnx, "power.1 main 2 resume"),
+                (Domain::Marker, "CDL_MARKER #1 brake noise"),
+            ]
+        );
+        assert_eq!(timeline.events.iter().filter(|e| e.domain == Domain::Qnx).count(), 2);
+        assert_eq!(timeline.events[0].text, "boot");
+
+        timeline.write_jsonl(&dir.join(TIMELINE_JSONL_FILE)).unwrap();
+        let jsonl = fs::read_to_string(dir.join(TIMELINE_JSONL_FILE)).unwrap();
+        let can: serde_json::Value = serde_json::from_str(jsonl.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(can["domain"], "can");
+        assert_eq!(can["source"], "CAN_LOGS/FD_CAN5.asc");
+        assert!(can["time"].as_str().unwrap().contains("10:00:01.000000"), "{}", can);
+        assert!(timeline.events[1].text_line().contains("  CAN      CAN_LOGS/FD_CAN5.asc 1 123 Rx d 1 01"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,6 +1,6 @@
-// CAN/ETH capture via vxlapi.dll (Vector hardware)
-// This is a standalone component, not interfering with the main logger.
-// Only loaded/used if enabled in the main app.
+// CAN capture backend for the Vector XL Driver Library (vxlapi.dll).
+// Only compiled with the `vxl-can` feature; the capture logic itself lives in can_capture
+// and reaches the hardware through the CanBackend implementation below.
 
 // --- Begin inlined vxlapi.dll FFI bindings ---
 use std::os::raw::{c_char, c_short, c_uint, c_ulonglong, c_void};
@@ -12,15 +12,29 @@ pub type XLportHandle = c_uint;
 #[allow(non_camel_case_types)]
 pub type XLaccess = c_ulonglong;
 #[allow(non_camel_case_types)]
-pub type XLdriverConfig = c_void;
+pub type XLhandle = *mut c_void;
 
 const XL_SUCCESS: XLstatus = 0;
 const XL_ERR_QUEUE_IS_EMPTY: XLstatus = 10;
+const XL_BUS_TYPE_CAN: u32 = 1;
+const XL_BUS_COMPATIBLE_CAN: u32 = 0x0000_0001;
+const XL_CHANNEL_FLAG_CANFD_BOSCH_SUPPORT: u32 = 0x2000_0000;
+const XL_CHANNEL_FLAG_CANFD_ISO_SUPPORT: u32 = 0x8000_0000;
+const XL_CONFIG_MAX_CHANNELS: usize = 64;
+const XL_MAX_LENGTH: usize = 31;
 const XL_CAN_EV_TAG_RX_OK: u16 = 0x0400;
+const XL_CAN_EV_TAG_RX_ERROR: u16 = 0x0401;
 const XL_CAN_EV_TAG_TX_OK: u16 = 0x0404;
+const XL_OUTPUT_MODE_SILENT: i32 = 0;
 const XL_OUTPUT_MODE_NORMAL: i32 = 1;
+const XL_CAN_EXT_MSG_ID: u32 = 0x8000_0000;
+const XL_CAN_RXMSG_FLAG_EDL: u32 = 0x0001;
+const XL_CAN_RXMSG_FLAG_BRS: u32 = 0x0002;
+const XL_CAN_RXMSG_FLAG_ESI: u32 = 0x0004;
+const XL_CAN_RXMSG_FLAG_RTR: u32 = 0x0010;
 
 #[repr(C)]
+#[allow(non_snake_case)]
 pub struct XLcanFdConf {
     pub arbitrationBitRate: u32,
     pub sjwAbr: u32,
@@ -38,6 +52,7 @@ pub struct XLcanFdConf {
 
 #[repr(C)]
 #[derive(Copy, Clone)]
+#[allow(non_snake_case)]
 pub struct XLCanRxMsg {
     pub canId: u32,
     pub msgFlags: u32,
@@ -51,6 +66,7 @@ pub struct XLCanRxMsg {
 
 #[repr(C)]
 #[derive(Copy, Clone)]
+#[allow(non_snake_case)]
 pub union XLcanRxTagData {
     pub raw: [u8; 96],
     pub canRxOkMsg: XLCanRxMsg,
@@ -58,6 +74,7 @@ pub union XLcanRxTagData {
 }
 
 #[repr(C)]
+#[allow(non_snake_case)]
 pub struct XLcanRxEvent {
     pub size: u32,
     pub tag: u16,
@@ -70,8 +87,68 @@ pub struct XLcanRxEvent {
     pub tagData: XLcanRxTagData,
 }
 
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+#[allow(non_snake_case)]
+pub struct XLbusParams {
+    pub busType: u32,
+    pub data: [u8; 28],
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+#[allow(non_snake_case, dead_code)]
+pub struct XLchannelConfig {
+    pub name: [c_char; XL_MAX_LENGTH + 1],
+    pub hwType: u8,
+    pub hwIndex: u8,
+    pub hwChannel: u8,
+    pub transceiverType: u16,
+    pub transceiverState: u16,
+    pub configError: u16,
+    pub channelIndex: u8,
+    pub channelMask: XLaccess,
+    pub channelCapabilities: u32,
+    pub channelBusCapabilities: u32,
+    pub isOnBus: u8,
+    pub connectedBusType: u32,
+    pub busParams: XLbusParams,
+    pub _doNotUse: u32,
+    pub driverVersion: u32,
+    pub interfaceVersion: u32,
+    pub raw_data: [u32; 10],
+    pub serialNumber: u32,
+    pub articleNumber: u32,
+    pub transceiverName: [c_char; XL_MAX_LENGTH + 1],
+    pub specialCabFlags: u32,
+    pub dominantTimeout: u32,
+    pub dominantRecessiveDelay: u8,
+    pub recessiveDominantDelay: u8,
+    pub connectionInfo: u8,
+    pub currentlyAvailableTimestamps: u8,
+    pub minimalSupplyVoltage: u16,
+    pub maximalSupplyVoltage: u16,
+    pub maximalBaudrate: u32,
+    pub fpgaCoreCapabilities: u8,
+    pub specialDeviceStatus: u8,
+    pub channelBusActiveCapabilities: u16,
+    pub breakOffset: u16,
+    pub delimiterOffset: u16,
+    pub reserved: [u32; 3],
+}
+
+#[repr(C)]
+#[allow(non_snake_case, dead_code)]
+pub struct XLdriverConfig {
+    pub dllVersion: u32,
+    pub channelCount: u32,
+    pub reserved: [u32; 10],
+    pub channel: [XLchannelConfig; XL_CONFIG_MAX_CHANNELS],
+}
+
 #[cfg_attr(all(target_os = "windows", target_arch = "x86_64"), link(name = "vxlapi64"))]
 #[cfg_attr(not(all(target_os = "windows", target_arch = "x86_64")), link(name = "vxlapi"))]
+#[allow(dead_code)]
 unsafe extern "C" {
     pub fn xlOpenDriver() -> XLstatus;
     pub fn xlCloseDriver() -> XLstatus;
@@ -86,8 +163,9 @@ unsafe extern "C" {
     ) -> XLstatus;
     pub fn xlGetChannelMask(hwType: i32, hwIndex: i32, hwChannel: i32) -> XLaccess;
     pub fn xlOpenPort(portHandle: *mut XLportHandle, appName: *const c_char, accessMask: XLaccess, permissionMask: *mut XLaccess, rxQueueSize: u32, xlInterfaceVersion: u32, busType: u32) -> XLstatus;
-    pub fn xlSetNotification(portHandle: XLportHandle, hwnd: *mut c_void, msg: u32) -> XLstatus;
+    pub fn xlSetNotification(portHandle: XLportHandle, pHandle: *mut XLhandle, queueLevel: i32) -> XLstatus;
     pub fn xlActivateChannel(portHandle: XLportHandle, accessMask: XLaccess, busType: u32, flags: u32) -> XLstatus;
+    pub fn xlGetSyncTime(portHandle: XLportHandle, pTime: *mut u64) -> XLstatus;
     pub fn xlCanReceive(portHandle: XLportHandle, pXlCanRxEvt: *mut XLcanRxEvent) -> XLstatus;
     pub fn xlCanGetEventString(pEv: *mut XLcanRxEvent) -> *const c_char;
     pub fn xlGetErrorString(err: XLstatus) -> *const c_char;
@@ -97,451 +175,68 @@ unsafe extern "C" {
     pub fn xlClosePort(portHandle: XLportHandle) -> XLstatus;
 }
 // --- End inlined vxlapi.dll FFI bindings ---
-use std::ffi::{CStr, CString};
-use std::fs;
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::path::PathBuf;
-use std::thread;
-use std::time::{Duration, Instant};
-
-#[derive(Copy, Clone)]
-pub enum CanLogFormat {
-    Text,
-    Asc,
-}
-
-pub fn try_open_driver() -> Result<(), String> {
-    let status = unsafe { xlOpenDriver() };
-    if status == XL_SUCCESS {
-        Ok(())
-    } else {
-        Err(format!("xlOpenDriver failed: status {} ({})", status, xl_error_string(status)))
-    }
-}
 
-pub fn try_close_driver() {
-    unsafe { xlCloseDriver() };
+#[cfg(target_os = "windows")]
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    fn WaitForSingleObject(hHandle: *mut c_void, dwMilliseconds: u32) -> u32;
 }
-
-// Add more wrappers for channel/network selection, CAN/ETH capture, etc.
-
-// Example usage (to be called from main app):
-// vxl_capture::try_open_driver();
-// ...
-// vxl_capture::try_close_driver();
-pub fn try_capture_can() -> Result<(), String> {
-    match try_capture_can_on_channel(0, 1000, "RustApp", 4)? {
-        true => Ok(()),
-        false => Err("No CAN event observed on channel 0 within timeout".to_string()),
-    }
+#[cfg(target_os = "windows")]
+const WAIT_OBJECT_0: u32 = 0;
+
+use crate::can_backend::{
+    CanAccess, CanBackend, CanBusMode, CanChannelInfo, CanEvent, CanFrame, CanHwChannel, CanPort, CanStatusError,
+};
+use crate::can_capture::progress;
+use crate::can_config::CanBusParams;
+use std::ffi::{CStr, CString};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Vector XL driver backend. Opening it opens the driver; dropping it closes the driver.
+pub struct VectorXlBackend {
+    /// Notification event handle (as usize) per open port handle, from `xlSetNotification`.
+    notifications: Mutex<HashMap<u64, usize>>,
 }
 
-pub fn try_capture_can_on_channel(
-    app_channel: u32,
-    timeout_ms: u64,
-    app_name: &str,
-    interface_version: u32,
-) -> Result<bool, String> {
-    const XL_BUS_TYPE_CAN: u32 = 1;
-
-    if app_channel >= 64 {
-        return Err(format!("Invalid app channel {} (must be < 64)", app_channel));
-    }
-
-    let app_name = CString::new(app_name)
-        .map_err(|_| "Invalid app name (contains NUL byte)".to_string())?;
-
-    let mut hw_type = 0u32;
-    let mut hw_index = 0u32;
-    let mut hw_channel = 0u32;
-    let app_cfg_status = unsafe {
-        xlGetApplConfig(
-            app_name.as_ptr() as *mut c_char,
-            app_channel,
-            &mut hw_type as *mut _,
-            &mut hw_index as *mut _,
-            &mut hw_channel as *mut _,
-            XL_BUS_TYPE_CAN,
-        )
-    };
-    let access_mask = if app_cfg_status == XL_SUCCESS {
-        let mapped_mask = unsafe { xlGetChannelMask(hw_type as i32, hw_index as i32, hw_channel as i32) };
-        if mapped_mask == 0 {
-            return Err(format!(
-                "No channel mask for app '{}' channel {} (hwType={}, hwIndex={}, hwChannel={})",
-                app_name.to_string_lossy(),
-                app_channel,
-                hw_type,
-                hw_index,
-                hw_channel
-            ));
-        }
-        mapped_mask
-    } else {
-        let all_mask = unsafe { xlGetChannelMask(-1, -1, -1) };
-        if all_mask == 0 {
-            return Err(format!(
-                "xlGetApplConfig failed for app '{}' channel {}: status {} ({}), and no wildcard channel mask available",
-                app_name.to_string_lossy(),
-                app_channel,
-                app_cfg_status,
-                xl_error_string(app_cfg_status)
-            ));
-        }
-
-        match nth_set_bit_mask(all_mask, app_channel as usize) {
-            Some(mask) => {
-                println!(
-                    "Using fallback hardware channel ordinal {} (display ch {}, app mapping missing for '{}')",
-                    app_channel,
-                    app_channel + 1,
-                    app_name.to_string_lossy()
-                );
-                mask
-            }
-            None => {
-                return Err(format!(
-                    "App mapping missing for '{}' and fallback ordinal {} exceeds available channels",
-                    app_name.to_string_lossy(),
-                    app_channel
-                ));
-            }
-        }
-    };
-
-    let mut port_handle: XLportHandle = 0;
-    let mut permission_mask: XLaccess = access_mask;
-    let rx_queue_size = 16384u32;
-    let bus_type = XL_BUS_TYPE_CAN;
-
-    // Open port
-    let status = unsafe {
-        xlOpenPort(
-            &mut port_handle as *mut _,
-            app_name.as_ptr(),
-            access_mask,
-            &mut permission_mask as *mut _,
-            rx_queue_size,
-            interface_version,
-            bus_type,
-        )
-    };
-    if status != XL_SUCCESS {
-        return Err(format!("xlOpenPort failed: status {} ({})", status, xl_error_string(status)));
-    }
-
-    if let Err(e) = apply_canfd_defaults(port_handle, access_mask) {
-        unsafe { xlClosePort(port_handle) };
-        return Err(e);
-    }
-
-    // Activate channel
-    let status = unsafe { xlActivateChannel(port_handle, access_mask, bus_type, 0) };
-    if status != XL_SUCCESS {
-        unsafe { xlClosePort(port_handle) };
-        return Err(format!("xlActivateChannel failed: status {} ({})", status, xl_error_string(status)));
-    }
-
-    let timeout = Duration::from_millis(timeout_ms);
-    let start = Instant::now();
-    let mut received = false;
-
-    while start.elapsed() < timeout {
-        let mut event = XLcanRxEvent {
-            size: 0,
-            tag: 0,
-            channelIndex: 0,
-            userHandle: 0,
-            flagsChip: 0,
-            reserved0: 0,
-            reserved1: 0,
-            timeStampSync: 0,
-            tagData: XLcanRxTagData { raw: [0; 96] },
-        };
-
-        let status = unsafe { xlCanReceive(port_handle, &mut event as *mut _) };
-
+impl VectorXlBackend {
+    pub fn open() -> Result<Self, String> {
+        let status = unsafe { xlOpenDriver() };
         if status == XL_SUCCESS {
-            if event.tag == XL_CAN_EV_TAG_RX_OK || event.tag == XL_CAN_EV_TAG_TX_OK {
-                let msg = unsafe { event.tagData.canRxOkMsg };
-                let data_len = can_dlc_to_len(msg.dlc).min(msg.data.len());
-                println!(
-                    "Received CAN channel={} (appCh={}) id=0x{:X} dlc={} data={:02X?}",
-                    event.channelIndex as u32 + 1,
-                    app_channel + 1,
-                    msg.canId,
-                    msg.dlc,
-                    &msg.data[..data_len]
-                );
-                received = true;
-                break;
-            }
-
-            let event_text = unsafe {
-                let ptr = xlCanGetEventString(&mut event as *mut _);
-                if ptr.is_null() {
-                    String::from("unknown event")
-                } else {
-                    CStr::from_ptr(ptr).to_string_lossy().into_owned()
-                }
-            };
-            println!(
-                "CAN event tag=0x{:X} channel={} ({})",
-                event.tag,
-                event.channelIndex as u32 + 1,
-                event_text
-            );
-            continue;
-        }
-
-        if status == XL_ERR_QUEUE_IS_EMPTY {
-            thread::sleep(Duration::from_millis(10));
-            continue;
-        }
-
-        unsafe {
-            xlDeactivateChannel(port_handle, access_mask);
-            xlClosePort(port_handle);
-        }
-        return Err(format!(
-            "xlCanReceive failed: status {} ({})",
-            status,
-            xl_error_string(status)
-        ));
-    }
-
-    unsafe {
-        xlDeactivateChannel(port_handle, access_mask);
-        xlClosePort(port_handle);
-    }
-
-    Ok(received)
-}
-
-fn can_dlc_to_len(dlc: u8) -> usize {
-    match dlc {
-        0..=8 => dlc as usize,
-        9 => 12,
-        10 => 16,
-        11 => 20,
-        12 => 24,
-        13 => 32,
-        14 => 48,
-        _ => 64,
-    }
-}
-
-fn xl_error_string(status: XLstatus) -> String {
-    unsafe {
-        let ptr = xlGetErrorString(status);
-        if ptr.is_null() {
-            return "unknown error".to_string();
+            Ok(Self {
+                notifications: Mutex::new(HashMap::new()),
+            })
+        } else {
+            Err(format!("xlOpenDriver failed: status {} ({})", status, xl_error_string(status)))
         }
-        CStr::from_ptr(ptr).to_string_lossy().into_owned()
     }
 }
 
-fn apply_canfd_defaults(port_handle: XLportHandle, access_mask: XLaccess) -> Result<(), String> {
-    let output_status = unsafe { xlCanSetChannelOutput(port_handle, access_mask, XL_OUTPUT_MODE_NORMAL) };
-    if output_status != XL_SUCCESS {
-        return Err(format!(
-            "xlCanSetChannelOutput failed: status {} ({})",
-            output_status,
-            xl_error_string(output_status)
-        ));
+impl Drop for VectorXlBackend {
+    fn drop(&mut self) {
+        unsafe { xlCloseDriver() };
     }
-
-    let mut fd_conf = XLcanFdConf {
-        arbitrationBitRate: 500_000,
-        sjwAbr: 16,
-        tseg1Abr: 63,
-        tseg2Abr: 16,
-        dataBitRate: 2_000_000,
-        sjwDbr: 10,
-        tseg1Dbr: 29,
-        tseg2Dbr: 10,
-        reserved: 0,
-        options: 0,
-        reserved1: [0; 2],
-        reserved2: 0,
-    };
-
-    let fd_status = unsafe { xlCanFdSetConfiguration(port_handle, access_mask, &mut fd_conf as *mut _) };
-    if fd_status != XL_SUCCESS {
-        return Err(format!(
-            "xlCanFdSetConfiguration failed: status {} ({})",
-            fd_status,
-            xl_error_string(fd_status)
-        ));
-    }
-
-    Ok(())
-}
-
-fn nth_set_bit_mask(mask: XLaccess, ordinal: usize) -> Option<XLaccess> {
-    let mut found = 0usize;
-    for bit in 0..64 {
-        let bit_mask = 1u64 << bit;
-        if (mask & bit_mask) != 0 {
-            if found == ordinal {
-                return Some(bit_mask);
-            }
-            found += 1;
-        }
-    }
-    None
 }
 
-pub fn try_capture_any_can(
-    max_channels: u32,
-    timeout_per_channel_ms: u64,
-    app_name: &str,
-    interface_version: u32,
-) -> Result<u32, String> {
-    let mut denied_111_count = 0u32;
-    let mut open_port_error_count = 0u32;
-
-    for channel in 0..max_channels {
-        println!("Trying CAN channel {}...", channel + 1);
-        match try_capture_can_on_channel(channel, timeout_per_channel_ms, app_name, interface_version) {
-            Ok(true) => return Ok(channel),
-            Ok(false) => {}
-            Err(e) => {
-                if e.contains("status 111") {
-                    denied_111_count += 1;
-                }
-                if e.contains("xlOpenPort failed") {
-                    open_port_error_count += 1;
-                }
-                println!("Channel {} not usable: {}", channel + 1, e);
-            }
-        }
+impl CanBackend for VectorXlBackend {
+    fn name(&self) -> &'static str {
+        "vector"
     }
 
-    if denied_111_count == max_channels && max_channels > 0 {
-        return Err(format!(
-            "All {} channels denied by XL Driver (xlOpenPort status 111). Configure XL channel access for app '{}' and retry.",
-            max_channels,
-            app_name
-        ));
-    }
-
-    if open_port_error_count == max_channels && max_channels > 0 {
-        return Err(format!(
-            "Unable to open any channel ({} open-port failures). Verify app name '{}', interface version {}, and channel mapping.",
-            open_port_error_count,
-            app_name,
-            interface_version
-        ));
-    }
+    fn app_channel_config(&self, app_name: &str, app_channel: u32) -> Result<CanHwChannel, CanStatusError> {
+        let app_name = CString::new(app_name).map_err(|_| CanStatusError {
+            call: "xlGetApplConfig",
+            status: -1,
+            description: "app name contains NUL byte".to_string(),
+        })?;
 
-    Err(format!(
-        "No CAN traffic detected on channels 0..{}",
-        max_channels.saturating_sub(1)
-    ))
-}
-
-pub fn diagnose_can_setup(max_channels: u32, app_name: &str, interface_version: u32) {
-    println!(
-        "CAN Diagnose: app='{}', iface={}, scan-channels=0..{}",
-        app_name,
-        interface_version,
-        max_channels.saturating_sub(1)
-    );
-
-    let mut channels_with_frames = 0u32;
-    let mut channels_open_no_frames = 0u32;
-    let mut mapping_errors = 0u32;
-    let mut invalid_channel_errors = 0u32;
-    let mut open_port_errors = 0u32;
-
-    for channel in 0..max_channels {
-        match try_capture_can_on_channel(channel, 250, app_name, interface_version) {
-            Ok(true) => {
-                channels_with_frames += 1;
-                println!("DIAG channel {}: OK (frames detected)", channel + 1);
-            }
-            Ok(false) => {
-                channels_open_no_frames += 1;
-                println!("DIAG channel {}: OK (no frames during 250ms)", channel + 1);
-            }
-            Err(e) => {
-                if e.contains("xlGetApplConfig failed") {
-                    mapping_errors += 1;
-                }
-                if e.contains("XL_ERR_INVALID_CHAN_INDEX") {
-                    invalid_channel_errors += 1;
-                }
-                if e.contains("xlOpenPort failed") {
-                    open_port_errors += 1;
-                }
-                println!("DIAG channel {}: {}", channel + 1, e);
-            }
-        }
-    }
-
-    println!(
-        "DIAG summary: frames={}, open_no_frames={}, mapping_errors={}, invalid_channel_errors={}, open_port_errors={}",
-        channels_with_frames,
-        channels_open_no_frames,
-        mapping_errors,
-        invalid_channel_errors,
-        open_port_errors
-    );
-
-    if channels_with_frames > 0 {
-        println!("DIAG result: CAN capture path is working.");
-        return;
-    }
-
-    if channels_open_no_frames > 0 {
-        println!("DIAG result: channels open successfully, but no traffic was observed in test window.");
-        return;
-    }
-
-    if mapping_errors == max_channels {
-        println!(
-            "DIAG hint: application mapping for '{}' is missing. Configure at least one app channel in Vector XL Driver Configuration.",
-            app_name
-        );
-    }
-
-    if invalid_channel_errors == max_channels {
-        println!(
-            "DIAG hint: channel ordinals are not valid for current setup. Re-check app-channel mapping indices.");
-    }
-
-    if open_port_errors == max_channels {
-        println!(
-            "DIAG hint: all channel opens failed. Verify XL permissions, app name, and interface version.");
-    }
-}
-
-pub fn print_can_channel_mapping(max_channels: u32, app_name: &str) {
-    const XL_BUS_TYPE_CAN: u32 = 1;
-    let app_name_c = match CString::new(app_name) {
-        Ok(s) => s,
-        Err(_) => {
-            println!("Invalid app name '{}': contains NUL byte", app_name);
-            return;
-        }
-    };
-
-    println!(
-        "CAN mapping for app '{}': channel(1-based) -> hwType/hwIndex/hwChannel (mask) -> VN -> Network",
-        app_name
-    );
-
-    let mut found = 0u32;
-    for app_channel in 0..max_channels {
         let mut hw_type = 0u32;
         let mut hw_index = 0u32;
         let mut hw_channel = 0u32;
-
         let status = unsafe {
             xlGetApplConfig(
-                app_name_c.as_ptr() as *mut c_char,
+                app_name.as_ptr() as *mut c_char,
                 app_channel,
                 &mut hw_type as *mut _,
                 &mut hw_index as *mut _,
@@ -549,202 +244,133 @@ pub fn print_can_channel_mapping(max_channels: u32, app_name: &str) {
                 XL_BUS_TYPE_CAN,
             )
         };
-
-        if status == XL_SUCCESS {
-            let mask = unsafe { xlGetChannelMask(hw_type as i32, hw_index as i32, hw_channel as i32) };
-            let display_channel = app_channel + 1;
-            let (vn_label, network_name) = can_network_alias(app_channel)
-                .unwrap_or(("UNMAPPED", "UNMAPPED"));
-            println!(
-                "ch={} (appCh={}) -> hwType={} hwIndex={} hwChannel={} mask=0x{:X} -> {} -> {}",
-                display_channel,
-                app_channel,
-                hw_type,
-                hw_index,
-                hw_channel,
-                mask,
-                vn_label,
-                network_name
-            );
-            found += 1;
+        if status != XL_SUCCESS {
+            return Err(xl_status_error("xlGetApplConfig", status));
         }
-    }
-
-    if found == 0 {
-        println!("No CAN app-channel mappings found for app '{}'.", app_name);
-    }
-}
 
-fn can_network_alias(app_channel: u32) -> Option<(&'static str, &'static str)> {
-    match app_channel {
-        0 => Some(("vn 1670 1", "FD_CANW")),
-        1 => Some(("vn 1670 1", "FD_CAN5")),
-        2 => Some(("vn 1670 2", "FD_CAN9")),
-        3 => Some(("vn 1670 2", "FD_CAN13")),
-        4 => Some(("vn 1670 2", "FD_CAN14")),
-        5 => Some(("vn 1670 1", "FD_CAN15")),
-        6 => Some(("vn 1670 1", "FD_CAN17")),
-        7 => Some(("vn 1670 1", "FD_CAN18")),
-        8 => Some(("vn 1670 1", "FD_CAN20")),
-        9 => Some(("vn 1670 1", "FD_CAN21")),
-        10 => Some(("vn 1670 1", "HS_CAN1")),
-        _ => None,
+        Ok(CanHwChannel {
+            hw_type,
+            hw_index,
+            hw_channel,
+        })
     }
-}
 
-fn can_output_file_stem(app_channel: u32) -> String {
-    if let Some((_, network_name)) = can_network_alias(app_channel) {
-        network_name.to_string()
-    } else {
-        format!("channel{}", app_channel + 1)
+    fn channel_mask(&self, hw: &CanHwChannel) -> CanAccess {
+        unsafe { xlGetChannelMask(hw.hw_type as i32, hw.hw_index as i32, hw.hw_channel as i32) }
     }
-}
 
-pub fn listen_can_on_channel(
-    app_channel: u32,
-    app_name: &str,
-    interface_version: u32,
-    duration_ms: Option<u64>,
-    log_file_path: Option<&str>,
-    log_format: CanLogFormat,
-) -> Result<(), String> {
-    const XL_BUS_TYPE_CAN: u32 = 1;
-
-    if app_channel >= 64 {
-        return Err(format!("Invalid app channel {} (must be < 64)", app_channel));
+    fn all_channels_mask(&self) -> CanAccess {
+        unsafe { xlGetChannelMask(-1, -1, -1) }
     }
 
-    let app_name = CString::new(app_name)
-        .map_err(|_| "Invalid app name (contains NUL byte)".to_string())?;
-
-    let mut hw_type = 0u32;
-    let mut hw_index = 0u32;
-    let mut hw_channel = 0u32;
-    let app_cfg_status = unsafe {
-        xlGetApplConfig(
-            app_name.as_ptr() as *mut c_char,
-            app_channel,
-            &mut hw_type as *mut _,
-            &mut hw_index as *mut _,
-            &mut hw_channel as *mut _,
-            XL_BUS_TYPE_CAN,
-        )
-    };
-
-    let access_mask = if app_cfg_status == XL_SUCCESS {
-        let mapped_mask = unsafe { xlGetChannelMask(hw_type as i32, hw_index as i32, hw_channel as i32) };
-        if mapped_mask == 0 {
-            return Err(format!(
-                "No channel mask for app '{}' channel {} (hwType={}, hwIndex={}, hwChannel={})",
-                app_name.to_string_lossy(),
-                app_channel,
-                hw_type,
-                hw_index,
-                hw_channel
-            ));
-        }
-        mapped_mask
-    } else {
-        let all_mask = unsafe { xlGetChannelMask(-1, -1, -1) };
-        if all_mask == 0 {
-            return Err(format!(
-                "xlGetApplConfig failed for app '{}' channel {}: status {} ({}), and no wildcard channel mask available",
-                app_name.to_string_lossy(),
-                app_channel,
-                app_cfg_status,
-                xl_error_string(app_cfg_status)
-            ));
+    fn channel_inventory(&self) -> Result<Vec<CanChannelInfo>, CanStatusError> {
+        let mut config: Box<XLdriverConfig> = Box::new(unsafe { std::mem::zeroed() });
+        let status = unsafe { xlGetDriverConfig(&mut *config as *mut XLdriverConfig) };
+        if status != XL_SUCCESS {
+            return Err(xl_status_error("xlGetDriverConfig", status));
         }
 
-        match nth_set_bit_mask(all_mask, app_channel as usize) {
-            Some(mask) => {
-                println!(
-                    "Using fallback hardware channel ordinal {} (app mapping missing for '{}')",
-                    app_channel,
-                    app_name.to_string_lossy()
-                );
-                mask
-            }
-            None => {
-                return Err(format!(
-                    "App mapping missing for '{}' and fallback ordinal {} exceeds available channels",
-                    app_name.to_string_lossy(),
-                    app_channel
-                ));
-            }
+        let count = (config.channelCount as usize).min(XL_CONFIG_MAX_CHANNELS);
+        Ok(config.channel[..count]
+            .iter()
+            .filter(|channel| channel.channelBusCapabilities & XL_BUS_COMPATIBLE_CAN != 0)
+            .map(|channel| CanChannelInfo {
+                name: c_char_array_to_string(&channel.name),
+                hw: CanHwChannel {
+                    hw_type: channel.hwType as u32,
+                    hw_index: channel.hwIndex as u32,
+                    hw_channel: channel.hwChannel as u32,
+                },
+                serial_number: channel.serialNumber,
+                article_number: channel.articleNumber,
+                transceiver: c_char_array_to_string(&channel.transceiverName),
+                fd_capable: channel.channelCapabilities
+                    & (XL_CHANNEL_FLAG_CANFD_ISO_SUPPORT | XL_CHANNEL_FLAG_CANFD_BOSCH_SUPPORT)
+                    != 0,
+                max_bitrate: channel.maximalBaudrate,
+                driver_version: format_xl_version(channel.driverVersion),
+                on_bus: channel.isOnBus != 0,
+            })
+            .collect())
+    }
+
+    fn open_port(
+        &self,
+        app_name: &str,
+        access_mask: CanAccess,
+        rx_queue_size: u32,
+        interface_version: u32,
+    ) -> Result<CanPort, CanStatusError> {
+        let app_name = CString::new(app_name).map_err(|_| CanStatusError {
+            call: "xlOpenPort",
+            status: -1,
+            description: "app name contains NUL byte".to_string(),
+        })?;
+
+        let mut port_handle: XLportHandle = 0;
+        let mut permission_mask: XLaccess = access_mask;
+        let status = unsafe {
+            xlOpenPort(
+                &mut port_handle as *mut _,
+                app_name.as_ptr(),
+                access_mask,
+                &mut permission_mask as *mut _,
+                rx_queue_size,
+                interface_version,
+                XL_BUS_TYPE_CAN,
+            )
+        };
+        if status != XL_SUCCESS {
+            return Err(xl_status_error("xlOpenPort", status));
         }
-    };
 
-    let mut port_handle: XLportHandle = 0;
-    let mut permission_mask: XLaccess = access_mask;
-    let rx_queue_size = 16384u32;
+        // Signalled as soon as one event is queued; without it wait_for_event falls back to short sleeps.
+        let mut notification: XLhandle = std::ptr::null_mut();
+        let status = unsafe { xlSetNotification(port_handle, &mut notification as *mut _, 1) };
+        if status == XL_SUCCESS && !notification.is_null() {
+            self.notifications
+                .lock()
+                .unwrap()
+                .insert(port_handle as u64, notification as usize);
+        } else {
+            progress!("xlSetNotification failed: status {} ({})", status, xl_error_string(status));
+        }
 
-    let status = unsafe {
-        xlOpenPort(
-            &mut port_handle as *mut _,
-            app_name.as_ptr(),
+        Ok(CanPort {
+            handle: port_handle as u64,
             access_mask,
-            &mut permission_mask as *mut _,
-            rx_queue_size,
-            interface_version,
-            XL_BUS_TYPE_CAN,
-        )
-    };
-    if status != XL_SUCCESS {
-        return Err(format!("xlOpenPort failed: status {} ({})", status, xl_error_string(status)));
+            permission_mask,
+        })
     }
 
-    if let Err(e) = apply_canfd_defaults(port_handle, access_mask) {
-        unsafe { xlClosePort(port_handle) };
-        return Err(e);
+    fn configure_port(
+        &self,
+        port: &CanPort,
+        channel_mask: CanAccess,
+        mode: CanBusMode,
+        params: &CanBusParams,
+    ) -> Result<(), CanStatusError> {
+        apply_bus_configuration(port.handle as XLportHandle, channel_mask, mode, params)
     }
 
-    let status = unsafe { xlActivateChannel(port_handle, access_mask, XL_BUS_TYPE_CAN, 0) };
-    if status != XL_SUCCESS {
-        unsafe { xlClosePort(port_handle) };
-        return Err(format!("xlActivateChannel failed: status {} ({})", status, xl_error_string(status)));
-    }
-
-    let mut log_file = if let Some(path) = log_file_path {
-        Some(
-            OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(path)
-                .map_err(|e| format!("Failed to open log file '{}': {}", path, e))?,
-        )
-    } else {
-        None
-    };
-
-    if let Some(file) = &mut log_file {
-        match log_format {
-            CanLogFormat::Text => {}
-            CanLogFormat::Asc => {
-                let _ = writeln!(file, "date {}", chrono::Local::now().format("%a %b %d %H:%M:%S%.3f %Y"));
-                let _ = writeln!(file, "base hex  timestamps relative");
-                let _ = writeln!(file, "internal events logged");
-                let _ = writeln!(file, "Begin Triggerblock {}", chrono::Local::now().format("%a %b %d %H:%M:%S%.3f %Y"));
-            }
+    fn activate_port(&self, port: &CanPort) -> Result<(), CanStatusError> {
+        let status = unsafe { xlActivateChannel(port.handle as XLportHandle, port.access_mask, XL_BUS_TYPE_CAN, 0) };
+        if status != XL_SUCCESS {
+            return Err(xl_status_error("xlActivateChannel", status));
         }
+        Ok(())
     }
 
-    println!(
-        "Listening continuously on CAN app-channel {}. Press Ctrl+C to stop.",
-        app_channel + 1
-    );
-
-    let start = Instant::now();
-    let mut frame_count: u64 = 0;
-
-    loop {
-        if let Some(ms) = duration_ms {
-            if start.elapsed() >= Duration::from_millis(ms) {
-                break;
-            }
+    fn clock_ns(&self, port: &CanPort) -> Result<u64, CanStatusError> {
+        let mut time: u64 = 0;
+        let status = unsafe { xlGetSyncTime(port.handle as XLportHandle, &mut time as *mut _) };
+        if status != XL_SUCCESS {
+            return Err(xl_status_error("xlGetSyncTime", status));
         }
+        Ok(time)
+    }
 
+    fn receive(&self, port: &CanPort) -> Result<Option<CanEvent>, CanStatusError> {
         let mut event = XLcanRxEvent {
             size: 0,
             tag: 0,
@@ -757,163 +383,162 @@ pub fn listen_can_on_channel(
             tagData: XLcanRxTagData { raw: [0; 96] },
         };
 
-        let status = unsafe { xlCanReceive(port_handle, &mut event as *mut _) };
+        let status = unsafe { xlCanReceive(port.handle as XLportHandle, &mut event as *mut _) };
+        if status == XL_ERR_QUEUE_IS_EMPTY {
+            return Ok(None);
+        }
+        if status != XL_SUCCESS {
+            return Err(xl_status_error("xlCanReceive", status));
+        }
 
-        if status == XL_SUCCESS {
-            if event.tag == XL_CAN_EV_TAG_RX_OK || event.tag == XL_CAN_EV_TAG_TX_OK {
-                let msg = unsafe { event.tagData.canRxOkMsg };
-                let data_len = can_dlc_to_len(msg.dlc).min(msg.data.len());
-                frame_count += 1;
-                let line = format!(
-                    "frame={} channel={} id=0x{:X} dlc={} data={:02X?}",
-                    frame_count,
-                    event.channelIndex,
-                    msg.canId,
-                    msg.dlc,
-                    &msg.data[..data_len]
-                );
-                println!("{}", line);
-                if let Some(file) = &mut log_file {
-                    match log_format {
-                        CanLogFormat::Text => {
-                            let _ = writeln!(file, "{}", line);
-                        }
-                        CanLogFormat::Asc => {
-                            let ts = start.elapsed().as_secs_f64();
-                            let channel = event.channelIndex as u32 + 1;
-                            let id = msg.canId & 0x1FFF_FFFF;
-                            let mut bytes = String::new();
-                            for (i, b) in msg.data[..data_len].iter().enumerate() {
-                                if i > 0 {
-                                    bytes.push(' ');
-                                }
-                                bytes.push_str(&format!("{:02X}", b));
-                            }
-                            let _ = writeln!(
-                                file,
-                                "{:.6} {} {:X} Rx d {} {}",
-                                ts,
-                                channel,
-                                id,
-                                data_len,
-                                bytes
-                            );
-                        }
-                    }
-                }
+        if event.tag == XL_CAN_EV_TAG_RX_OK || event.tag == XL_CAN_EV_TAG_TX_OK {
+            let msg = unsafe { event.tagData.canRxOkMsg };
+            let data_len = can_dlc_to_len(msg.dlc).min(msg.data.len());
+            let remote = (msg.msgFlags & XL_CAN_RXMSG_FLAG_RTR) != 0;
+            return Ok(Some(CanEvent::Frame(CanFrame {
+                channel_index: event.channelIndex,
+                timestamp_ns: event.timeStampSync,
+                id: msg.canId & 0x1FFF_FFFF,
+                extended: (msg.canId & XL_CAN_EXT_MSG_ID) != 0,
+                tx: event.tag == XL_CAN_EV_TAG_TX_OK,
+                fd: (msg.msgFlags & XL_CAN_RXMSG_FLAG_EDL) != 0,
+                brs: (msg.msgFlags & XL_CAN_RXMSG_FLAG_BRS) != 0,
+                esi: (msg.msgFlags & XL_CAN_RXMSG_FLAG_ESI) != 0,
+                remote,
+                dlc: msg.dlc,
+                data: if remote { Vec::new() } else { msg.data[..data_len].to_vec() },
+            })));
+        }
+
+        let description = unsafe {
+            let ptr = xlCanGetEventString(&mut event as *mut _);
+            if ptr.is_null() {
+                String::from("unknown event")
+            } else {
+                CStr::from_ptr(ptr).to_string_lossy().into_owned()
             }
-            continue;
+        };
+        if event.tag == XL_CAN_EV_TAG_RX_ERROR {
+            return Ok(Some(CanEvent::ErrorFrame {
+                channel_index: event.channelIndex,
+                timestamp_ns: event.timeStampSync,
+                description,
+            }));
         }
+        Ok(Some(CanEvent::Other {
+            channel_index: event.channelIndex,
+            tag: event.tag,
+            description,
+        }))
+    }
 
-        if status == XL_ERR_QUEUE_IS_EMPTY {
-            thread::sleep(Duration::from_millis(5));
-            continue;
+    fn wait_for_event(&self, port: &CanPort, timeout: Duration) -> Result<bool, CanStatusError> {
+        let notification = self.notifications.lock().unwrap().get(&port.handle).copied();
+
+        #[cfg(target_os = "windows")]
+        if let Some(handle) = notification {
+            let timeout_ms = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+            let result = unsafe { WaitForSingleObject(handle as *mut c_void, timeout_ms) };
+            return Ok(result == WAIT_OBJECT_0);
         }
 
+        let _ = notification;
+        std::thread::sleep(timeout.min(Duration::from_millis(5)));
+        Ok(false)
+    }
+
+    fn close_port(&self, port: &CanPort) {
+        // The notification event belongs to the port and is released by xlClosePort.
+        self.notifications.lock().unwrap().remove(&port.handle);
         unsafe {
-            xlDeactivateChannel(port_handle, access_mask);
-            xlClosePort(port_handle);
+            xlDeactivateChannel(port.handle as XLportHandle, port.access_mask);
+            xlClosePort(port.handle as XLportHandle);
         }
-        return Err(format!(
-            "xlCanReceive failed: status {} ({})",
-            status,
-            xl_error_string(status)
-        ));
     }
+}
 
-    unsafe {
-        xlDeactivateChannel(port_handle, access_mask);
-        xlClosePort(port_handle);
+fn can_dlc_to_len(dlc: u8) -> usize {
+    match dlc {
+        0..=8 => dlc as usize,
+        9 => 12,
+        10 => 16,
+        11 => 20,
+        12 => 24,
+        13 => 32,
+        14 => 48,
+        _ => 64,
     }
+}
 
-    if let Some(file) = &mut log_file {
-        if let CanLogFormat::Asc = log_format {
-            let _ = writeln!(file, "End Triggerblock");
-        }
-    }
+/// `major.minor.build` as packed by the XL driver (8/8/16 bits).
+fn format_xl_version(version: u32) -> String {
+    format!("{}.{}.{}", version >> 24, (version >> 16) & 0xFF, version & 0xFFFF)
+}
 
-    println!("Stopped listening. Total frames captured: {}", frame_count);
-    Ok(())
+/// Fixed-size, NUL-terminated driver string.
+fn c_char_array_to_string(chars: &[c_char]) -> String {
+    let bytes: Vec<u8> = chars.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
 }
 
-pub fn listen_can_all_connected(
-    max_channels: u32,
-    app_name: &str,
-    interface_version: u32,
-    duration_ms: Option<u64>,
-    output_dir: Option<&str>,
-    log_format: CanLogFormat,
-) -> Result<(), String> {
-    let mut usable_channels: Vec<u32> = Vec::new();
-
-    for channel in 0..max_channels {
-        match try_capture_can_on_channel(channel, 100, app_name, interface_version) {
-            Ok(_) => {
-                usable_channels.push(channel);
-                println!("Detected usable channel {}", channel + 1);
-            }
-            Err(e) => {
-                println!("Skipping channel {}: {}", channel + 1, e);
-            }
+fn xl_error_string(status: XLstatus) -> String {
+    unsafe {
+        let ptr = xlGetErrorString(status);
+        if ptr.is_null() {
+            return "unknown error".to_string();
         }
+        CStr::from_ptr(ptr).to_string_lossy().into_owned()
     }
+}
 
-    if usable_channels.is_empty() {
-        return Err("No usable channels detected for capture".to_string());
+fn xl_status_error(call: &'static str, status: XLstatus) -> CanStatusError {
+    CanStatusError {
+        call,
+        status: status as i32,
+        description: xl_error_string(status),
     }
+}
 
-    let base_dir = output_dir.unwrap_or(".");
-    fs::create_dir_all(base_dir)
-        .map_err(|e| format!("Failed to create output directory '{}': {}", base_dir, e))?;
-
-    println!(
-        "Starting parallel capture on channels: {:?}",
-        usable_channels
-    );
-
-    let mut handles = Vec::new();
-    for channel in usable_channels {
-        let app_name_owned = app_name.to_string();
-        let file_stem = can_output_file_stem(channel);
-        let file_name = match log_format {
-            CanLogFormat::Asc => format!("{}.asc", file_stem),
-            CanLogFormat::Text => format!("{}.log", file_stem),
-        };
-        let log_path = PathBuf::from(base_dir).join(file_name);
-        let log_path_string = log_path.to_string_lossy().into_owned();
-
-        handles.push(thread::spawn(move || {
-            let result = listen_can_on_channel(
-                channel,
-                &app_name_owned,
-                interface_version,
-                duration_ms,
-                Some(&log_path_string),
-                log_format,
-            );
-            (channel, result)
-        }));
+/// Sets the output mode and the bit timing. The timing was validated when the network config was
+/// loaded. Ports use the FD interface, so classic networks go through xlCanFdSetConfiguration as
+/// well, with the data phase mirroring the nominal timing (it is never used on a classic bus).
+fn apply_bus_configuration(
+    port_handle: XLportHandle,
+    access_mask: XLaccess,
+    mode: CanBusMode,
+    params: &CanBusParams,
+) -> Result<(), CanStatusError> {
+    let output_mode = match mode {
+        CanBusMode::Active => XL_OUTPUT_MODE_NORMAL,
+        CanBusMode::Passive => XL_OUTPUT_MODE_SILENT,
+    };
+    let output_status = unsafe { xlCanSetChannelOutput(port_handle, access_mask, output_mode) };
+    if output_status != XL_SUCCESS {
+        return Err(xl_status_error("xlCanSetChannelOutput", output_status));
     }
 
-    let mut failures = 0u32;
-    for handle in handles {
-        match handle.join() {
-            Ok((channel, Ok(()))) => {
-                println!("Channel {} capture finished", channel + 1);
-            }
-            Ok((channel, Err(e))) => {
-                failures += 1;
-                println!("Channel {} capture failed: {}", channel + 1, e);
-            }
-            Err(_) => {
-                failures += 1;
-                println!("A channel capture thread panicked");
-            }
-        }
-    }
+    let (arbitration, data) = match params {
+        CanBusParams::Classic(timing) => (timing, timing),
+        CanBusParams::Fd { arbitration, data } => (arbitration, data),
+    };
+    let mut fd_conf = XLcanFdConf {
+        arbitrationBitRate: arbitration.bitrate,
+        sjwAbr: arbitration.sjw,
+        tseg1Abr: arbitration.tseg1,
+        tseg2Abr: arbitration.tseg2,
+        dataBitRate: data.bitrate,
+        sjwDbr: data.sjw,
+        tseg1Dbr: data.tseg1,
+        tseg2Dbr: data.tseg2,
+        reserved: 0,
+        options: 0,
+        reserved1: [0; 2],
+        reserved2: 0,
+    };
 
-    if failures > 0 {
-        return Err(format!("{} channel capture(s) failed", failures));
+    let fd_status = unsafe { xlCanFdSetConfiguration(port_handle, access_mask, &mut fd_conf as *mut _) };
+    if fd_status != XL_SUCCESS {
+        return Err(xl_status_error("xlCanFdSetConfiguration", fd_status));
     }
 
     Ok(())